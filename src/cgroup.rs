@@ -0,0 +1,522 @@
+//! cgroup v2 resource control
+//!
+//! Each jail gets its own cgroup below the subtree systemd delegates to the
+//! user (`user@<uid>.service`), so memory, CPU, process and IO limits can be
+//! applied without root. All sessions of a jail share the same cgroup.
+//! Sessions started outside of the user's service manager move into a scope
+//! of it first.
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Directory below the delegated subtree that holds all jail cgroups
+const ROBOJAIL_CGROUP: &str = "robojail";
+
+/// Controllers we try to enable for jail cgroups
+const CONTROLLERS: [&str; 4] = ["memory", "cpu", "pids", "io"];
+
+/// cpu.max period in microseconds
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// How long the user's service manager gets to move us into a new scope
+const SCOPE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Resource limits for a jail
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResourceLimits {
+    /// memory.max: bytes with optional K/M/G/T suffix, or "max"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_max: Option<String>,
+
+    /// memory.swap.max: bytes with optional K/M/G/T suffix, or "max"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_swap_max: Option<String>,
+
+    /// CPU time as a number of CPUs (written to cpu.max)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<f64>,
+
+    /// pids.max: maximum number of processes and threads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_max: Option<u64>,
+
+    /// io.max lines, e.g. "259:0 rbps=104857600 wiops=1000"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub io_max: Vec<String>,
+}
+
+impl ResourceLimits {
+    /// Whether no limit is set
+    pub fn is_empty(&self) -> bool {
+        self == &ResourceLimits::default()
+    }
+
+    /// Fill in unset limits from `defaults`
+    pub fn or(self, defaults: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            memory_max: self.memory_max.or_else(|| defaults.memory_max.clone()),
+            memory_swap_max: self.memory_swap_max.or_else(|| defaults.memory_swap_max.clone()),
+            cpus: self.cpus.or(defaults.cpus),
+            pids_max: self.pids_max.or(defaults.pids_max),
+            io_max: if self.io_max.is_empty() {
+                defaults.io_max.clone()
+            } else {
+                self.io_max
+            },
+        }
+    }
+
+    /// Check that all limits are well-formed
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [("memory", &self.memory_max), ("memory-swap", &self.memory_swap_max)] {
+            if let Some(value) = value {
+                if value != "max" && parse_size(value).is_none() {
+                    return Err(Error::Config(format!("invalid {name} limit '{value}'")));
+                }
+            }
+        }
+
+        if let Some(cpus) = self.cpus {
+            if !(cpus > 0.0 && cpus.is_finite()) {
+                return Err(Error::Config(format!("invalid cpus limit '{cpus}'")));
+            }
+        }
+
+        for line in &self.io_max {
+            let valid = line
+                .split_once(' ')
+                .is_some_and(|(dev, _)| dev.split_once(':').is_some_and(|(maj, min)| {
+                    maj.parse::<u32>().is_ok() && min.parse::<u32>().is_ok()
+                }));
+            if !valid {
+                return Err(Error::Config(format!(
+                    "invalid io-max '{line}' (expected 'MAJ:MIN key=value ...')"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Render the limits as (cgroup file, value) pairs
+    fn cgroup_files(&self) -> Vec<(&'static str, String)> {
+        let mut files = Vec::new();
+
+        if let Some(ref memory) = self.memory_max {
+            files.push(("memory.max", memory.clone()));
+        }
+        if let Some(ref swap) = self.memory_swap_max {
+            files.push(("memory.swap.max", swap.clone()));
+        }
+        if let Some(cpus) = self.cpus {
+            let quota = (cpus * CPU_PERIOD_USEC as f64).round() as u64;
+            files.push(("cpu.max", format!("{} {}", quota.max(1000), CPU_PERIOD_USEC)));
+        }
+        if let Some(pids) = self.pids_max {
+            files.push(("pids.max", pids.to_string()));
+        }
+        for line in &self.io_max {
+            files.push(("io.max", line.clone()));
+        }
+
+        files
+    }
+}
+
+impl std::fmt::Display for ResourceLimits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+
+        if let Some(ref memory) = self.memory_max {
+            parts.push(format!("memory {memory}"));
+        }
+        if let Some(ref swap) = self.memory_swap_max {
+            parts.push(format!("swap {swap}"));
+        }
+        if let Some(cpus) = self.cpus {
+            parts.push(format!("cpus {cpus}"));
+        }
+        if let Some(pids) = self.pids_max {
+            parts.push(format!("pids {pids}"));
+        }
+        for line in &self.io_max {
+            parts.push(format!("io {line}"));
+        }
+
+        if parts.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// Live resource usage of a jail's cgroup
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResourceUsage {
+    /// Current memory usage in bytes (memory.current)
+    pub memory_bytes: Option<u64>,
+    /// Peak memory usage in bytes (memory.peak, kernel 5.19+)
+    pub memory_peak_bytes: Option<u64>,
+    /// CPU time consumed in microseconds
+    pub cpu_usec: Option<u64>,
+    /// Number of processes and threads
+    pub pids: Option<u64>,
+    /// Bytes read from block devices
+    pub io_read_bytes: Option<u64>,
+    /// Bytes written to block devices
+    pub io_write_bytes: Option<u64>,
+}
+
+/// Format a byte count for humans, e.g. "1.5G"
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+
+    if bytes < 1024 {
+        return format!("{bytes}B");
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for u in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = u;
+    }
+    format!("{value:.1}{unit}")
+}
+
+/// Parse a size like "512M" or "4G" into bytes
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1u64 << 10),
+        'M' => (&value[..value.len() - 1], 1 << 20),
+        'G' => (&value[..value.len() - 1], 1 << 30),
+        'T' => (&value[..value.len() - 1], 1 << 40),
+        _ => (value, 1),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// A jail's cgroup
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Locate the cgroup for a jail (it may not exist yet)
+    pub fn for_jail(name: &str) -> Result<Self> {
+        Ok(Self {
+            path: delegated_root()?.join(ROBOJAIL_CGROUP).join(name),
+        })
+    }
+
+    /// Create the cgroup (if needed) and apply limits
+    pub fn create(&self, limits: &ResourceLimits) -> Result<()> {
+        let parent = self.path.parent().expect("jail cgroup has a parent");
+
+        // Controllers must be enabled at every level above the jail cgroup.
+        // Failures are fine here; we report missing controllers below.
+        if let Some(root) = parent.parent() {
+            enable_controllers(root);
+        }
+        fs::create_dir_all(parent).map_err(|e| cgroup_error(parent, e))?;
+        enable_controllers(parent);
+        fs::create_dir_all(&self.path).map_err(|e| cgroup_error(&self.path, e))?;
+
+        for (file, value) in limits.cgroup_files() {
+            let path = self.path.join(file);
+            if !path.exists() {
+                return Err(Error::Cgroup(format!(
+                    "cannot set {file}: the '{}' controller is not delegated to {}",
+                    file.split('.').next().unwrap_or(file),
+                    parent.display()
+                )));
+            }
+            fs::write(&path, &value)
+                .map_err(|e| Error::Cgroup(format!("failed to write '{value}' to {}: {e}", path.display())))?;
+        }
+
+        Ok(())
+    }
+
+    /// Move the calling process into the cgroup
+    pub fn add_self(&self) -> Result<()> {
+        let procs = self.path.join("cgroup.procs");
+        fs::write(&procs, "0").map_err(|e| cgroup_error(&procs, e))
+    }
+
+    /// Read current usage, or None if the cgroup doesn't exist (jail not running)
+    pub fn usage(&self) -> Option<ResourceUsage> {
+        if !self.path.join("cgroup.procs").exists() {
+            return None;
+        }
+
+        let read_u64 = |file: &str| -> Option<u64> {
+            fs::read_to_string(self.path.join(file)).ok()?.trim().parse().ok()
+        };
+
+        let cpu_usec = fs::read_to_string(self.path.join("cpu.stat"))
+            .ok()
+            .and_then(|stat| flat_keyed_value(&stat, "usage_usec"));
+
+        // io.stat has one line per device: "8:0 rbytes=... wbytes=... ..."
+        let (io_read_bytes, io_write_bytes) = match fs::read_to_string(self.path.join("io.stat")) {
+            Ok(stat) => {
+                let sum = |key: &str| stat.lines().filter_map(|line| nested_keyed_value(line, key)).sum();
+                (Some(sum("rbytes")), Some(sum("wbytes")))
+            }
+            Err(_) => (None, None),
+        };
+
+        Some(ResourceUsage {
+            memory_bytes: read_u64("memory.current"),
+            memory_peak_bytes: read_u64("memory.peak"),
+            cpu_usec,
+            pids: read_u64("pids.current"),
+            io_read_bytes,
+            io_write_bytes,
+        })
+    }
+
+    /// Freeze or thaw every process in the cgroup
+    ///
+    /// Waits up to a second for the freeze to take effect.
+    pub fn freeze(&self, frozen: bool) -> Result<()> {
+        let control = self.path.join("cgroup.freeze");
+        fs::write(&control, if frozen { "1" } else { "0" }).map_err(|e| cgroup_error(&control, e))?;
+
+        let wanted = if frozen { "frozen 1" } else { "frozen 0" };
+        for _ in 0..100 {
+            let events = fs::read_to_string(self.path.join("cgroup.events")).unwrap_or_default();
+            if events.lines().any(|line| line == wanted) {
+                return Ok(());
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        Err(Error::Cgroup(format!("{} did not {}", self.path.display(), if frozen { "freeze" } else { "thaw" })))
+    }
+
+    /// Remove the cgroup (fails harmlessly while processes are still in it)
+    pub fn remove(&self) {
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Look up `key` in a flat-keyed file like cpu.stat ("key value" per line)
+fn flat_keyed_value(content: &str, key: &str) -> Option<u64> {
+    content.lines().find_map(|line| {
+        let (k, v) = line.split_once(' ')?;
+        if k == key {
+            v.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Look up `key` in a nested-keyed line like io.stat ("dev key=value ...")
+fn nested_keyed_value(line: &str, key: &str) -> Option<u64> {
+    line.split_whitespace().skip(1).find_map(|field| {
+        let (k, v) = field.split_once('=')?;
+        if k == key {
+            v.parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+/// Enable as many controllers as possible for children of `dir`
+fn enable_controllers(dir: &Path) {
+    let control = dir.join("cgroup.subtree_control");
+    let enabled = fs::read_to_string(&control).unwrap_or_default();
+
+    for controller in CONTROLLERS {
+        if !enabled.split_whitespace().any(|c| c == controller) {
+            let _ = fs::write(&control, format!("+{controller}"));
+        }
+    }
+}
+
+/// Move this process into a scope of the user's service manager
+///
+/// This is what `systemd-run --user --scope` does, for robojail started
+/// outside of `user@<uid>.service` (e.g. from an SSH login), so that jail
+/// cgroups can be created in the subtree the service manager owns.
+pub fn enter_user_scope() -> Result<()> {
+    let pid = std::process::id().to_string();
+    let unit = format!("robojail-{pid}.scope");
+    let output = Command::new("busctl")
+        .args([
+            "--user",
+            "call",
+            "org.freedesktop.systemd1",
+            "/org/freedesktop/systemd1",
+            "org.freedesktop.systemd1.Manager",
+            "StartTransientUnit",
+            "ssa(sv)a(sa(sv))",
+            &unit,
+            "fail",
+            "1",
+            "PIDs",
+            "au",
+            "1",
+            &pid,
+            "0",
+        ])
+        .output()
+        .map_err(|e| Error::CgroupNotDelegated(format!("cannot run busctl to start {unit}: {e}")))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::CgroupNotDelegated(format!("failed to start {unit}: {}", stderr.trim())));
+    }
+
+    // The service manager moves us once the scope's start job has run
+    let deadline = Instant::now() + SCOPE_TIMEOUT;
+    while Instant::now() < deadline {
+        if delegated_root().is_ok() {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    Err(Error::CgroupNotDelegated(format!("{unit} did not take over this process")))
+}
+
+/// Find the cgroup subtree we are allowed to manage
+///
+/// This is the `user@<uid>.service` cgroup systemd delegates to the user's
+/// service manager. Without one (e.g. when running as root outside of a
+/// user session) we fall back to our own cgroup.
+fn delegated_root() -> Result<PathBuf> {
+    let mount = cgroup2_mount()?;
+    let own = fs::read_to_string("/proc/self/cgroup")?
+        .lines()
+        .find_map(|line| line.strip_prefix("0::").map(str::to_string))
+        .ok_or_else(|| Error::Cgroup("not running in a cgroup v2 hierarchy".to_string()))?;
+
+    let uid = nix::unistd::getuid();
+    let service = format!("user@{uid}.service");
+
+    let mut delegated = PathBuf::new();
+    for component in own.trim_start_matches('/').split('/').filter(|c| !c.is_empty()) {
+        delegated.push(component);
+        if component == service {
+            return Ok(mount.join(delegated));
+        }
+    }
+
+    if uid.is_root() {
+        return Ok(mount.join(own.trim_start_matches('/')));
+    }
+
+    Err(Error::CgroupNotDelegated(format!("not running under {service}")))
+}
+
+/// Find where the cgroup v2 hierarchy is mounted
+fn cgroup2_mount() -> Result<PathBuf> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+
+    mountinfo
+        .lines()
+        .find_map(|line| {
+            let (pre, post) = line.split_once(" - ")?;
+            if post.split_whitespace().next()? == "cgroup2" {
+                pre.split_whitespace().nth(4).map(PathBuf::from)
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| Error::Cgroup("cgroup v2 is not mounted".to_string()))
+}
+
+fn cgroup_error(path: &Path, e: std::io::Error) -> Error {
+    Error::Cgroup(format!("{}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("512M"), Some(512 << 20));
+        assert_eq!(parse_size("4g"), Some(4 << 30));
+        assert_eq!(parse_size("lots"), None);
+    }
+
+    #[test]
+    fn test_limits_or_defaults() {
+        let defaults = ResourceLimits {
+            memory_max: Some("8G".to_string()),
+            pids_max: Some(1024),
+            ..Default::default()
+        };
+        let limits = ResourceLimits {
+            memory_max: Some("2G".to_string()),
+            cpus: Some(2.0),
+            ..Default::default()
+        }
+        .or(&defaults);
+
+        assert_eq!(limits.memory_max.as_deref(), Some("2G"));
+        assert_eq!(limits.pids_max, Some(1024));
+        assert_eq!(limits.cpus, Some(2.0));
+    }
+
+    #[test]
+    fn test_limits_validate() {
+        let mut limits = ResourceLimits {
+            memory_max: Some("4G".to_string()),
+            cpus: Some(1.5),
+            io_max: vec!["8:0 rbps=1048576".to_string()],
+            ..Default::default()
+        };
+        assert!(limits.validate().is_ok());
+
+        limits.cpus = Some(0.0);
+        assert!(limits.validate().is_err());
+
+        limits.cpus = None;
+        limits.io_max = vec!["sda rbps=1".to_string()];
+        assert!(limits.validate().is_err());
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(3 << 30), "3.0G");
+    }
+
+    #[test]
+    fn test_stat_parsing() {
+        let cpu_stat = "usage_usec 1234567\nuser_usec 1000000\nsystem_usec 234567\n";
+        assert_eq!(flat_keyed_value(cpu_stat, "usage_usec"), Some(1234567));
+        assert_eq!(flat_keyed_value(cpu_stat, "nr_periods"), None);
+
+        let io_line = "259:0 rbytes=4096 wbytes=8192 rios=1 wios=2 dbytes=0 dios=0";
+        assert_eq!(nested_keyed_value(io_line, "rbytes"), Some(4096));
+        assert_eq!(nested_keyed_value(io_line, "wbytes"), Some(8192));
+    }
+
+    #[test]
+    fn test_cpu_max() {
+        let limits = ResourceLimits {
+            cpus: Some(2.5),
+            ..Default::default()
+        };
+        assert_eq!(limits.cgroup_files(), vec![("cpu.max", "250000 100000".to_string())]);
+    }
+}
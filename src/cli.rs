@@ -1,3 +1,7 @@
+use crate::config::NetworkMode;
+use crate::jail::{ExportFormat, MergeStrategy};
+use crate::sandbox::PortMapping;
+use crate::state::JailMode;
 use clap::{Parser, Subcommand};
 use std::path::PathBuf;
 
@@ -12,7 +16,9 @@ pub struct Cli {
     pub command: Command,
 }
 
+// Parsed once at startup, so the size of `Create` doesn't matter
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 pub enum Command {
     /// Create a new jail from a git repository
     #[command(visible_alias = "new")]
@@ -29,10 +35,77 @@ pub enum Command {
         #[arg(short, long)]
         branch: Option<String>,
 
+        /// How the jail's files are backed: a git worktree, or a copy-on-write
+        /// overlay of the checkout including untracked and ignored files
+        #[arg(long, value_enum, default_value_t = JailMode::Worktree)]
+        mode: JailMode,
+
         /// Entrypoint program to run (e.g., 'claude', '/usr/bin/python')
         /// The binary will be auto-detected and bind-mounted into the jail
         #[arg(short, long)]
         entrypoint: Option<String>,
+
+        /// Memory limit (e.g., '4G'; overrides the config default)
+        #[arg(long, value_name = "SIZE")]
+        memory: Option<String>,
+
+        /// Swap limit (e.g., '0' to disable swap)
+        #[arg(long, value_name = "SIZE")]
+        memory_swap: Option<String>,
+
+        /// CPU limit as a number of CPUs (e.g., '2.5')
+        #[arg(long, value_name = "N")]
+        cpus: Option<f64>,
+
+        /// Maximum number of processes and threads
+        #[arg(long, value_name = "N")]
+        pids: Option<u64>,
+
+        /// IO limit in cgroup io.max format (e.g., '259:0 wbps=10485760'); repeatable
+        #[arg(long, value_name = "SPEC")]
+        io_max: Vec<String>,
+
+        /// Network mode (overrides the config default)
+        #[arg(long, value_enum)]
+        network: Option<NetworkMode>,
+
+        /// Domain reachable in proxy network mode (e.g., 'crates.io', '*.github.com'); repeatable
+        #[arg(long, value_name = "DOMAIN")]
+        allow_domain: Vec<String>,
+
+        /// Destination reachable in filtered network mode (e.g., '127.0.0.1:5432', '10.0.0.0/8'); repeatable
+        #[arg(long, value_name = "CIDR[:PORT]")]
+        allow_net: Vec<String>,
+
+        /// Publish a jail port on the host's 127.0.0.1 (e.g., '3000:8080'); repeatable
+        #[arg(short, long, value_name = "HOST:JAIL")]
+        publish: Vec<PortMapping>,
+    },
+
+    /// Create a new jail from another jail's current state, uncommitted changes included
+    Clone {
+        /// Jail to clone (may be running)
+        source: String,
+
+        /// Name of the new jail
+        name: String,
+
+        /// Clone even if the repository's hooks or config changed while the source existed
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Rename a jail (refused while it is running)
+    Rename {
+        /// Current name of the jail
+        name: String,
+
+        /// New name
+        new_name: String,
+
+        /// Also rename the jail's branch to match
+        #[arg(long)]
+        rename_branch: bool,
     },
 
     /// List all jails
@@ -48,6 +121,65 @@ pub enum Command {
     Enter {
         /// Name of the jail to enter
         name: String,
+
+        /// Start in the background; attach with `robojail attach`
+        #[arg(short, long)]
+        detach: bool,
+    },
+
+    /// Attach the terminal to a jail's detached session (detach with Ctrl-P Ctrl-Q)
+    Attach {
+        /// Name of the jail
+        name: String,
+
+        /// Only watch; keystrokes are not sent to the jail
+        #[arg(long)]
+        read_only: bool,
+    },
+
+    /// Show a jail's logged output, and when its sessions started and ended
+    Logs {
+        /// Name of the jail
+        name: String,
+
+        /// Keep printing new lines as they are logged
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Only lines since a time span ago (10m, 2h, 1d) or a time (2025-01-01 12:00)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Only the last N lines
+        #[arg(long, value_name = "N")]
+        tail: Option<usize>,
+    },
+
+    /// List a jail's recorded sessions
+    Sessions {
+        /// Name of the jail
+        name: String,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Play back a recorded session
+    Replay {
+        /// Name of the jail
+        name: String,
+
+        /// Session to play, as listed by `robojail sessions`
+        session: String,
+
+        /// Playback speed multiplier
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Shorten pauses to at most this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        idle_limit: Option<f64>,
     },
 
     /// Destroy a jail and clean up its worktree
@@ -67,6 +199,10 @@ pub enum Command {
         /// Name of the jail
         name: String,
 
+        /// Start in the background; attach with `robojail attach`
+        #[arg(short, long)]
+        detach: bool,
+
         /// Command to run (with arguments)
         #[arg(trailing_var_arg = true, required = true)]
         command: Vec<String>,
@@ -84,5 +220,169 @@ pub enum Command {
         /// Show full diff output
         #[arg(short, long)]
         diff: bool,
+
+        /// Show the diff against the commit the jail started from, including its commits
+        #[arg(long)]
+        since_base: bool,
+    },
+
+    /// Commit a jail's pending changes and merge its branch into a branch of the repository
+    Merge {
+        /// Name of the jail
+        name: String,
+
+        /// Branch to merge into (defaults to the branch checked out in the repository)
+        #[arg(long, value_name = "BRANCH")]
+        into: Option<String>,
+
+        /// How to bring the jail's commits into the target branch
+        #[arg(long, value_enum, default_value_t = MergeStrategy::Merge)]
+        strategy: MergeStrategy,
+
+        /// Message for the commit of pending changes (and the squash commit)
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Shell command that must succeed inside the jail before merging (e.g., 'cargo test')
+        #[arg(long, value_name = "COMMAND")]
+        verify: Option<String>,
+
+        /// Destroy the jail after a successful merge
+        #[arg(long)]
+        destroy: bool,
+
+        /// Merge even if the repository's hooks or config changed while the jail existed
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Save the state of a jail's worktree as a checkpoint
+    Checkpoint {
+        /// Name of the jail
+        name: String,
+
+        /// Description of the checkpoint
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Also save ignored files (e.g., build output)
+        #[arg(long)]
+        include_ignored: bool,
+
+        /// Checkpoint even if the repository's hooks or config changed while the jail existed
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// List a jail's checkpoints
+    Checkpoints {
+        /// Name of the jail
+        name: String,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List a jail's automatic snapshots, or diff two of them
+    History {
+        /// Name of the jail
+        name: String,
+
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+
+        /// Show the changes between two snapshots
+        #[arg(long, num_args = 2, value_names = ["FROM", "TO"])]
+        diff: Option<Vec<u32>>,
+    },
+
+    /// Restore a jail's worktree to a checkpoint
+    Rollback {
+        /// Name of the jail
+        name: String,
+
+        /// Checkpoint ID (see `robojail checkpoints`)
+        checkpoint: u32,
+
+        /// Roll back even if the repository's hooks or config changed while the jail existed
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Rebase a jail's branch onto the latest tip of its base branch (committing pending work first)
+    Sync {
+        /// Name of the jail
+        name: String,
+
+        /// Branch to rebase onto (defaults to the branch the jail started from)
+        #[arg(long, value_name = "BRANCH")]
+        onto: Option<String>,
+
+        /// Output the result, including any conflicts, in JSON format
+        #[arg(long)]
+        json: bool,
+
+        /// Sync even if the repository's hooks or config changed while the jail existed
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Export a jail's work as patches or a git bundle
+    Export {
+        /// Name of the jail
+        name: String,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Patch)]
+        format: ExportFormat,
+
+        /// Output path: a directory for patches, a file for mbox and bundle
+        #[arg(short, long)]
+        output: PathBuf,
+
+        /// Commit to export from (defaults to the commit the jail started from)
+        #[arg(long, value_name = "COMMIT")]
+        base: Option<String>,
+
+        /// Export even if the repository's hooks or config changed while the jail existed
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Manage ports published from a jail to the host
+    Port {
+        /// Name of the jail
+        name: String,
+
+        #[command(subcommand)]
+        action: PortAction,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum PortAction {
+    /// Publish jail ports on the host (applies immediately if the jail is running)
+    Add {
+        /// Port mappings (e.g., '3000:8080', or '8080' for the same port on both sides)
+        #[arg(required = true, value_name = "HOST:JAIL")]
+        mappings: Vec<PortMapping>,
+    },
+
+    /// Stop publishing host ports
+    #[command(visible_alias = "rm")]
+    Remove {
+        /// Host ports to unpublish
+        #[arg(required = true, value_name = "HOST_PORT")]
+        ports: Vec<u16>,
+    },
+
+    /// List published ports
+    #[command(visible_alias = "ls")]
+    List {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
     },
 }
@@ -1,4 +1,6 @@
+use crate::cgroup::ResourceLimits;
 use crate::error::{Error, Result};
+use crate::sandbox::{DEFAULT_BLOCKED, DEFAULT_EXCEPTIONS};
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
@@ -9,9 +11,21 @@ pub struct Config {
     /// Default shell to use inside jails
     pub default_shell: String,
 
-    /// Whether to share network with host
+    /// Whether to share network with host (used when `network` is unset)
     pub network_enabled: bool,
 
+    /// Network mode for jails; overrides `network_enabled`
+    pub network: Option<NetworkMode>,
+
+    /// Domains reachable from every jail in `proxy` network mode
+    pub network_allow: Vec<String>,
+
+    /// Address ranges jails can't reach in `filtered` network mode (CIDRs)
+    pub network_blocked: Vec<String>,
+
+    /// Destinations reachable despite `network_blocked` ("CIDR" or "CIDR:port")
+    pub network_exceptions: Vec<String>,
+
     /// Additional paths to bind read-only
     pub extra_ro_binds: Vec<PathBuf>,
 
@@ -23,6 +37,115 @@ pub struct Config {
 
     /// Environment variables to pass through to jail
     pub env_passthrough: Vec<String>,
+
+    /// Built-in seccomp syscall filter profile
+    pub seccomp_profile: SeccompProfile,
+
+    /// Additional syscalls to deny inside jails (names or numbers)
+    pub seccomp_deny: Vec<String>,
+
+    /// Enforce a Landlock ruleset built from the mount plan
+    pub landlock: bool,
+
+    /// Default cgroup resource limits for new jails
+    pub limits: ResourceLimits,
+
+    /// Checkpoint worktree jails before every `enter` and `run`
+    pub checkpoint_on_start: bool,
+
+    /// Automatic snapshots of worktree jails during `enter` and `run`
+    pub snapshots: SnapshotSettings,
+
+    /// Terminal recordings of `enter` and `run` sessions
+    pub recording: RecordingSettings,
+
+    /// Rotation of the per-jail output logs
+    pub logs: LogSettings,
+}
+
+/// When per-jail logs are rotated, and how many old ones are kept
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogSettings {
+    /// Size in KiB at which a log is rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_size_kb: Option<u64>,
+
+    /// Number of rotated logs to keep per jail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+}
+
+/// Whether sessions are recorded, and what is captured
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingSettings {
+    /// Record sessions (detached ones, and foreground ones on a terminal)
+    pub enabled: bool,
+
+    /// Also record keystrokes, which may include passwords
+    pub input: bool,
+}
+
+/// When to take automatic snapshots, and how long to keep them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotSettings {
+    /// Minutes between snapshots while a session runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interval_minutes: Option<u64>,
+
+    /// Also snapshot when a session ends
+    pub on_exit: bool,
+
+    /// Number of snapshots to keep per jail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep: Option<usize>,
+
+    /// Age in hours after which snapshots are pruned
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age_hours: Option<u64>,
+}
+
+/// Built-in seccomp filter profiles
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SeccompProfile {
+    /// Deny kernel attack surface and sandbox-escape primitives
+    #[default]
+    Default,
+    /// Also deny ptrace, mounting and namespace manipulation
+    Strict,
+    /// No built-in filtering (`seccomp_deny` still applies)
+    Off,
+}
+
+/// How a jail is connected to the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// Share the host's network namespace
+    Host,
+    /// Private network namespace without connectivity
+    None,
+    /// Private network namespace whose only egress is an allowlisting proxy
+    Proxy,
+    /// Private network namespace with userspace networking (slirp4netns)
+    Slirp,
+    /// Host network, minus loopback and local-network destinations
+    Filtered,
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Proxy => write!(f, "proxy"),
+            NetworkMode::Slirp => write!(f, "slirp"),
+            NetworkMode::Filtered => write!(f, "filtered"),
+        }
+    }
 }
 
 impl Default for Config {
@@ -30,6 +153,10 @@ impl Default for Config {
         Self {
             default_shell: "/bin/bash".to_string(),
             network_enabled: true,
+            network: None,
+            network_allow: vec![],
+            network_blocked: DEFAULT_BLOCKED.iter().map(|s| s.to_string()).collect(),
+            network_exceptions: DEFAULT_EXCEPTIONS.iter().map(|s| s.to_string()).collect(),
             extra_ro_binds: vec![],
             extra_rw_binds: vec![],
             hidden_paths: vec![
@@ -49,6 +176,14 @@ impl Default for Config {
                 "LC_ALL".to_string(),
                 "COLORTERM".to_string(),
             ],
+            seccomp_profile: SeccompProfile::Default,
+            seccomp_deny: vec![],
+            landlock: false,
+            limits: ResourceLimits::default(),
+            checkpoint_on_start: false,
+            snapshots: SnapshotSettings::default(),
+            recording: RecordingSettings::default(),
+            logs: LogSettings::default(),
         }
     }
 }
@@ -94,6 +229,35 @@ impl Config {
     pub fn jails_dir() -> Result<PathBuf> {
         Ok(Self::data_dir()?.join("jails"))
     }
+
+    /// Get the per-jail state directory (logs and other records)
+    pub fn jail_state_dir(name: &str) -> Result<PathBuf> {
+        Ok(Self::state_dir()?.join("jails").join(name))
+    }
+
+    /// Get the runtime directory (sockets)
+    pub fn runtime_dir() -> Result<PathBuf> {
+        let dirs = ProjectDirs::from("", "", "robojail")
+            .ok_or_else(|| Error::Config("could not determine runtime directory".to_string()))?;
+        match dirs.runtime_dir() {
+            Some(dir) => Ok(dir.to_path_buf()),
+            None => Ok(Self::state_dir()?.join("run")),
+        }
+    }
+
+    /// Get the per-jail runtime directory
+    pub fn jail_runtime_dir(name: &str) -> Result<PathBuf> {
+        Ok(Self::runtime_dir()?.join(name))
+    }
+
+    /// Network mode used for jails without their own setting
+    pub fn network_mode(&self) -> NetworkMode {
+        match self.network {
+            Some(mode) => mode,
+            None if self.network_enabled => NetworkMode::Host,
+            None => NetworkMode::None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -106,6 +270,8 @@ mod tests {
         assert_eq!(config.default_shell, "/bin/bash");
         assert!(config.network_enabled);
         assert!(config.hidden_paths.contains(&".ssh".to_string()));
+        assert_eq!(config.seccomp_profile, SeccompProfile::Default);
+        assert_eq!(config.network_mode(), NetworkMode::Host);
     }
 
     #[test]
@@ -115,9 +281,39 @@ mod tests {
             network_enabled = false
             hidden_paths = [".ssh", ".gnupg"]
             env_passthrough = ["TERM"]
+            seccomp_profile = "strict"
+            seccomp_deny = ["socket"]
+
+            [limits]
+            memory_max = "4G"
+            pids_max = 512
+
+            [snapshots]
+            interval_minutes = 10
+            keep = 20
         "#;
         let config: Config = toml::from_str(toml_str).unwrap();
         assert_eq!(config.default_shell, "/bin/zsh");
         assert!(!config.network_enabled);
+        assert_eq!(config.seccomp_profile, SeccompProfile::Strict);
+        assert_eq!(config.seccomp_deny, vec!["socket".to_string()]);
+        assert_eq!(config.limits.memory_max.as_deref(), Some("4G"));
+        assert_eq!(config.limits.pids_max, Some(512));
+        assert_eq!(config.snapshots.interval_minutes, Some(10));
+        assert_eq!(config.snapshots.keep, Some(20));
+        assert!(!config.snapshots.on_exit);
+        assert_eq!(config.network_mode(), NetworkMode::None);
+    }
+
+    #[test]
+    fn test_network_mode_overrides_network_enabled() {
+        let toml_str = r#"
+            network_enabled = true
+            network = "proxy"
+            network_allow = ["crates.io", "*.github.com"]
+        "#;
+        let config: Config = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.network_mode(), NetworkMode::Proxy);
+        assert_eq!(config.network_allow.len(), 2);
     }
 }
@@ -42,12 +42,24 @@ pub enum Error {
     #[error("git command failed: {0}")]
     GitCommand(String),
 
+    #[error("'{branch}' conflicts with '{target}'; nothing was changed. Conflicting files:{files}")]
+    MergeConflict { branch: String, target: String, files: String },
+
+    #[error("cgroup error: {0}")]
+    Cgroup(String),
+
+    #[error("no cgroup is delegated to robojail: {0}")]
+    CgroupNotDelegated(String),
+
     #[error("config error: {0}")]
     Config(String),
 
     #[error("state file corrupted: {0}")]
     StateCorrupted(String),
 
+    #[error("failed to lock the state file: {0}")]
+    StateLock(String),
+
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
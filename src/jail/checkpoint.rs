@@ -0,0 +1,183 @@
+use super::repo::{base_commit, check_branch_access, git, snapshot_commit};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::state::{Checkpoint, JailInfo, JailMode, Series, State};
+use chrono::Utc;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct CheckpointEntry {
+    id: u32,
+    message: String,
+    created: String,
+    commit: String,
+    include_ignored: bool,
+    /// Changes relative to the jail's base commit
+    changes: String,
+}
+
+/// Save the jail's worktree as a new checkpoint
+pub fn checkpoint(name: &str, message: Option<&str>, include_ignored: bool, force: bool) -> Result<()> {
+    let jail = State::load()?.get_jail(name)?.clone();
+    check_branch_access(&jail, force)?;
+
+    let message = message.unwrap_or("Manual checkpoint");
+    if let Some(checkpoint) = save(&jail, Series::Checkpoints, message, include_ignored, false)? {
+        println!("Saved checkpoint {} of jail '{}'", checkpoint.id, name);
+    }
+
+    Ok(())
+}
+
+/// Checkpoint the jail before a session starts, if the config asks for it
+///
+/// Failures only warn; they shouldn't keep anyone out of the jail.
+pub(super) fn checkpoint_before_session(name: &str, config: &Config, what: &str) {
+    if !config.checkpoint_on_start {
+        return;
+    }
+
+    let result = State::load().and_then(|state| {
+        let jail = state.get_jail(name)?.clone();
+        if jail.mode == JailMode::Overlay {
+            return Ok(());
+        }
+        check_branch_access(&jail, false)?;
+        let message = format!("Before {what}");
+        if let Some(checkpoint) = save(&jail, Series::Checkpoints, &message, false, true)? {
+            eprintln!("Saved checkpoint {} of jail '{}'", checkpoint.id, name);
+        }
+        Ok(())
+    });
+
+    if let Err(e) = result {
+        eprintln!("warning: failed to checkpoint jail '{name}': {e}");
+    }
+}
+
+/// Snapshot the worktree, pin it with a ref and record it in the state
+///
+/// With `skip_unchanged`, nothing is saved if the worktree and HEAD match the
+/// latest entry of the series.
+pub(super) fn save(
+    jail: &JailInfo,
+    series: Series,
+    message: &str,
+    include_ignored: bool,
+    skip_unchanged: bool,
+) -> Result<Option<Checkpoint>> {
+    let commit = snapshot_commit(jail, message, include_ignored)?;
+
+    // Numbered under the state lock, so concurrent saves get distinct IDs
+    State::update(|state| {
+        let latest = state.get_jail(&jail.name)?.saved(series).last().cloned();
+
+        if skip_unchanged {
+            if let Some(ref latest) = latest {
+                let same = |rev: &str| {
+                    git(&jail.repo_path, &["rev-parse", &format!("{commit}{rev}")]).ok()
+                        == git(&jail.repo_path, &["rev-parse", &format!("{}{rev}", latest.commit)]).ok()
+                };
+                if same("^{tree}") && same("^") {
+                    return Ok(None);
+                }
+            }
+        }
+
+        let id = latest.map_or(1, |latest| latest.id + 1);
+        git(&jail.repo_path, &["update-ref", &series.git_ref(&jail.name, id), &commit])?;
+
+        let checkpoint = Checkpoint {
+            id,
+            message: message.to_string(),
+            created_at: Utc::now(),
+            commit: commit.clone(),
+            include_ignored,
+        };
+        state.add_saved(&jail.name, series, checkpoint.clone())?;
+        Ok(Some(checkpoint))
+    })
+}
+
+/// List a jail's checkpoints
+pub fn checkpoints(name: &str, json: bool) -> Result<()> {
+    let state = State::load()?;
+    let jail = state.get_jail(name)?;
+
+    let base = base_commit(jail).ok();
+    let entries: Vec<CheckpointEntry> = jail
+        .checkpoints
+        .iter()
+        .map(|checkpoint| {
+            let changes = match base {
+                Some(ref base) => git(&jail.repo_path, &["diff", "--shortstat", base, &checkpoint.commit]),
+                None => Ok(String::new()),
+            };
+            CheckpointEntry {
+                id: checkpoint.id,
+                message: checkpoint.message.clone(),
+                created: checkpoint.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                commit: checkpoint.commit.clone(),
+                include_ignored: checkpoint.include_ignored,
+                changes: changes.unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No checkpoints. Save one with: robojail checkpoint {}", name);
+        return Ok(());
+    }
+
+    println!("{:<4} {:<20} {:<45} MESSAGE", "ID", "CREATED", "CHANGES SINCE BASE");
+    println!("{}", "-".repeat(100));
+    for entry in entries {
+        let changes = if entry.changes.is_empty() { "none".to_string() } else { entry.changes };
+        let message = if entry.include_ignored { format!("{} (with ignored files)", entry.message) } else { entry.message };
+        println!("{:<4} {:<20} {:<45} {}", entry.id, entry.created, changes, message);
+    }
+
+    Ok(())
+}
+
+/// Restore a jail's worktree, index and branch to a checkpoint
+///
+/// The current state is checkpointed first, so a rollback can be undone.
+pub fn rollback(name: &str, id: u32, force: bool) -> Result<()> {
+    let jail = State::load()?.get_jail(name)?.clone();
+
+    if jail.is_running() {
+        return Err(Error::JailRunning(name.to_string()));
+    }
+    check_branch_access(&jail, force)?;
+
+    let target = jail
+        .checkpoints
+        .iter()
+        .find(|checkpoint| checkpoint.id == id)
+        .cloned()
+        .ok_or_else(|| Error::Config(format!("jail '{name}' has no checkpoint {id}")))?;
+
+    let message = format!("Before rolling back to checkpoint {id}");
+    if let Some(saved) = save(&jail, Series::Checkpoints, &message, target.include_ignored, true)? {
+        println!("Saved the current state as checkpoint {}", saved.id);
+    }
+
+    // Branch and index go back to the HEAD the checkpoint was taken on, the
+    // files to the snapshot, which leaves uncommitted work uncommitted
+    let worktree = &jail.worktree_path;
+    let head = format!("{}^", target.commit);
+    git(worktree, &["reset", "--quiet", "--hard", &head])?;
+    let clean: &[&str] = if target.include_ignored { &["clean", "-q", "-f", "-d", "-x"] } else { &["clean", "-q", "-f", "-d"] };
+    git(worktree, clean)?;
+    git(worktree, &["read-tree", "-u", "--reset", &target.commit])?;
+    git(worktree, &["reset", "--quiet"])?;
+
+    println!("Rolled back jail '{}' to checkpoint {} ({})", name, id, target.message);
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use super::create::create_worktree;
+use super::repo::{discard_jail, git, jail_branch, snapshot_commit};
+use crate::cgroup::Cgroup;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::overlay;
+use crate::state::{JailInfo, JailMode, State};
+use crate::tamper;
+use crate::validation::validate_jail_name;
+use chrono::Utc;
+use std::path::Path;
+use std::process::Command;
+use uuid::Uuid;
+
+/// Create a new jail from another jail's current state
+///
+/// A worktree clone gets a branch at the source's HEAD with the source's
+/// uncommitted and untracked files on top, left uncommitted; an overlay
+/// clone gets a copy of the upper layer. A running source is frozen while
+/// it is copied, so the copy is consistent.
+pub fn clone(source: &str, name: &str, force: bool) -> Result<()> {
+    validate_jail_name(name)?;
+
+    let state = State::load()?;
+    let src = state.get_jail(source)?.clone();
+    src.check_idle()?;
+    if state.jails.contains_key(name) {
+        return Err(Error::JailExists(name.to_string()));
+    }
+
+    if src.mode == JailMode::Worktree && tamper::warn_if_changed(&src) && !force {
+        return Err(Error::GitCommand(
+            "refusing to run git in a repository whose hooks or config changed (use --force once reviewed)"
+                .to_string(),
+        ));
+    }
+
+    let jail_path = Config::jails_dir()?.join(name);
+    let branch_name = match src.mode {
+        JailMode::Worktree => jail_branch(name, &Uuid::new_v4().to_string()[..8]),
+        JailMode::Overlay => String::new(),
+    };
+
+    let info = JailInfo {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        worktree_path: jail_path.clone(),
+        branch_name: branch_name.clone(),
+        created_at: Utc::now(),
+        sessions: vec![],
+        landlock_abi: None,
+        // Both jails can't bind the same host ports
+        ports: vec![],
+        checkpoints: vec![],
+        snapshots: vec![],
+        creating: true,
+        ..src.clone()
+    };
+
+    // Reserve the name before touching the disk, as `create` does
+    State::update(|state| state.add_jail(info.clone()))?;
+    let copied = copy_jail(&src, &info).and_then(|()| {
+        State::update(|state| state.finish_jail(name)).inspect_err(|_| discard_jail(&info))
+    });
+    if let Err(e) = copied {
+        let _ = State::update(|state| state.abandon_jail(name, info.id));
+        return Err(e);
+    }
+
+    println!("Cloned jail '{}' to '{}' at {}", source, name, jail_path.display());
+    if src.mode == JailMode::Worktree {
+        println!("Branch: {}", branch_name);
+    }
+    if !src.ports.is_empty() {
+        println!("Published ports were not copied; add them with: robojail port add {} <mapping>", name);
+    }
+
+    Ok(())
+}
+
+/// Copy the source jail's state into the reserved jail `info`
+///
+/// Leaves nothing behind if it fails, and never touches a directory that
+/// was there before.
+fn copy_jail(src: &JailInfo, info: &JailInfo) -> Result<()> {
+    let jail_path = &info.worktree_path;
+    if jail_path.exists() {
+        return Err(Error::Config(format!("{} already exists", jail_path.display())));
+    }
+
+    let copied = match src.mode {
+        JailMode::Worktree => {
+            let message = format!("State of jail '{}' when cloned to '{}'", src.name, info.name);
+            let snapshot = while_frozen(src, || snapshot_commit(src, &message, false))?;
+            create_worktree(&src.repo_path, jail_path, &info.branch_name, &format!("{snapshot}^"))?;
+
+            // Files as in the snapshot, index and branch at its parent
+            git(jail_path, &["read-tree", "-u", "--reset", &snapshot])
+                .and_then(|_| git(jail_path, &["reset", "--quiet"]))
+                .map(drop)
+        }
+        JailMode::Overlay => std::fs::create_dir_all(jail_path.join(overlay::WORK_DIR))
+            .map_err(Into::into)
+            .and_then(|()| while_frozen(src, || copy_dir(&src.upper_dir(), &jail_path.join(overlay::UPPER_DIR)))),
+    };
+    copied.inspect_err(|_| discard_jail(info))
+}
+
+/// Run `f` with the jail's processes frozen, if it is running
+///
+/// Without a cgroup to freeze the copy is taken live, with a warning.
+fn while_frozen<T>(jail: &JailInfo, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    // A session's cgroup only exists while it runs
+    let Some(cgroup) = Cgroup::for_jail(&jail.name).ok().filter(|cgroup| cgroup.usage().is_some()) else {
+        if jail.is_running() {
+            eprintln!("warning: jail '{}' has no cgroup to freeze; copying it while it runs", jail.name);
+        }
+        return f();
+    };
+
+    if let Err(e) = cgroup.freeze(true) {
+        eprintln!("warning: could not freeze jail '{}' ({e}); copying it while it runs", jail.name);
+        let _ = cgroup.freeze(false);
+        return f();
+    }
+
+    let result = f();
+    cgroup.freeze(false)?;
+    result
+}
+
+/// Copy a directory tree, keeping overlayfs whiteouts and opaque markers
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    let output = Command::new("cp")
+        .arg("-a")
+        .arg("--reflink=auto")
+        .arg(from)
+        .arg(to)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Config(format!(
+            "failed to copy {}: {}",
+            from.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
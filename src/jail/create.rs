@@ -1,6 +1,11 @@
-use crate::config::Config;
+use super::repo::{discard_jail, jail_branch};
+use crate::cgroup::ResourceLimits;
+use crate::config::{Config, NetworkMode};
 use crate::error::{Error, Result};
-use crate::state::{JailInfo, State};
+use crate::overlay;
+use crate::sandbox::{check_unique_ports, NetworkPolicy, PortMapping};
+use crate::state::{JailInfo, JailMode, State};
+use crate::tamper;
 use crate::validation::{validate_git_repo, validate_jail_name};
 use chrono::Utc;
 use std::path::{Path, PathBuf};
@@ -64,53 +69,79 @@ fn resolve_command(cmd: &str) -> Result<PathBuf> {
     )))
 }
 
+/// Options for creating a jail
+#[derive(Debug)]
+pub struct CreateOptions<'a> {
+    /// Name of the jail
+    pub name: &'a str,
+    /// Path to the git repository
+    pub repo: &'a Path,
+    /// Branch to base the worktree on (defaults to HEAD)
+    pub branch: Option<&'a str>,
+    /// Worktree or overlay
+    pub mode: JailMode,
+    /// Entrypoint command line
+    pub entrypoint: Option<&'a str>,
+    /// Resource limits overriding the config defaults
+    pub limits: ResourceLimits,
+    /// Network mode overriding the config default
+    pub network: Option<NetworkMode>,
+    /// Extra domains reachable in proxy mode
+    pub allow_domains: Vec<String>,
+    /// Extra destinations reachable in filtered mode
+    pub allow_net: Vec<String>,
+    /// Jail ports to publish on the host
+    pub ports: Vec<PortMapping>,
+}
+
 /// Create a new jail from a git repository
-pub fn create(name: &str, repo: &Path, branch: Option<&str>, entrypoint: Option<&str>, _config: &Config) -> Result<()> {
+pub fn create(opts: CreateOptions, config: &Config) -> Result<()> {
+    let CreateOptions { name, repo, branch, mode, entrypoint, limits, network, allow_domains, allow_net, ports } = opts;
+
     // Validate inputs
     validate_jail_name(name)?;
     validate_git_repo(repo)?;
 
-    // Load state
-    let mut state = State::load()?;
+    let limits = limits.or(&config.limits);
+    limits.validate()?;
 
-    // Check if jail already exists
-    if state.jails.contains_key(name) {
-        return Err(Error::JailExists(name.to_string()));
+    let network_mode = network.unwrap_or_else(|| config.network_mode());
+    if !allow_domains.is_empty() && network_mode != NetworkMode::Proxy {
+        eprintln!("warning: --allow-domain only takes effect in proxy network mode");
     }
+    if !allow_net.is_empty() && network_mode != NetworkMode::Filtered {
+        eprintln!("warning: --allow-net only takes effect in filtered network mode");
+    }
+    let exceptions = [config.network_exceptions.as_slice(), &allow_net].concat();
+    NetworkPolicy::new(&config.network_blocked, &exceptions)?;
+    check_unique_ports(&ports)?;
 
-    // Generate unique branch name
-    let short_uuid = &Uuid::new_v4().to_string()[..8];
-    let branch_name = format!("robojail/{}-{}", name, short_uuid);
-
-    // Determine base ref
-    let base_ref = branch.unwrap_or("HEAD");
+    // Recorded before the jail exists, so anything it changes shows up later
+    let repo_fingerprint = tamper::fingerprint(repo)?;
 
-    // Create jail directory
-    let jails_dir = Config::jails_dir()?;
-    let jail_path = jails_dir.join(name);
-    std::fs::create_dir_all(&jail_path)?;
+    let repo_path = repo.canonicalize()?;
+    let jail_path = Config::jails_dir()?.join(name);
 
-    // Create git worktree
-    let output = Command::new("git")
-        .args([
-            "-C",
-            repo.to_str().ok_or_else(|| Error::Config("invalid repo path".to_string()))?,
-            "worktree",
-            "add",
-            "-b",
-            &branch_name,
-            jail_path.to_str().ok_or_else(|| Error::Config("invalid jail path".to_string()))?,
-            base_ref,
-        ])
-        .output()?;
-
-    if !output.status.success() {
-        // Clean up directory on failure
-        let _ = std::fs::remove_dir_all(&jail_path);
+    // Overlay jails always start from the checkout as it is
+    let base_ref = match mode {
+        JailMode::Worktree => branch.unwrap_or("HEAD"),
+        JailMode::Overlay => "HEAD",
+    };
+    let (base_commit, base_branch) = resolve_base(repo, base_ref)?;
 
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(Error::WorktreeCreation(stderr.to_string()));
-    }
+    let branch_name = match mode {
+        JailMode::Worktree => {
+            // Generate unique branch name
+            let short_uuid = &Uuid::new_v4().to_string()[..8];
+            jail_branch(name, short_uuid)
+        }
+        JailMode::Overlay => {
+            if branch.is_some() {
+                eprintln!("warning: --branch is ignored in overlay mode; the jail starts from the checkout as it is");
+            }
+            String::new()
+        }
+    };
 
     // Parse and resolve entrypoint if provided
     let resolved_entrypoint = if let Some(ep) = entrypoint {
@@ -129,19 +160,111 @@ pub fn create(name: &str, repo: &Path, branch: Option<&str>, entrypoint: Option<
     let info = JailInfo {
         id: Uuid::new_v4(),
         name: name.to_string(),
-        repo_path: repo.canonicalize()?,
+        repo_path,
         worktree_path: jail_path.clone(),
-        branch_name,
+        branch_name: branch_name.clone(),
+        mode,
+        base_commit: Some(base_commit.clone()),
+        base_branch,
         created_at: Utc::now(),
-        pid: None,
+        sessions: vec![],
         entrypoint: resolved_entrypoint,
+        landlock_abi: None,
+        limits,
+        network,
+        network_allow: allow_domains,
+        network_exceptions: allow_net,
+        ports,
+        repo_fingerprint: Some(repo_fingerprint),
+        checkpoints: vec![],
+        snapshots: vec![],
+        creating: true,
+        busy: None,
     };
 
-    // Add to state
-    state.add_jail(info)?;
+    // Reserve the name before touching the disk, so a concurrent create of
+    // the same jail fails here rather than racing for its directory
+    State::update(|state| state.add_jail(info.clone()))?;
+
+    if let Err(e) = set_up(&info, &base_commit) {
+        let _ = State::update(|state| state.abandon_jail(name, info.id));
+        return Err(e);
+    }
+    if let Err(e) = State::update(|state| state.finish_jail(name)) {
+        discard_jail(&info);
+        let _ = State::update(|state| state.abandon_jail(name, info.id));
+        return Err(e);
+    }
 
     println!("Created jail '{}' at {}", name, jail_path.display());
-    println!("Branch: robojail/{}-{}", name, short_uuid);
+    match mode {
+        JailMode::Worktree => println!("Branch: {}", branch_name),
+        JailMode::Overlay => println!("Overlay of: {}", repo.display()),
+    }
+
+    Ok(())
+}
+
+/// Create a reserved jail's worktree or overlay directories
+///
+/// Leaves nothing behind if it fails, and never touches a directory that
+/// was there before.
+fn set_up(info: &JailInfo, base_commit: &str) -> Result<()> {
+    let jail_path = &info.worktree_path;
+    if jail_path.exists() {
+        return Err(Error::Config(format!("{} already exists", jail_path.display())));
+    }
+    std::fs::create_dir_all(jail_path)?;
+
+    match info.mode {
+        JailMode::Worktree => create_worktree(&info.repo_path, jail_path, &info.branch_name, base_commit),
+        JailMode::Overlay => std::fs::create_dir_all(jail_path.join(overlay::UPPER_DIR))
+            .and_then(|()| std::fs::create_dir_all(jail_path.join(overlay::WORK_DIR)))
+            .map_err(|e| {
+                discard_jail(info);
+                e.into()
+            }),
+    }
+}
+
+/// Resolve the ref a jail starts from to a commit, and to a branch name if it is one
+fn resolve_base(repo: &Path, base_ref: &str) -> Result<(String, Option<String>)> {
+    let git = |args: &[&str]| {
+        Command::new("git").arg("-C").arg(repo).args(args).output().map(|output| {
+            output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+    };
+
+    let commit = git(&["rev-parse", "--verify", "--quiet", &format!("{base_ref}^{{commit}}")])?
+        .ok_or_else(|| Error::WorktreeCreation(format!("'{base_ref}' is not a commit")))?;
+    let branch = git(&["rev-parse", "--symbolic-full-name", base_ref])?
+        .and_then(|name| name.strip_prefix("refs/heads/").map(str::to_string));
+
+    Ok((commit, branch))
+}
+
+/// Create the git worktree for a jail on a new branch
+pub(super) fn create_worktree(repo: &Path, jail_path: &Path, branch_name: &str, base_ref: &str) -> Result<()> {
+    let output = Command::new("git")
+        .args([
+            "-C",
+            repo.to_str().ok_or_else(|| Error::Config("invalid repo path".to_string()))?,
+            "worktree",
+            "add",
+            "-b",
+            branch_name,
+            jail_path.to_str().ok_or_else(|| Error::Config("invalid jail path".to_string()))?,
+            base_ref,
+        ])
+        .output()?;
+
+    if !output.status.success() {
+        // Clean up directory on failure
+        let _ = std::fs::remove_dir_all(jail_path);
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::WorktreeCreation(stderr.to_string()));
+    }
 
     Ok(())
 }
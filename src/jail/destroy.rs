@@ -1,40 +1,148 @@
+use super::repo::delete_jail_refs;
+use crate::cgroup::Cgroup;
+use crate::config::Config;
 use crate::error::{Error, Result};
-use crate::state::State;
+use crate::overlay;
+use crate::sandbox::GitLayout;
+use crate::state::{Busy, JailInfo, JailMode, State};
+use crate::tamper;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::process::Command;
 
 /// Destroy a jail and clean up its worktree
+///
+/// The jail is marked as being destroyed first, so no session starts and no
+/// other command uses it while it is torn down.
 pub fn destroy(name: &str, force: bool) -> Result<()> {
-    let mut state = State::load()?;
-    let jail = state.get_jail(name)?;
-
-    // Check if running
-    if let Some(pid) = jail.pid {
-        if State::is_pid_alive(pid) {
-            if !force {
-                return Err(Error::JailRunning(name.to_string()));
+    let jail = State::update(|state| {
+        let jail = state.get_jail_mut(name)?;
+
+        // Only a create, rename or destroy that died leaves a jail in these states for long
+        if !force {
+            if let Err(Error::Config(reason)) = jail.check_idle() {
+                return Err(Error::Config(format!("{reason}; use --force if that was interrupted")));
+            }
+        }
+
+        // A jail ends with the processes running its sandboxes
+        if jail.is_running() && !force {
+            let sessions: Vec<String> = jail.live_sessions().iter().map(|session| session.describe()).collect();
+            eprintln!("Running sessions:\n  {}", sessions.join("\n  "));
+            return Err(Error::JailRunning(name.to_string()));
+        }
+
+        jail.busy = Some(Busy::Destroying);
+        Ok(jail.clone())
+    })?;
+
+    if let Err(e) = tear_down(&jail, force) {
+        let cleared = State::update(|state| {
+            let jail = state.get_jail_mut(name)?;
+            if jail.busy == Some(Busy::Destroying) {
+                jail.busy = None;
             }
+            Ok(())
+        });
+        if let Err(e) = cleared {
+            eprintln!("warning: failed to clear the jail's destroy: {e}");
+        }
+        return Err(e);
+    }
+
+    // Remove from state
+    State::update(|state| state.remove_jail(name))?;
+
+    println!("Destroyed jail '{}'", name);
+
+    Ok(())
+}
 
-            // Kill the process
-            eprintln!("Killing running jail process (PID {})...", pid);
+/// Kill a jail's sessions and remove everything it has on disk
+fn tear_down(jail: &JailInfo, force: bool) -> Result<()> {
+    let name = jail.name.as_str();
+
+    // Sessions can't start any more, so this is all of them
+    if jail.is_running() {
+        // Detached sessions are listed too, by their supervisor
+        let sessions = jail.live_sessions();
+        for session in &sessions {
+            eprintln!("Killing session {}...", session.describe());
             unsafe {
-                libc::kill(pid as i32, libc::SIGTERM);
+                libc::kill(session.pid as i32, libc::SIGTERM);
             }
+        }
 
-            // Give it a moment to terminate
-            std::thread::sleep(std::time::Duration::from_millis(500));
+        // Give them a moment to terminate
+        std::thread::sleep(std::time::Duration::from_millis(500));
 
-            // Force kill if still alive
-            if State::is_pid_alive(pid) {
+        // Force kill if still alive; the start time rules out a reused PID
+        for session in &sessions {
+            if session.is_alive() {
                 unsafe {
-                    libc::kill(pid as i32, libc::SIGKILL);
+                    libc::kill(session.pid as i32, libc::SIGKILL);
                 }
             }
         }
     }
 
+    tamper::warn_if_changed(jail);
+
     let repo_path = jail.repo_path.clone();
     let worktree_path = jail.worktree_path.clone();
+    let mode = jail.mode;
+
+    if mode == JailMode::Worktree {
+        // Commits on the jail's branch need their objects in the main store
+        if let Some(layout) = GitLayout::discover(&worktree_path) {
+            layout.release()?;
+        }
+        remove_worktree(&repo_path, &worktree_path, force)?;
+    } else {
+        // overlayfs leaves a work/work directory with mode 000 behind
+        let _ = std::fs::set_permissions(
+            worktree_path.join(overlay::WORK_DIR).join("work"),
+            std::fs::Permissions::from_mode(0o700),
+        );
+    }
 
+    // Clean up directory if it still exists
+    if worktree_path.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&worktree_path) {
+            eprintln!(
+                "warning: failed to remove jail directory {}: {e}",
+                worktree_path.display()
+            );
+        }
+    }
+
+    // Prune worktrees
+    if mode == JailMode::Worktree {
+        let _ = Command::new("git")
+            .args(["-C", repo_path.to_str().unwrap_or("."), "worktree", "prune"])
+            .output();
+    }
+
+    // Drop the refs pinning the jail's checkpoints
+    if let Err(e) = delete_jail_refs(&repo_path, name) {
+        eprintln!("warning: failed to delete the jail's refs: {e}");
+    }
+
+    // Remove the jail's cgroup, if one is left over
+    if let Ok(cgroup) = Cgroup::for_jail(name) {
+        cgroup.remove();
+    }
+
+    // Remove the jail's sockets and logs
+    for dir in [Config::jail_runtime_dir(name), Config::jail_state_dir(name)].into_iter().flatten() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    Ok(())
+}
+
+/// Remove a jail's git worktree, forcing it if the jail has changes
+fn remove_worktree(repo_path: &Path, worktree_path: &Path, force: bool) -> Result<()> {
     // Try to remove git worktree
     let output = Command::new("git")
         .args([
@@ -83,25 +191,5 @@ pub fn destroy(name: &str, force: bool) -> Result<()> {
         }
     }
 
-    // Clean up directory if it still exists
-    if worktree_path.exists() {
-        if let Err(e) = std::fs::remove_dir_all(&worktree_path) {
-            eprintln!(
-                "warning: failed to remove jail directory {}: {e}",
-                worktree_path.display()
-            );
-        }
-    }
-
-    // Prune worktrees
-    let _ = Command::new("git")
-        .args(["-C", repo_path.to_str().unwrap_or("."), "worktree", "prune"])
-        .output();
-
-    // Remove from state
-    state.remove_jail(name)?;
-
-    println!("Destroyed jail '{}'", name);
-
     Ok(())
 }
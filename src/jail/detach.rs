@@ -0,0 +1,188 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::logs::Log;
+use crate::recording::{self, Recorder};
+use crate::session::{self, Detach, Pty};
+use crate::state::{JailInfo, Session, SessionKind, State};
+use chrono::Utc;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{dup2, fork, pipe, setsid, ForkResult};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Start a session in the background, under a supervisor that owns its terminal
+///
+/// Runs `command`, or without one enters the jail like `robojail enter`.
+/// Returns once the session is recorded in the state.
+pub fn detach(name: &str, command: Option<&[String]>, config: &Config) -> Result<()> {
+    let state = State::load()?;
+    let jail = state.get_jail(name)?;
+    if let Some(session) = jail.detached_session() {
+        return Err(Error::Config(format!(
+            "jail '{name}' already has a detached session (PID {}); attach with: robojail attach {name}",
+            session.pid
+        )));
+    }
+
+    let socket = Config::jail_runtime_dir(name)?.join("session.sock");
+    let log_path = Config::jail_state_dir(name)?.join("session.log");
+    std::fs::create_dir_all(Config::jail_state_dir(name)?)?;
+    let log = OpenOptions::new().create(true).append(true).open(&log_path)?;
+    let pty = Pty::open()?;
+    let (ready_read, ready_write) = pipe()?;
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => {
+            drop(ready_write);
+            let mut ready = [0u8; 1];
+            if File::from(ready_read).read(&mut ready).unwrap_or(0) == 0 {
+                let _ = waitpid(child, None);
+                return Err(Error::SandboxSetup(format!(
+                    "the session failed to start; see {}",
+                    log_path.display()
+                )));
+            }
+
+            println!("Started jail '{}' in the background (PID {})", name, child);
+            println!("Attach with: robojail attach {}   (detach with Ctrl-P Ctrl-Q)", name);
+            Ok(())
+        }
+        Ok(ForkResult::Child) => {
+            drop(ready_read);
+            let code = match supervise(name, command, config, pty, &socket, &log, File::from(ready_write)) {
+                Ok(code) => code,
+                Err(e) => {
+                    eprintln!("robojail: detached session of jail '{name}' failed: {e}");
+                    1
+                }
+            };
+            std::process::exit(code)
+        }
+        Err(e) => Err(Error::Nix(e)),
+    }
+}
+
+/// Body of the supervisor: start the session, relay its terminal, clean up
+fn supervise(
+    name: &str,
+    command: Option<&[String]>,
+    config: &Config,
+    pty: Pty,
+    socket: &Path,
+    log: &File,
+    mut ready: File,
+) -> Result<i32> {
+    // Leave the starting terminal behind
+    setsid()?;
+    for fd in 1..=2 {
+        dup2(log.as_raw_fd(), fd)?;
+    }
+    dup2(File::open("/dev/null")?.as_raw_fd(), 0)?;
+
+    // Recorded before the session starts, as the session updates the state too.
+    // The socket is only bound under the lock, once no other detached session
+    // can be using it.
+    let (session, listener) = State::update(|state| {
+        let jail = state.get_jail(name)?;
+        // Checked again under the lock, in case another one just started
+        if let Some(other) = jail.detached_session() {
+            return Err(Error::Config(format!("jail '{name}' already has a detached session (PID {})", other.pid)));
+        }
+        let mut session = Session::current(SessionKind::Detached, &description(command, jail, config), None);
+        session.socket = Some(socket.to_path_buf());
+        state.add_session(name, session.clone())?;
+        Ok((session, session::bind(socket)?))
+    })?;
+    let recorder = match config.recording.enabled {
+        true => {
+            let path = recording::new_path(name)?;
+            Some(Arc::new(Recorder::create(&path, (24, 80), &session.command, name, config.recording.input)?))
+        }
+        false => None,
+    };
+
+    let runner = match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => child,
+        Ok(ForkResult::Child) => {
+            drop(listener);
+            drop(ready);
+
+            // The supervisor records the session
+            let mut config = config.clone();
+            config.recording.enabled = false;
+            let code = pty.into_terminal().and_then(|_| match command {
+                Some(command) => super::run(name, command, &config),
+                None => super::enter(name, &config).map(|_| 0),
+            });
+            match code {
+                Ok(code) => std::process::exit(code),
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => {
+            let _ = State::update(|state| state.remove_session(name, session.id));
+            return Err(Error::Nix(e));
+        }
+    };
+
+    ready.write_all(b"1")?;
+    drop(ready);
+
+    // The runner logs when the session starts and ends, the supervisor its output
+    let log = Log::open(name, &config.logs)
+        .inspect_err(|e| eprintln!("warning: failed to open the jail's log: {e}"))
+        .ok();
+
+    // Threads only from here on, now that nothing else is forked
+    session::serve(pty.into_master(), listener, recorder, log);
+
+    let code = match waitpid(runner, None) {
+        Ok(WaitStatus::Exited(_, code)) => code,
+        Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+        _ => 1,
+    };
+    eprintln!("{}: session of jail '{}' exited with code {}", Utc::now().format("%Y-%m-%d %H:%M:%S"), name, code);
+
+    let _ = std::fs::remove_file(socket);
+    State::update(|state| state.remove_session(name, session.id))?;
+    Ok(code)
+}
+
+/// What a detached session runs, for the state and its recording
+fn description(command: Option<&[String]>, jail: &JailInfo, config: &Config) -> String {
+    match (command, &jail.entrypoint) {
+        (Some(command), _) => command.join(" "),
+        (None, Some(entrypoint)) => entrypoint.join(" "),
+        (None, None) => config.default_shell.clone(),
+    }
+}
+
+/// Attach the terminal to a jail's detached session
+pub fn attach(name: &str, read_only: bool) -> Result<()> {
+    let state = State::load()?;
+    let jail = state.get_jail(name)?;
+    let session = jail.detached_session().ok_or_else(|| {
+        Error::Config(format!("jail '{name}' has no detached session; start one with: robojail run --detach {name} -- <command>"))
+    })?;
+
+    if read_only {
+        eprintln!("Watching jail '{}' (read-only); leave with Ctrl-C or Ctrl-P Ctrl-Q", name);
+    } else {
+        eprintln!("Attached to jail '{}'; detach with Ctrl-P Ctrl-Q", name);
+    }
+
+    let socket = session.socket.as_ref().ok_or_else(|| {
+        Error::Config(format!("the detached session of jail '{name}' has no socket to attach to"))
+    })?;
+    match session::attach(socket, read_only)? {
+        Detach::Detached => eprintln!("\r\nDetached from jail '{}'", name),
+        Detach::Ended => eprintln!("\r\nThe session of jail '{}' ended", name),
+    }
+    Ok(())
+}
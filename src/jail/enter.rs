@@ -1,12 +1,18 @@
+use super::checkpoint::checkpoint_before_session;
+use super::history::{periodic_snapshots, snapshot_after_session};
+use super::sessions::session;
 use crate::config::Config;
 use crate::error::Result;
-use crate::sandbox::create_jail_sandbox;
-use crate::state::State;
+use crate::sandbox::jail_sandbox_builder;
+use crate::state::{SessionKind, State};
 
 /// Enter a jail interactively
 pub fn enter(name: &str, config: &Config) -> Result<()> {
-    let mut state = State::load()?;
-    let jail = state.get_jail(name)?;
+    // Before loading the state, which the checkpoint updates
+    checkpoint_before_session(name, config, "enter");
+
+    let state = State::load()?;
+    let jail = state.get_jail(name)?.clone();
 
     // Check that worktree still exists
     if !jail.worktree_path.exists() {
@@ -16,29 +22,27 @@ pub fn enter(name: &str, config: &Config) -> Result<()> {
         );
     }
 
-    let worktree_path = jail.worktree_path.clone();
     let entrypoint = jail.entrypoint.clone();
 
-    // Update PID in state (we use our PID as a marker that we're running)
-    // The actual sandbox runs in a child process
-    state.set_pid(name, Some(std::process::id()))?;
-
     // Create and enter sandbox
-    let sandbox = create_jail_sandbox(&worktree_path, config, entrypoint.as_deref());
+    let mut builder = jail_sandbox_builder(&jail, config)?;
+    if let Some(periodic) = periodic_snapshots(&jail, config) {
+        builder = builder.every(periodic);
+    }
+    let sandbox = builder.build();
 
     // Determine what to run
     let exit_code = if let Some(ref ep) = entrypoint {
         let display_cmd = ep.join(" ");
         println!("Running '{}' in jail '{}'...", display_cmd, name);
-        sandbox.run(ep)?
+        session(name, config, SessionKind::Enter, &display_cmd, || sandbox.run(ep))?
     } else {
         println!("Entering jail '{}'...", name);
-        sandbox.enter(&config.default_shell)?
+        let shell = &config.default_shell;
+        session(name, config, SessionKind::Enter, shell, || sandbox.enter(shell))?
     };
 
-    // Clear PID on exit
-    let mut state = State::load()?;
-    state.set_pid(name, None)?;
+    snapshot_after_session(name, config);
 
     if exit_code != 0 {
         std::process::exit(exit_code);
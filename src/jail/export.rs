@@ -0,0 +1,101 @@
+use super::repo::{base_commit, check_branch_access, git, has_pending_changes, snapshot_commit};
+use crate::error::{Error, Result};
+use crate::state::{JailInfo, State};
+use crate::tamper;
+use std::fs;
+use std::path::Path;
+
+/// Output format of `robojail export`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// One `git format-patch` file per commit, in a directory
+    Patch,
+    /// All patches in a single mbox file, for `git am`
+    Mbox,
+    /// A git bundle holding the jail's branch
+    Bundle,
+}
+
+/// Options for exporting a jail
+#[derive(Debug)]
+pub struct ExportOptions<'a> {
+    /// Name of the jail
+    pub name: &'a str,
+    pub format: ExportFormat,
+    /// Directory for `patch`, file for `mbox` and `bundle`
+    pub output: &'a Path,
+    /// Commit to export from (defaults to the commit the jail started from)
+    pub base: Option<&'a str>,
+    /// Export even if the repository's hooks or config changed
+    pub force: bool,
+}
+
+/// Export a jail's work without touching the repository's branches
+///
+/// Uncommitted and untracked files are included as an extra commit on top
+/// of the branch, built with a throwaway index so the jail's own index and
+/// branch stay as they are.
+pub fn export(opts: ExportOptions) -> Result<()> {
+    let ExportOptions { name, format, output, base, force } = opts;
+
+    let state = State::load()?;
+    let jail = state.get_jail(name)?.clone();
+    check_branch_access(&jail, force)?;
+
+    let base = match base {
+        Some(base) => git(&jail.repo_path, &["rev-parse", "--verify", &format!("{base}^{{commit}}")])?,
+        None => base_commit(&jail)?,
+    };
+
+    let mut tip = git(&jail.repo_path, &["rev-parse", &jail.branch_name])?;
+    if has_pending_changes(&jail)? {
+        let message = format!("Uncommitted changes from jail '{}'", jail.name);
+        tip = snapshot_commit(&jail, &message, false)?;
+        println!("Including uncommitted changes as an extra commit");
+    }
+
+    let range = format!("{base}..{tip}");
+    let count = git(&jail.repo_path, &["rev-list", "--count", &range])?;
+    if count == "0" {
+        return Err(Error::Config(format!("jail '{name}' has no changes since {}", &base[..12.min(base.len())])));
+    }
+
+    let output_str = output.to_string_lossy().to_string();
+    match format {
+        ExportFormat::Patch => {
+            fs::create_dir_all(output)?;
+            git(&jail.repo_path, &["format-patch", "--quiet", "-o", &output_str, &range])?;
+        }
+        ExportFormat::Mbox => {
+            let mbox = git(&jail.repo_path, &["format-patch", "--stdout", &range])?;
+            fs::write(output, mbox + "\n")?;
+        }
+        ExportFormat::Bundle => write_bundle(&jail, &base, &tip, output)?,
+    }
+
+    println!("Exported {} commit(s) from jail '{}' to {}", count, name, output.display());
+    Ok(())
+}
+
+/// Bundle `base..tip` under the jail's branch name
+///
+/// `tip` may be a commit no ref points at, so the bundle is made from a
+/// temporary repository that borrows the objects and has just that branch.
+fn write_bundle(jail: &JailInfo, base: &str, tip: &str, output: &Path) -> Result<()> {
+    let objects = tamper::common_dir(&jail.repo_path)?.join("objects");
+    let temp = std::env::temp_dir().join(format!("robojail-export-{}", std::process::id()));
+    let temp_str = temp.to_string_lossy().to_string();
+
+    let result = (|| {
+        git(&jail.repo_path, &["init", "--quiet", "--bare", &temp_str])?;
+        fs::write(temp.join("objects/info/alternates"), format!("{}\n", objects.display()))?;
+        git(&temp, &["update-ref", &format!("refs/heads/{}", jail.branch_name), tip])?;
+
+        let output = std::path::absolute(output)?;
+        let output_str = output.to_string_lossy().to_string();
+        git(&temp, &["bundle", "create", "--quiet", &output_str, &jail.branch_name, &format!("^{base}")])
+    })();
+
+    let _ = fs::remove_dir_all(&temp);
+    result.map(|_| ())
+}
@@ -0,0 +1,158 @@
+use super::checkpoint::save;
+use super::repo::git;
+use crate::config::{Config, SnapshotSettings};
+use crate::error::{Error, Result};
+use crate::sandbox::Periodic;
+use crate::state::{Checkpoint, JailInfo, JailMode, Series, State};
+use crate::tamper;
+use chrono::{Duration, Utc};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct SnapshotEntry {
+    id: u32,
+    message: String,
+    created: String,
+    commit: String,
+    /// Changes relative to the previous snapshot (or to the HEAD it was taken on)
+    changes: String,
+}
+
+/// Snapshot the jail every `interval_minutes` while a session runs
+///
+/// Returns `None` if no interval is configured or the jail has no branch.
+pub(super) fn periodic_snapshots(jail: &JailInfo, config: &Config) -> Option<Periodic> {
+    let minutes = config.snapshots.interval_minutes.filter(|&minutes| minutes > 0)?;
+    if jail.mode == JailMode::Overlay {
+        return None;
+    }
+
+    let name = jail.name.clone();
+    let settings = config.snapshots.clone();
+    let interval = std::time::Duration::from_secs(minutes * 60);
+    Some(Periodic::new(interval, move || {
+        if let Err(e) = snapshot(&name, &settings, "Periodic snapshot") {
+            eprintln!("warning: failed to snapshot jail '{name}': {e}");
+        }
+    }))
+}
+
+/// Snapshot the jail once a session ends, if the config asks for it
+///
+/// Like the checkpoint before a session, failures only warn.
+pub(super) fn snapshot_after_session(name: &str, config: &Config) {
+    if !config.snapshots.on_exit {
+        return;
+    }
+
+    match snapshot(name, &config.snapshots, "On exit") {
+        Ok(Some(snapshot)) => eprintln!("Saved snapshot {} of jail '{}'", snapshot.id, name),
+        Ok(None) => {}
+        Err(e) => eprintln!("warning: failed to snapshot jail '{name}': {e}"),
+    }
+}
+
+/// Take a snapshot unless nothing changed since the last one, then prune
+fn snapshot(name: &str, settings: &SnapshotSettings, message: &str) -> Result<Option<Checkpoint>> {
+    // Loaded afresh each time, since the session's own state copy is stale
+    let jail = State::load()?.get_jail(name)?.clone();
+    if jail.mode == JailMode::Overlay {
+        return Ok(None);
+    }
+
+    // Git on the host would run the changed hooks or config
+    if tamper::changed(&jail) {
+        return Err(Error::GitCommand(
+            "the repository's hooks or config changed; not running git on the host".to_string(),
+        ));
+    }
+
+    let saved = save(&jail, Series::Snapshots, message, false, true)?;
+    State::update(|state| prune(state, name, settings))?;
+    Ok(saved)
+}
+
+/// Drop snapshots beyond the configured count or age, and their refs
+fn prune(state: &mut State, name: &str, settings: &SnapshotSettings) -> Result<()> {
+    let jail = state.get_jail(name)?;
+    let snapshots = jail.saved(Series::Snapshots);
+
+    let excess = settings.keep.map_or(0, |keep| snapshots.len().saturating_sub(keep));
+    let cutoff = settings.max_age_hours.map(|hours| Utc::now() - Duration::hours(hours as i64));
+    let expired: Vec<&Checkpoint> = snapshots
+        .iter()
+        .enumerate()
+        .filter(|(i, snapshot)| *i < excess || cutoff.is_some_and(|cutoff| snapshot.created_at < cutoff))
+        .map(|(_, snapshot)| snapshot)
+        .collect();
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    for snapshot in &expired {
+        git(&jail.repo_path, &["update-ref", "-d", &Series::Snapshots.git_ref(name, snapshot.id)])?;
+    }
+    let ids: Vec<u32> = expired.iter().map(|snapshot| snapshot.id).collect();
+    state.remove_saved(name, Series::Snapshots, &ids)
+}
+
+/// List a jail's snapshots, or diff two of them
+pub fn history(name: &str, json: bool, diff: Option<(u32, u32)>) -> Result<()> {
+    let state = State::load()?;
+    let jail = state.get_jail(name)?;
+    let snapshots = jail.saved(Series::Snapshots);
+
+    if let Some((from, to)) = diff {
+        let commit = |id: u32| {
+            snapshots
+                .iter()
+                .find(|snapshot| snapshot.id == id)
+                .map(|snapshot| snapshot.commit.as_str())
+                .ok_or_else(|| Error::Config(format!("jail '{name}' has no snapshot {id}")))
+        };
+        // No external diff drivers: the repository's config may come from the jail
+        let output = git(&jail.repo_path, &["diff", "--no-ext-diff", "--no-textconv", commit(from)?, commit(to)?])?;
+        if !output.is_empty() {
+            println!("{output}");
+        }
+        return Ok(());
+    }
+
+    let entries: Vec<SnapshotEntry> = snapshots
+        .iter()
+        .enumerate()
+        .map(|(i, snapshot)| {
+            let previous = match i {
+                0 => format!("{}^", snapshot.commit),
+                _ => snapshots[i - 1].commit.clone(),
+            };
+            SnapshotEntry {
+                id: snapshot.id,
+                message: snapshot.message.clone(),
+                created: snapshot.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                commit: snapshot.commit.clone(),
+                changes: git(&jail.repo_path, &["diff", "--shortstat", &previous, &snapshot.commit]).unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No snapshots. Set [snapshots] interval_minutes or on_exit in the config to take them.");
+        return Ok(());
+    }
+
+    println!("{:<4} {:<20} {:<45} MESSAGE", "ID", "CREATED", "CHANGES SINCE PREVIOUS");
+    println!("{}", "-".repeat(100));
+    for entry in entries {
+        let changes = if entry.changes.is_empty() { "none".to_string() } else { entry.changes };
+        println!("{:<4} {:<20} {:<45} {}", entry.id, entry.created, changes, entry.message);
+    }
+    println!("\nCompare two snapshots with: robojail history {} --diff <FROM> <TO>", name);
+
+    Ok(())
+}
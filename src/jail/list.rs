@@ -1,5 +1,6 @@
+use crate::cgroup::{format_size, Cgroup, ResourceUsage};
 use crate::error::Result;
-use crate::state::State;
+use crate::state::{Busy, JailInfo, JailMode, Session, State};
 use serde::Serialize;
 
 #[derive(Serialize)]
@@ -7,10 +8,17 @@ struct JailListEntry {
     name: String,
     repo: String,
     branch: String,
+    mode: JailMode,
     #[serde(skip_serializing_if = "Option::is_none")]
     entrypoint: Option<String>,
     created: String,
     status: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sessions: Vec<Session>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    landlock_abi: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<ResourceUsage>,
 }
 
 /// List all jails
@@ -31,18 +39,17 @@ pub fn list(json: bool) -> Result<()> {
         let entries: Vec<JailListEntry> = jails
             .iter()
             .map(|j| {
-                let status = match j.pid {
-                    Some(pid) if State::is_pid_alive(pid) => "running".to_string(),
-                    _ => "stopped".to_string(),
-                };
-
                 JailListEntry {
                     name: j.name.clone(),
                     repo: j.repo_path.display().to_string(),
                     branch: j.branch_name.clone(),
+                    mode: j.mode,
                     entrypoint: j.entrypoint.as_ref().map(|ep| ep.join(" ")),
                     created: j.created_at.format("%Y-%m-%d %H:%M:%S").to_string(),
-                    status,
+                    status: status(j),
+                    sessions: j.live_sessions().into_iter().cloned().collect(),
+                    landlock_abi: j.landlock_abi,
+                    usage: jail_usage(&j.name),
                 }
             })
             .collect();
@@ -51,27 +58,22 @@ pub fn list(json: bool) -> Result<()> {
     } else {
         // Table format
         println!(
-            "{:<20} {:<30} {:<25} {:<20} {:<10}",
-            "NAME", "REPO", "BRANCH", "CREATED", "STATUS"
+            "{:<20} {:<30} {:<25} {:<20} {:<12} {:<8} {:<8} {:<8} {:<9} {:<6} {:<8}",
+            "NAME", "REPO", "BRANCH", "CREATED", "STATUS", "LANDLOCK", "MEM", "PEAK", "CPU", "PIDS", "IO"
         );
-        println!("{}", "-".repeat(105));
+        println!("{}", "-".repeat(160));
 
         for jail in jails {
-            let status = match jail.pid {
-                Some(pid) if State::is_pid_alive(pid) => "running",
-                _ => "stopped",
-            };
-
             let repo_display = jail
                 .repo_path
                 .file_name()
                 .map(|s| s.to_string_lossy().to_string())
                 .unwrap_or_else(|| jail.repo_path.display().to_string());
 
-            let branch_display = jail
-                .branch_name
-                .strip_prefix("robojail/")
-                .unwrap_or(&jail.branch_name);
+            let branch_display = match jail.mode {
+                JailMode::Worktree => jail.branch_name.strip_prefix("robojail/").unwrap_or(&jail.branch_name),
+                JailMode::Overlay => "(overlay)",
+            };
 
             // Show entrypoint indicator if set
             let name_display = if let Some(ref ep) = jail.entrypoint {
@@ -84,13 +86,39 @@ pub fn list(json: bool) -> Result<()> {
                 jail.name.clone()
             };
 
+            let landlock_display = jail
+                .landlock_abi
+                .map(|abi| format!("v{}", abi))
+                .unwrap_or_else(|| "-".to_string());
+
+            let usage = jail_usage(&jail.name).unwrap_or_default();
+            let size = |bytes: Option<u64>| bytes.map(format_size).unwrap_or_else(|| "-".to_string());
+            let cpu_display = usage
+                .cpu_usec
+                .map(|usec| format!("{:.1}s", usec as f64 / 1_000_000.0))
+                .unwrap_or_else(|| "-".to_string());
+            let pids_display = usage
+                .pids
+                .map(|pids| pids.to_string())
+                .unwrap_or_else(|| "-".to_string());
+            let io_display = match (usage.io_read_bytes, usage.io_write_bytes) {
+                (Some(read), Some(write)) => format_size(read + write),
+                _ => "-".to_string(),
+            };
+
             println!(
-                "{:<20} {:<30} {:<25} {:<20} {:<10}",
+                "{:<20} {:<30} {:<25} {:<20} {:<12} {:<8} {:<8} {:<8} {:<9} {:<6} {:<8}",
                 truncate(&name_display, 19),
                 truncate(&repo_display, 29),
                 truncate(branch_display, 24),
                 jail.created_at.format("%Y-%m-%d %H:%M"),
-                status
+                status(jail),
+                landlock_display,
+                size(usage.memory_bytes),
+                size(usage.memory_peak_bytes),
+                cpu_display,
+                pids_display,
+                io_display
             );
         }
     }
@@ -98,6 +126,26 @@ pub fn list(json: bool) -> Result<()> {
     Ok(())
 }
 
+/// Whether a jail runs detached or in the foreground, with the number of
+/// sessions if there are several
+fn status(jail: &JailInfo) -> String {
+    let count = jail.live_sessions().len();
+    match count {
+        _ if jail.creating => "creating".to_string(),
+        _ if matches!(jail.busy, Some(Busy::Renaming(_))) => "renaming".to_string(),
+        _ if jail.busy == Some(Busy::Destroying) => "destroying".to_string(),
+        _ if jail.detached_session().is_some() => "detached".to_string(),
+        0 => "stopped".to_string(),
+        1 => "running".to_string(),
+        _ => format!("running ({count})"),
+    }
+}
+
+/// Live cgroup usage of a jail, if it is running in one
+fn jail_usage(name: &str) -> Option<ResourceUsage> {
+    Cgroup::for_jail(name).ok().and_then(|cgroup| cgroup.usage())
+}
+
 fn truncate(s: &str, max: usize) -> String {
     if s.len() <= max {
         s.to_string()
@@ -0,0 +1,228 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::logs::{self, Log, Stream};
+use crate::state::State;
+use chrono::{DateTime, Utc};
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{dup2, fork, isatty, pipe, ForkResult};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Run a session, logging when it started and how it ended
+///
+/// Output going to a terminal is left alone (recordings cover it); output
+/// going anywhere else is passed on through a pipe and copied to the log.
+pub(super) fn logged(name: &str, config: &Config, command: &str, session: impl FnOnce() -> Result<i32>) -> Result<i32> {
+    let log = match Log::open(name, &config.logs) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("warning: failed to open the jail's log: {e}");
+            return session();
+        }
+    };
+
+    log.note(&format!("started: {command}"));
+    let start = Instant::now();
+
+    let redirected: Vec<(RawFd, Stream)> = [(1, Stream::Out), (2, Stream::Err)]
+        .into_iter()
+        .filter(|(fd, _)| !isatty(*fd).unwrap_or(false))
+        .collect();
+    let code = match redirected.is_empty() {
+        true => session(),
+        false => tee(&log, &redirected, session),
+    };
+
+    let elapsed = start.elapsed().as_secs_f64();
+    match &code {
+        Ok(code) => log.note(&format!("exited with code {code} after {elapsed:.1}s")),
+        Err(e) => log.note(&format!("failed after {elapsed:.1}s: {e}")),
+    }
+    code
+}
+
+/// Run the session with the given descriptors on pipes, copied to where they
+/// pointed and to the log
+fn tee(log: &Log, streams: &[(RawFd, Stream)], session: impl FnOnce() -> Result<i32>) -> Result<i32> {
+    let pipes = streams.iter().map(|_| pipe()).collect::<nix::Result<Vec<(OwnedFd, OwnedFd)>>>()?;
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => {
+            let readers: Vec<(File, RawFd, Stream)> = pipes
+                .into_iter()
+                .zip(streams)
+                .map(|((read, _), &(fd, stream))| (File::from(read), fd, stream))
+                .collect();
+
+            // Like the sandbox, leave keyboard interrupts to the session
+            let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+            let old_int = unsafe { sigaction(Signal::SIGINT, &ignore) }?;
+            let old_quit = unsafe { sigaction(Signal::SIGQUIT, &ignore) }?;
+
+            copy(log, readers);
+            let code = match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, code)) => code,
+                Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+                _ => 1,
+            };
+
+            unsafe {
+                sigaction(Signal::SIGINT, &old_int)?;
+                sigaction(Signal::SIGQUIT, &old_quit)?;
+            }
+            log.flush();
+            Ok(code)
+        }
+        Ok(ForkResult::Child) => {
+            let code = pipes
+                .into_iter()
+                .zip(streams)
+                .try_for_each(|((_, write), &(fd, _))| dup2(write.as_raw_fd(), fd).map(|_| ()))
+                .map_err(Error::from)
+                .and_then(|_| session());
+            match code {
+                Ok(code) => std::process::exit(code),
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => Err(Error::Nix(e)),
+    }
+}
+
+/// Copy the pipes to their descriptors and the log until they close
+///
+/// A pipe whose descriptor can't be written to any more is closed, so the
+/// session gets the broken pipe it would have got without us.
+fn copy(log: &Log, mut readers: Vec<(File, RawFd, Stream)>) {
+    let mut buf = [0u8; 8192];
+    while !readers.is_empty() {
+        let mut fds: Vec<PollFd> = readers.iter().map(|(file, _, _)| PollFd::new(file.as_fd(), PollFlags::POLLIN)).collect();
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) | Err(nix::Error::EINTR) => {}
+            Err(_) => return,
+        }
+        let ready: Vec<bool> = fds.iter().map(|fd| fd.revents().is_some_and(|events| !events.is_empty())).collect();
+
+        let mut index = 0;
+        readers.retain_mut(|(file, fd, stream)| {
+            let is_ready = ready[index];
+            index += 1;
+            if !is_ready {
+                return true;
+            }
+            match file.read(&mut buf) {
+                Ok(0) | Err(_) => false,
+                Ok(n) => {
+                    log.write(*stream, &buf[..n]);
+                    let target = unsafe { BorrowedFd::borrow_raw(*fd) };
+                    write_all(target, &buf[..n]).is_ok()
+                }
+            }
+        });
+    }
+}
+
+fn write_all(fd: BorrowedFd, mut data: &[u8]) -> nix::Result<()> {
+    while !data.is_empty() {
+        match nix::unistd::write(fd, data) {
+            Ok(n) => data = &data[n..],
+            Err(nix::Error::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Print a jail's logs, optionally following them as they grow
+pub fn logs(name: &str, follow: bool, since: Option<&str>, tail: Option<usize>) -> Result<()> {
+    State::load()?.get_jail(name)?;
+    let since = since.map(|since| logs::parse_since(since, Utc::now())).transpose()?;
+
+    let mut lines = vec![];
+    for path in logs::files(name)? {
+        let file = File::open(&path)?;
+        lines.extend(BufReader::new(file).lines().map_while(|line| line.ok()).filter(|line| after(line, since)));
+    }
+    if let Some(tail) = tail {
+        lines.drain(..lines.len().saturating_sub(tail));
+    }
+
+    let printed = print_lines(&lines).and_then(|_| match follow {
+        true => follow_log(&logs::path(name)?),
+        false => Ok(()),
+    });
+    match printed {
+        // Such as when piped into head
+        Err(Error::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        Err(e) => Err(e),
+        Ok(()) if lines.is_empty() && since.is_none() => {
+            eprintln!("No logs yet for jail '{}'", name);
+            Ok(())
+        }
+        Ok(()) => Ok(()),
+    }
+}
+
+fn print_lines(lines: &[String]) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    for line in lines {
+        writeln!(stdout, "{line}")?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Whether a line is from `since` on; lines without a time are kept
+fn after(line: &str, since: Option<DateTime<Utc>>) -> bool {
+    match (since, logs::line_time(line)) {
+        (Some(since), Some(time)) => time >= since,
+        _ => true,
+    }
+}
+
+/// Print what is appended to the log from now on, until interrupted
+///
+/// Like `tail -F`: once the log is rotated, the rest of the old file is
+/// printed before moving on to the new one.
+fn follow_log(path: &Path) -> Result<()> {
+    let mut file = File::open(path).ok();
+    if let Some(ref mut file) = file {
+        file.seek(SeekFrom::End(0))?;
+    }
+    let mut pending = vec![];
+    let mut stdout = io::stdout();
+
+    loop {
+        if let Some(ref mut current) = file {
+            current.read_to_end(&mut pending)?;
+
+            // Only whole lines; the rest is still being written
+            if let Some(end) = pending.iter().rposition(|&b| b == b'\n') {
+                stdout.write_all(&pending[..=end])?;
+                stdout.flush()?;
+                pending.drain(..=end);
+            }
+        }
+
+        let replaced = match (&file, std::fs::metadata(path)) {
+            (Some(current), Ok(meta)) => current.metadata()?.ino() != meta.ino(),
+            (None, Ok(_)) => true,
+            (_, Err(_)) => false,
+        };
+        if replaced {
+            file = File::open(path).ok();
+            pending.clear();
+        } else {
+            std::thread::sleep(Duration::from_millis(250));
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use super::repo::{check_branch_access, commit_pending, conflict_error, conflicted_files, git};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::state::{JailInfo, State};
+use std::path::{Path, PathBuf};
+
+/// How a jail's branch is brought into the target branch
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MergeStrategy {
+    /// A merge commit, even when a fast-forward is possible
+    #[default]
+    Merge,
+    /// Rebase the jail's commits onto the target, then fast-forward it
+    Rebase,
+    /// A single commit with all of the jail's changes
+    Squash,
+}
+
+impl std::fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MergeStrategy::Merge => write!(f, "merge"),
+            MergeStrategy::Rebase => write!(f, "rebase"),
+            MergeStrategy::Squash => write!(f, "squash"),
+        }
+    }
+}
+
+/// Options for merging a jail
+#[derive(Debug)]
+pub struct MergeOptions<'a> {
+    /// Name of the jail
+    pub name: &'a str,
+    /// Branch to merge into (defaults to the branch checked out in the repository)
+    pub into: Option<&'a str>,
+    pub strategy: MergeStrategy,
+    /// Message for the commit of pending changes (and the squash commit)
+    pub message: Option<&'a str>,
+    /// Shell command that must succeed inside the jail before merging
+    pub verify: Option<&'a str>,
+    /// Destroy the jail after a successful merge
+    pub destroy: bool,
+    /// Merge even if the repository's hooks or config changed
+    pub force: bool,
+}
+
+/// Commit a jail's pending changes and merge its branch into a target branch
+pub fn merge(opts: MergeOptions, config: &Config) -> Result<()> {
+    let MergeOptions { name, into, strategy, message, verify, destroy, force } = opts;
+
+    let state = State::load()?;
+    let jail = state.get_jail(name)?.clone();
+
+    if jail.is_running() {
+        return Err(Error::JailRunning(name.to_string()));
+    }
+    check_branch_access(&jail, force)?;
+
+    let target = match into {
+        Some(branch) => branch.to_string(),
+        None => git(&jail.repo_path, &["symbolic-ref", "--short", "HEAD"])
+            .map_err(|_| Error::Config("the repository has no branch checked out; use --into".to_string()))?,
+    };
+    git(&jail.repo_path, &["rev-parse", "--verify", "--quiet", &format!("refs/heads/{target}")])
+        .map_err(|_| Error::Config(format!("no branch named '{target}' in {}", jail.repo_path.display())))?;
+    if target == jail.branch_name {
+        return Err(Error::Config("cannot merge a jail's branch into itself".to_string()));
+    }
+
+    let generated = format!("Changes from jail '{}'", jail.name);
+    if commit_pending(&jail, message.unwrap_or(&generated))? {
+        println!("Committed pending changes in jail '{}'", name);
+    }
+
+    let ahead = git(&jail.repo_path, &["rev-list", "--count", &format!("{target}..{}", jail.branch_name)])?;
+    if ahead == "0" {
+        println!("Nothing to merge: '{}' has no commits that aren't on '{}'", jail.branch_name, target);
+    } else {
+        if let Some(command) = verify {
+            println!("Verifying with: {}", command);
+            let argv = ["/bin/sh".to_string(), "-c".to_string(), command.to_string()];
+            let code = super::run(name, &argv, config)?;
+            if code != 0 {
+                return Err(Error::Config(format!(
+                    "verification command exited with status {code}; nothing was merged"
+                )));
+            }
+        }
+
+        let checkout = TargetCheckout::open(&jail, &target)?;
+        let result = checkout.apply(&jail, &target, strategy, message);
+        checkout.close(&jail.repo_path);
+        let summary = result?;
+
+        println!("Merged '{}' into '{}' ({}, {} commit(s))", jail.branch_name, target, strategy, ahead);
+        if !summary.is_empty() {
+            println!("{}", summary);
+        }
+    }
+
+    if destroy {
+        super::destroy(name, false)?;
+    }
+
+    Ok(())
+}
+
+/// Where the target branch is checked out for the merge
+struct TargetCheckout {
+    path: PathBuf,
+    /// Created for this merge because the branch wasn't checked out anywhere
+    temporary: bool,
+}
+
+impl TargetCheckout {
+    /// Use the worktree that has `target` checked out, or add a temporary one
+    fn open(jail: &JailInfo, target: &str) -> Result<Self> {
+        let listing = git(&jail.repo_path, &["worktree", "list", "--porcelain"])?;
+        let mut path = None;
+        for line in listing.lines() {
+            if let Some(worktree) = line.strip_prefix("worktree ") {
+                path = Some(PathBuf::from(worktree));
+            } else if line.strip_prefix("branch refs/heads/") == Some(target) {
+                let path = path.take().unwrap_or_else(|| jail.repo_path.clone());
+                if !git(&path, &["status", "--porcelain", "--untracked-files=no"])?.is_empty() {
+                    return Err(Error::GitCommand(format!(
+                        "'{target}' is checked out at {} with uncommitted changes; commit or stash them first",
+                        path.display()
+                    )));
+                }
+                return Ok(Self { path, temporary: false });
+            }
+        }
+
+        let path = Config::jails_dir()?.join(format!(".merge-{}", jail.name));
+        let path_str = path.to_string_lossy().to_string();
+        git(&jail.repo_path, &["worktree", "add", "--quiet", &path_str, target])?;
+        Ok(Self { path, temporary: true })
+    }
+
+    /// Bring the jail's branch into the target; returns a diffstat summary
+    ///
+    /// On conflicts everything is rolled back and the conflicting files are
+    /// reported in the error.
+    fn apply(&self, jail: &JailInfo, target: &str, strategy: MergeStrategy, message: Option<&str>) -> Result<String> {
+        let branch = jail.branch_name.as_str();
+        let before = git(&self.path, &["rev-parse", "HEAD"])?;
+
+        match strategy {
+            MergeStrategy::Merge => {
+                let message = format!("Merge jail '{}' ({})", jail.name, branch);
+                if let Err(e) = git(&self.path, &["merge", "--no-ff", "-m", &message, branch]) {
+                    return Err(rollback(&self.path, &["merge", "--abort"], branch, target, e));
+                }
+            }
+            MergeStrategy::Squash => {
+                if let Err(e) = git(&self.path, &["merge", "--squash", branch]) {
+                    return Err(rollback(&self.path, &["reset", "--merge"], branch, target, e));
+                }
+                // The squash message lists the jail's commits unless one was given
+                match message {
+                    Some(message) => git(&self.path, &["commit", "--quiet", "-m", message])?,
+                    None => git(&self.path, &["commit", "--quiet", "--no-edit"])?,
+                };
+            }
+            MergeStrategy::Rebase => {
+                if let Err(e) = git(&jail.worktree_path, &["rebase", target]) {
+                    return Err(rollback(&jail.worktree_path, &["rebase", "--abort"], branch, target, e));
+                }
+                git(&self.path, &["merge", "--ff-only", branch])?;
+            }
+        }
+
+        git(&self.path, &["diff", "--shortstat", &before, "HEAD"])
+    }
+
+    fn close(self, repo: &Path) {
+        if self.temporary {
+            let path = self.path.to_string_lossy().to_string();
+            if let Err(e) = git(repo, &["worktree", "remove", "--force", &path]) {
+                eprintln!("warning: failed to remove temporary worktree {path}: {e}");
+            }
+        }
+    }
+}
+
+/// Collect the conflicting files, undo the operation and build the error
+fn rollback(dir: &Path, undo: &[&str], branch: &str, target: &str, cause: Error) -> Error {
+    let conflicts = conflicted_files(dir);
+    let _ = git(dir, undo);
+
+    if conflicts.is_empty() {
+        return cause;
+    }
+    conflict_error(branch, target, &conflicts)
+}
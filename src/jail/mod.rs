@@ -1,13 +1,36 @@
+mod checkpoint;
+mod clone;
 mod create;
 mod destroy;
+mod detach;
 mod enter;
+mod export;
+mod history;
 mod list;
+mod logs;
+mod merge;
+mod port;
+mod rename;
+mod repo;
 mod run;
+mod sessions;
 mod status;
+mod sync;
 
-pub use create::create;
+pub use checkpoint::{checkpoint, checkpoints, rollback};
+pub use clone::clone;
+pub use create::{create, CreateOptions};
 pub use destroy::destroy;
+pub use detach::{attach, detach};
 pub use enter::enter;
+pub use export::{export, ExportFormat, ExportOptions};
+pub use history::history;
 pub use list::list;
+pub use logs::logs;
+pub use merge::{merge, MergeOptions, MergeStrategy};
+pub use port::{port_add, port_list, port_remove};
+pub use rename::rename;
 pub use run::run;
+pub use sessions::{replay, sessions};
 pub use status::status;
+pub use sync::sync;
@@ -0,0 +1,78 @@
+use crate::error::{Error, Result};
+use crate::sandbox::{check_unique_ports, ports_socket, update_running_ports, PortMapping};
+use crate::state::State;
+
+/// Publish jail ports on the host
+pub fn port_add(name: &str, mappings: &[PortMapping]) -> Result<()> {
+    check_unique_ports(mappings)?;
+
+    let ports = State::update(|state| {
+        let mut ports = state.get_jail(name)?.ports.clone();
+
+        // A host port maps to one jail port; re-adding it changes the target
+        ports.retain(|port| !mappings.iter().any(|m| m.host == port.host));
+        ports.extend_from_slice(mappings);
+        state.set_ports(name, ports.clone())?;
+        Ok(ports)
+    })?;
+
+    for mapping in mappings {
+        println!("Published 127.0.0.1:{} -> jail port {}", mapping.host, mapping.jail);
+    }
+    apply_to_running(name, &ports)
+}
+
+/// Stop publishing host ports
+pub fn port_remove(name: &str, host_ports: &[u16]) -> Result<()> {
+    let ports = State::update(|state| {
+        let mut ports = state.get_jail(name)?.ports.clone();
+
+        for host in host_ports {
+            if !ports.iter().any(|port| port.host == *host) {
+                return Err(Error::Config(format!("jail '{name}' does not publish port {host}")));
+            }
+        }
+
+        ports.retain(|port| !host_ports.contains(&port.host));
+        state.set_ports(name, ports.clone())?;
+        Ok(ports)
+    })?;
+
+    for host in host_ports {
+        println!("Unpublished port {host}");
+    }
+    apply_to_running(name, &ports)
+}
+
+/// List the ports a jail publishes
+pub fn port_list(name: &str, json: bool) -> Result<()> {
+    let state = State::load()?;
+    let jail = state.get_jail(name)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&jail.ports)?);
+    } else if jail.ports.is_empty() {
+        println!("Jail '{}' publishes no ports", name);
+    } else {
+        println!("{:<20} {:<10}", "HOST", "JAIL");
+        for mapping in &jail.ports {
+            println!("{:<20} {:<10}", format!("127.0.0.1:{}", mapping.host), mapping.jail);
+        }
+    }
+
+    Ok(())
+}
+
+/// Hand the new mappings to the jail's session, if it is running
+fn apply_to_running(name: &str, ports: &[PortMapping]) -> Result<()> {
+    match update_running_ports(&ports_socket(name)?, ports)? {
+        Some(problems) => {
+            for problem in problems {
+                eprintln!("warning: {problem}");
+            }
+        }
+        None => println!("Changes take effect the next time the jail is started"),
+    }
+
+    Ok(())
+}
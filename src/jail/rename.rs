@@ -0,0 +1,140 @@
+use super::repo::{git, jail_branch, move_jail_refs};
+use crate::cgroup::Cgroup;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::state::{JailInfo, JailMode, State};
+use crate::tamper;
+use crate::validation::validate_jail_name;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Rename a jail, moving its directory and optionally its branch
+///
+/// The new name is reserved first, which also keeps sessions from starting
+/// while the files move. The state only switches to the new name once
+/// everything on disk has moved, and the moves are undone if that fails,
+/// so an interrupted rename leaves the jail under its old name.
+pub fn rename(old: &str, new: &str, rename_branch: bool) -> Result<()> {
+    validate_jail_name(new)?;
+
+    let jail = State::update(|state| {
+        let jail = state.begin_rename(old, new)?;
+        if Cgroup::for_jail(old).ok().and_then(|cgroup| cgroup.usage()).is_some() {
+            return Err(Error::JailRunning(old.to_string()));
+        }
+        Ok(jail)
+    })?;
+
+    let new_path = Config::jails_dir()?.join(new);
+    let new_branch = match jail.mode {
+        JailMode::Worktree if rename_branch => {
+            // Keep the branch's unique id if it has the usual form
+            let id = jail
+                .branch_name
+                .strip_prefix(&format!("robojail/{old}-"))
+                .map(|rest| rest.trim_end_matches("/work").to_string())
+                .unwrap_or_else(|| Uuid::new_v4().to_string()[..8].to_string());
+            jail_branch(new, &id)
+        }
+        _ => jail.branch_name.clone(),
+    };
+
+    let renamed = move_files(&jail, new, &new_path, &new_branch).and_then(|()| {
+        State::update(|state| state.finish_rename(old, new_path.clone(), new_branch.clone()))
+            .inspect_err(|_| restore_files(&jail, new, &new_path, &new_branch))
+    });
+    if let Err(e) = renamed {
+        if let Err(e) = State::update(|state| state.abandon_rename(old)) {
+            eprintln!("warning: failed to clear the jail's rename: {e}");
+        }
+        return Err(e);
+    }
+
+    // Sockets and cgroups are recreated by the next session
+    let _ = std::fs::remove_dir_all(Config::jail_runtime_dir(old)?);
+    if let Ok(cgroup) = Cgroup::for_jail(old) {
+        cgroup.remove();
+    }
+
+    println!("Renamed jail '{}' to '{}'", old, new);
+    if new_branch != jail.branch_name {
+        println!("Branch: {}", new_branch);
+    }
+
+    Ok(())
+}
+
+/// Move a jail's directory, branch, refs and logs to its new name
+///
+/// Undoes what it moved if the directory or branch can't be moved.
+fn move_files(jail: &JailInfo, new: &str, new_path: &Path, new_branch: &str) -> Result<()> {
+    if new_path.exists() {
+        return Err(Error::Config(format!("{} already exists", new_path.display())));
+    }
+
+    match jail.mode {
+        JailMode::Worktree => {
+            tamper::warn_if_changed(jail);
+
+            let (old_path, new_path) = (jail.worktree_path.to_string_lossy(), new_path.to_string_lossy());
+            git(&jail.repo_path, &["worktree", "move", &old_path, &new_path])?;
+
+            if new_branch != jail.branch_name {
+                if let Err(e) = git(&jail.repo_path, &["branch", "-m", &jail.branch_name, new_branch]) {
+                    let _ = git(&jail.repo_path, &["worktree", "move", &new_path, &old_path]);
+                    return Err(e);
+                }
+            }
+        }
+        JailMode::Overlay => std::fs::rename(&jail.worktree_path, new_path)?,
+    }
+
+    if let Err(e) = move_jail_refs(&jail.repo_path, &jail.name, new) {
+        eprintln!("warning: failed to move the jail's checkpoint refs: {e}");
+    }
+
+    // Logs follow the jail
+    let (old_logs, new_logs) = (Config::jail_state_dir(&jail.name)?, Config::jail_state_dir(new)?);
+    if old_logs.exists() {
+        if let Err(e) = std::fs::rename(&old_logs, &new_logs) {
+            eprintln!("warning: failed to move {}: {e}", old_logs.display());
+        }
+    }
+
+    Ok(())
+}
+
+/// Move everything [`move_files`] moved back to the jail's old name
+fn restore_files(jail: &JailInfo, new: &str, new_path: &Path, new_branch: &str) {
+    let warn = |what: &str, e: &dyn std::fmt::Display| eprintln!("warning: failed to move back the jail's {what}: {e}");
+
+    if let (Ok(old_logs), Ok(new_logs)) = (Config::jail_state_dir(&jail.name), Config::jail_state_dir(new)) {
+        if new_logs.exists() {
+            if let Err(e) = std::fs::rename(&new_logs, &old_logs) {
+                warn("logs", &e);
+            }
+        }
+    }
+    if let Err(e) = move_jail_refs(&jail.repo_path, new, &jail.name) {
+        warn("checkpoint refs", &e);
+    }
+
+    match jail.mode {
+        JailMode::Worktree => {
+            if new_branch != jail.branch_name {
+                if let Err(e) = git(&jail.repo_path, &["branch", "-m", new_branch, &jail.branch_name]) {
+                    warn("branch", &e);
+                }
+            }
+            let (old_path, new_path) = (jail.worktree_path.to_string_lossy(), new_path.to_string_lossy());
+            if let Err(e) = git(&jail.repo_path, &["worktree", "move", &new_path, &old_path]) {
+                warn("worktree", &e);
+            }
+        }
+        JailMode::Overlay => {
+            if let Err(e) = std::fs::rename(new_path, &jail.worktree_path) {
+                warn("directory", &e);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Host-side git helpers for commands that work with a jail's branch
+
+use crate::error::{Error, Result};
+use crate::state::{JailInfo, JailMode};
+use crate::tamper;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Run git in `dir` and return its trimmed stdout
+pub(super) fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    git_env(dir, &[], args)
+}
+
+/// Run git in `dir` with extra environment variables
+pub(super) fn git_env(dir: &Path, env: &[(&str, &Path)], args: &[&str]) -> Result<String> {
+    let output = Command::new("git").arg("-C").arg(dir).envs(env.iter().copied()).args(args).output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let detail = if stderr.trim().is_empty() { stdout } else { stderr };
+        return Err(Error::GitCommand(format!("git {}: {}", args.join(" "), detail.trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Name of a jail's branch
+///
+/// Each branch sits in a directory of its own, which is the only part of
+/// `refs/heads` its jail can write to.
+pub(super) fn jail_branch(name: &str, id: &str) -> String {
+    format!("robojail/{name}-{id}/work")
+}
+
+/// Remove what was created on disk for a jail that failed to come up
+///
+/// Only for jails whose directory, worktree and branch the caller created.
+pub(super) fn discard_jail(info: &JailInfo) {
+    if info.mode == JailMode::Worktree {
+        let path = info.worktree_path.to_string_lossy();
+        let _ = git(&info.repo_path, &["worktree", "remove", "--force", &path]);
+        let _ = git(&info.repo_path, &["branch", "-D", &info.branch_name]);
+    }
+    let _ = fs::remove_dir_all(&info.worktree_path);
+}
+
+/// Check that host-side git can safely work with the jail's branch
+///
+/// The jail needs to be a worktree jail whose worktree is still on its own
+/// branch (the jail can move HEAD, and committing on the host must not land
+/// anywhere else), and the repository's hooks and config must be unchanged,
+/// since git on the host runs them.
+pub(super) fn check_branch_access(jail: &JailInfo, force: bool) -> Result<()> {
+    if jail.mode == JailMode::Overlay {
+        return Err(Error::Config(format!("jail '{}' is an overlay jail and has no branch", jail.name)));
+    }
+
+    if tamper::warn_if_changed(jail) && !force {
+        return Err(Error::GitCommand(
+            "refusing to run git in a repository whose hooks or config changed (use --force once reviewed)"
+                .to_string(),
+        ));
+    }
+
+    let head = git(&jail.worktree_path, &["symbolic-ref", "--short", "HEAD"]).unwrap_or_default();
+    if head != jail.branch_name {
+        return Err(Error::GitCommand(format!(
+            "jail '{}' is not on its branch '{}'; check out that branch in the jail first",
+            jail.name, jail.branch_name
+        )));
+    }
+
+    Ok(())
+}
+
+/// The commit a worktree jail started from
+///
+/// Jails created before the base was recorded fall back to where their
+/// branch forked from the repository's HEAD.
+pub(super) fn base_commit(jail: &JailInfo) -> Result<String> {
+    match jail.base_commit {
+        Some(ref commit) => Ok(commit.clone()),
+        None => git(&jail.repo_path, &["merge-base", "HEAD", &jail.branch_name]),
+    }
+}
+
+/// Commit the worktree as it is on top of HEAD, without moving any ref
+///
+/// Untracked files are included, and with `include_ignored` ignored ones
+/// too. A throwaway index is used, so the jail's own index stays as it is.
+pub(super) fn snapshot_commit(jail: &JailInfo, message: &str, include_ignored: bool) -> Result<String> {
+    let worktree = &jail.worktree_path;
+    let index = PathBuf::from(git(worktree, &["rev-parse", "--path-format=absolute", "--git-path", "index"])?);
+    let scratch = index.with_file_name(format!("robojail-index-{}", std::process::id()));
+    fs::copy(&index, &scratch)?;
+
+    let env = [("GIT_INDEX_FILE", scratch.as_path())];
+    let add: &[&str] = if include_ignored { &["add", "--all", "--force"] } else { &["add", "--all"] };
+    let tree = git_env(worktree, &env, add).and_then(|_| git_env(worktree, &env, &["write-tree"]));
+    let _ = fs::remove_file(&scratch);
+
+    git(worktree, &["commit-tree", &tree?, "-p", "HEAD", "-m", message])
+}
+
+/// Delete the refs robojail keeps for a jail (`refs/robojail/<name>/...`)
+pub(super) fn delete_jail_refs(repo: &Path, name: &str) -> Result<()> {
+    let refs = git(repo, &["for-each-ref", "--format=%(refname)", &format!("refs/robojail/{name}/")])?;
+    for name in refs.lines() {
+        git(repo, &["update-ref", "-d", name])?;
+    }
+    Ok(())
+}
+
+/// Move the refs robojail keeps for a jail to a new jail name
+pub(super) fn move_jail_refs(repo: &Path, old: &str, new: &str) -> Result<()> {
+    let prefix = format!("refs/robojail/{old}/");
+    let refs = git(repo, &["for-each-ref", "--format=%(refname) %(objectname)", &prefix])?;
+    for line in refs.lines() {
+        let Some((name, commit)) = line.split_once(' ') else { continue };
+        let renamed = format!("refs/robojail/{new}/{}", &name[prefix.len()..]);
+        git(repo, &["update-ref", &renamed, commit])?;
+        git(repo, &["update-ref", "-d", name, commit])?;
+    }
+    Ok(())
+}
+
+/// Whether the jail's worktree has uncommitted or untracked changes
+pub(super) fn has_pending_changes(jail: &JailInfo) -> Result<bool> {
+    Ok(!git(&jail.worktree_path, &["status", "--porcelain"])?.is_empty())
+}
+
+/// Commit everything pending in the jail's worktree; returns whether there was anything
+pub(super) fn commit_pending(jail: &JailInfo, message: &str) -> Result<bool> {
+    if !has_pending_changes(jail)? {
+        return Ok(false);
+    }
+
+    git(&jail.worktree_path, &["add", "--all"])?;
+    git(&jail.worktree_path, &["commit", "--quiet", "-m", message])?;
+    Ok(true)
+}
+
+/// Files with unresolved conflicts in the checkout at `dir`
+pub(super) fn conflicted_files(dir: &Path) -> Vec<String> {
+    let files = git(dir, &["diff", "--name-only", "--diff-filter=U"]).unwrap_or_default();
+    files.lines().map(str::to_string).collect()
+}
+
+/// The error for `branch` conflicting with `target`
+pub(super) fn conflict_error(branch: &str, target: &str, files: &[String]) -> Error {
+    Error::MergeConflict {
+        branch: branch.to_string(),
+        target: target.to_string(),
+        files: files.iter().map(|file| format!("\n  {file}")).collect(),
+    }
+}
@@ -1,12 +1,18 @@
+use super::checkpoint::checkpoint_before_session;
+use super::history::{periodic_snapshots, snapshot_after_session};
+use super::sessions::session;
 use crate::config::Config;
 use crate::error::Result;
-use crate::sandbox::create_jail_sandbox;
-use crate::state::State;
+use crate::sandbox::jail_sandbox_builder;
+use crate::state::{SessionKind, State};
 
 /// Run a command inside a jail
 pub fn run(name: &str, command: &[String], config: &Config) -> Result<i32> {
+    // Before loading the state, which the checkpoint updates
+    checkpoint_before_session(name, config, &format!("run: {}", command.join(" ")));
+
     let state = State::load()?;
-    let jail = state.get_jail(name)?;
+    let jail = state.get_jail(name)?.clone();
 
     // Check that worktree still exists
     if !jail.worktree_path.exists() {
@@ -16,12 +22,17 @@ pub fn run(name: &str, command: &[String], config: &Config) -> Result<i32> {
         );
     }
 
-    let worktree_path = jail.worktree_path.clone();
-    let entrypoint = jail.entrypoint.clone();
-
     // Create sandbox and run command
-    // We pass entrypoint so it gets bind-mounted even for explicit commands
-    let sandbox = create_jail_sandbox(&worktree_path, config, entrypoint.as_deref());
-    sandbox.run(command)
+    // The jail's entrypoint still gets bind-mounted even for explicit commands
+    let mut builder = jail_sandbox_builder(&jail, config)?;
+    if let Some(periodic) = periodic_snapshots(&jail, config) {
+        builder = builder.every(periodic);
+    }
+    let sandbox = builder.build();
+    let display_cmd = command.join(" ");
+    let exit_code = session(name, config, SessionKind::Run, &display_cmd, || sandbox.run(command))?;
+
+    snapshot_after_session(name, config);
+    Ok(exit_code)
 }
 
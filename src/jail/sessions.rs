@@ -0,0 +1,126 @@
+use super::logs::logged;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::recording::{self, Recorder};
+use crate::session::{self, Pty};
+use crate::state::{Session, SessionKind, State};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, isatty, ttyname, ForkResult};
+use std::path::PathBuf;
+
+/// Run a jail session: tracked in the state, logged, and recorded if the
+/// config asks for it
+pub(super) fn session(
+    name: &str,
+    config: &Config,
+    kind: SessionKind,
+    command: &str,
+    run: impl FnOnce() -> Result<i32>,
+) -> Result<i32> {
+    // The terminal the user started it from, before recording puts another in between
+    let tty = ttyname(std::io::stdin()).ok();
+    logged(name, config, command, || recorded(name, config, command, || tracked(name, kind, command, tty, run)))
+}
+
+/// Run a session, listed in the state while it runs
+///
+/// Called in the process that runs the sandbox, whose death ends the jail.
+fn tracked(name: &str, kind: SessionKind, command: &str, tty: Option<PathBuf>, run: impl FnOnce() -> Result<i32>) -> Result<i32> {
+    let session = Session::current(kind, command, tty);
+    let id = session.id;
+    State::update(|state| state.add_session(name, session))?;
+
+    let code = run();
+
+    // Left behind if we die first, until the next session notices
+    if let Err(e) = State::update(|state| state.remove_session(name, id)) {
+        eprintln!("warning: failed to remove the session from the state: {e}");
+    }
+    code
+}
+
+/// Run a foreground session, recording it if the config asks for it
+///
+/// Only sessions on a terminal are recorded: they get a pseudo-terminal of
+/// their own, relayed to ours. Anything else runs as it is, since putting a
+/// terminal in front of a pipe changes how programs behave.
+fn recorded(name: &str, config: &Config, command: &str, session: impl FnOnce() -> Result<i32>) -> Result<i32> {
+    let size = session::terminal_size(0).filter(|_| isatty(1).unwrap_or(false));
+    let (true, Some(size)) = (config.recording.enabled, size) else {
+        return session();
+    };
+
+    let recorder = Recorder::create(&recording::new_path(name)?, size, command, name, config.recording.input)?;
+    let pty = Pty::open()?;
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { child }) => {
+            let relayed = session::relay(pty.into_master(), &recorder);
+            let code = match waitpid(child, None) {
+                Ok(WaitStatus::Exited(_, code)) => code,
+                Ok(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+                _ => 1,
+            };
+            relayed?;
+            Ok(code)
+        }
+        Ok(ForkResult::Child) => {
+            let code = pty.into_terminal().and_then(|_| session());
+            match code {
+                Ok(code) => std::process::exit(code),
+                Err(e) => {
+                    eprintln!("error: {e}");
+                    std::process::exit(1);
+                }
+            }
+        }
+        Err(e) => Err(Error::Nix(e)),
+    }
+}
+
+/// List a jail's recorded sessions
+pub fn sessions(name: &str, json: bool) -> Result<()> {
+    State::load()?.get_jail(name)?;
+    let recordings = recording::list(name)?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&recordings)?);
+        return Ok(());
+    }
+
+    if recordings.is_empty() {
+        println!("No recorded sessions. Set [recording] enabled = true in the config to record them.");
+        return Ok(());
+    }
+
+    println!("{:<26} {:<20} {:<10} {:<9} COMMAND", "SESSION", "STARTED", "DURATION", "SIZE");
+    println!("{}", "-".repeat(100));
+    for recording in recordings {
+        let duration = recording.duration as u64;
+        println!(
+            "{:<26} {:<20} {:<10} {:<9} {}",
+            recording.id,
+            recording.started.format("%Y-%m-%d %H:%M:%S"),
+            format!("{}:{:02}:{:02}", duration / 3600, duration / 60 % 60, duration % 60),
+            crate::cgroup::format_size(recording.bytes),
+            recording.command.unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+/// Play a recorded session back in the terminal
+pub fn replay(name: &str, id: &str, speed: f64, idle_limit: Option<f64>) -> Result<()> {
+    State::load()?.get_jail(name)?;
+    if speed.is_nan() || speed <= 0.0 {
+        return Err(Error::Config("--speed must be greater than 0".to_string()));
+    }
+
+    let recording = recording::list(name)?
+        .into_iter()
+        .find(|recording| recording.id == id)
+        .ok_or_else(|| Error::Config(format!("jail '{name}' has no recorded session '{id}' (see robojail sessions {name})")))?;
+
+    recording::replay(&recording.path, speed, idle_limit)
+}
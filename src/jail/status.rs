@@ -1,15 +1,135 @@
+use super::repo::{base_commit, git};
+use crate::cgroup::{format_size, Cgroup, ResourceLimits, ResourceUsage};
+use crate::config::{Config, NetworkMode};
 use crate::error::{Error, Result};
-use crate::state::State;
+use crate::sandbox::PortMapping;
+use crate::overlay;
+use crate::state::{JailInfo, JailMode, Session, State};
+use crate::tamper;
 use serde::Serialize;
+use std::path::PathBuf;
 use std::process::Command;
 
 #[derive(Serialize)]
 struct StatusOutput {
     name: String,
+    mode: JailMode,
     modified: Vec<String>,
     added: Vec<String>,
     deleted: Vec<String>,
     stats: DiffStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    landlock_abi: Option<u32>,
+    limits: ResourceLimits,
+    network: NetworkPolicy,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    ports: Vec<PortMapping>,
+    /// Sessions running in the jail
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sessions: Vec<Session>,
+    /// Live cgroup usage (only while the jail is running)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<ResourceUsage>,
+    /// Whether the repository's hooks or config changed since the jail was created
+    repo_config_changed: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    base: Option<BaseInfo>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    progress: Option<Progress>,
+}
+
+/// The commit a jail started from
+#[derive(Serialize)]
+struct BaseInfo {
+    commit: String,
+    /// Host branch the jail started from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+    /// Commits added to that branch on the host since (None if it's gone)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch_ahead: Option<u32>,
+}
+
+impl BaseInfo {
+    /// One-line summary for the text output
+    fn describe(&self) -> String {
+        let commit = &self.commit[..12.min(self.commit.len())];
+        match (&self.branch, self.branch_ahead) {
+            (Some(branch), Some(0)) => format!("{commit} on {branch} (up to date)"),
+            (Some(branch), Some(ahead)) => format!("{commit} on {branch} ({branch} has moved {ahead} commit(s) ahead since)"),
+            (Some(branch), None) => format!("{commit} on {branch} ({branch} no longer exists)"),
+            (None, _) => commit.to_string(),
+        }
+    }
+}
+
+/// Work in a worktree jail on top of its base commit
+#[derive(Serialize)]
+struct Progress {
+    /// Commits made since the base, newest first
+    commits: Vec<CommitSummary>,
+    /// Index changes against HEAD, as "<status> <path>"
+    staged: Vec<String>,
+    /// Working tree changes against the index, as "<status> <path>"
+    unstaged: Vec<String>,
+    untracked: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CommitSummary {
+    sha: String,
+    subject: String,
+}
+
+/// Effective network policy of a jail
+#[derive(Serialize)]
+struct NetworkPolicy {
+    mode: NetworkMode,
+    /// Domains reachable through the proxy
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    allow: Vec<String>,
+    /// Ranges blocked in filtered mode
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    blocked: Vec<String>,
+    /// Exceptions to `blocked`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    exceptions: Vec<String>,
+}
+
+impl NetworkPolicy {
+    fn for_jail(jail: &JailInfo, config: &Config) -> Self {
+        let mode = jail.network_mode(config);
+        let mut policy = Self { mode, allow: vec![], blocked: vec![], exceptions: vec![] };
+
+        match mode {
+            NetworkMode::Proxy => policy.allow = jail.network_allowlist(config),
+            NetworkMode::Filtered => {
+                policy.blocked = config.network_blocked.clone();
+                policy.exceptions = jail.network_exceptions(config);
+            }
+            _ => {}
+        }
+
+        policy
+    }
+
+    /// One-line summary for the text output
+    fn describe(&self) -> String {
+        match self.mode {
+            NetworkMode::Host => "host (shared with the host)".to_string(),
+            NetworkMode::None => "none (loopback only)".to_string(),
+            NetworkMode::Slirp => "slirp (userspace networking, host loopback unreachable)".to_string(),
+            NetworkMode::Proxy if self.allow.is_empty() => "proxy, no domains allowed".to_string(),
+            NetworkMode::Proxy => format!("proxy, allowing {}", self.allow.join(", ")),
+            NetworkMode::Filtered => {
+                let mut line = format!("filtered, blocking {}", self.blocked.join(", "));
+                if !self.exceptions.is_empty() {
+                    line.push_str(&format!(" except {}", self.exceptions.join(", ")));
+                }
+                line
+            }
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -20,7 +140,11 @@ struct DiffStats {
 }
 
 /// Show git status of a jail (external supervisor view)
-pub fn status(name: &str, json: bool, show_diff: bool) -> Result<()> {
+///
+/// For worktree jails everything is relative to the commit the jail started
+/// from, so work the jail has committed still shows up. With `since_base`,
+/// the diff is against that commit too rather than the index.
+pub fn status(name: &str, json: bool, show_diff: bool, since_base: bool, config: &Config) -> Result<()> {
     let state = State::load()?;
     let jail = state.get_jail(name)?;
 
@@ -33,83 +157,37 @@ pub fn status(name: &str, json: bool, show_diff: bool) -> Result<()> {
         )));
     }
 
-    // Get git status
-    let status_output = Command::new("git")
-        .args([
-            "-C",
-            jail.worktree_path.to_str().unwrap_or("."),
-            "status",
-            "--porcelain",
-        ])
-        .output()
-        .map_err(|e| Error::GitCommand(format!("failed to run git status: {e}")))?;
-
-    if !status_output.status.success() {
-        let stderr = String::from_utf8_lossy(&status_output.stderr);
-        return Err(Error::GitCommand(format!("git status failed: {stderr}")));
-    }
-
-    let status_str = String::from_utf8_lossy(&status_output.stdout);
+    // Before any git command runs with whatever the config now says
+    let repo_config_changed = tamper::warn_if_changed(jail);
 
-    // Parse status into categories
-    let mut modified = Vec::new();
-    let mut added = Vec::new();
-    let mut deleted = Vec::new();
-
-    for line in status_str.lines() {
-        if line.len() < 3 {
-            continue;
+    let (base, progress) = match jail.mode {
+        JailMode::Worktree => {
+            let base = base_commit(jail)?;
+            let progress = worktree_progress(jail, &base)?;
+            (Some(base), Some(progress))
         }
+        JailMode::Overlay => (jail.base_commit.clone(), None),
+    };
 
-        let status_code = &line[0..2];
-        let file = line[3..].trim();
-
-        match status_code.trim() {
-            "M" | "MM" | "AM" | " M" => modified.push(file.to_string()),
-            "A" | "??" => added.push(file.to_string()),
-            "D" | " D" => deleted.push(file.to_string()),
-            "R" => {
-                // Renamed: old -> new
-                if let Some((_, new)) = file.split_once(" -> ") {
-                    modified.push(new.to_string());
-                } else {
-                    modified.push(file.to_string());
-                }
-            }
-            _ => {
-                // Other status codes - treat as modified
-                if !file.is_empty() {
-                    modified.push(file.to_string());
-                }
-            }
-        }
-    }
-
-    // Get diff stats
-    let diff_stat_output = Command::new("git")
-        .args([
-            "-C",
-            jail.worktree_path.to_str().unwrap_or("."),
-            "diff",
-            "--stat",
-            "--stat-width=1000",
-        ])
-        .output()
-        .ok();
-
-    let (insertions, deletions, files_changed) = if let Some(output) = diff_stat_output {
-        if output.status.success() {
-            parse_diff_stats(&String::from_utf8_lossy(&output.stdout))
-        } else {
-            (0, 0, 0)
+    let ((modified, added, deleted), (insertions, deletions, files_changed)) = match (&base, &progress) {
+        (Some(base), Some(progress)) => (
+            worktree_changes(jail, base, &progress.untracked)?,
+            worktree_stats(jail, base, &progress.untracked),
+        ),
+        _ => {
+            let changes = overlay::changes(&jail.repo_path, &jail.upper_dir())?;
+            let stats = overlay_stats(&overlay_pairs(jail, &changes.modified, &changes.added, &changes.deleted));
+            ((changes.modified, changes.added, changes.deleted), stats)
         }
-    } else {
-        (0, 0, 0)
     };
+    let base = base.map(|base| base_info(jail, &base));
+
+    let usage = Cgroup::for_jail(name).ok().and_then(|cgroup| cgroup.usage());
 
     if json {
         let output = StatusOutput {
             name: name.to_string(),
+            mode: jail.mode,
             modified,
             added,
             deleted,
@@ -118,6 +196,15 @@ pub fn status(name: &str, json: bool, show_diff: bool) -> Result<()> {
                 deletions,
                 files_changed,
             },
+            landlock_abi: jail.landlock_abi,
+            limits: jail.limits.clone(),
+            network: NetworkPolicy::for_jail(jail, config),
+            ports: jail.ports.clone(),
+            sessions: jail.live_sessions().into_iter().cloned().collect(),
+            usage,
+            repo_config_changed,
+            base,
+            progress,
         };
         println!("{}", serde_json::to_string_pretty(&output)?);
     } else {
@@ -154,12 +241,76 @@ pub fn status(name: &str, json: bool, show_diff: bool) -> Result<()> {
             }
         }
 
+        if let Some(ref progress) = progress {
+            print_progress(progress);
+        }
+
+        println!();
+        if jail.mode == JailMode::Overlay {
+            println!("Mode: overlay of {}", jail.repo_path.display());
+        }
+        if let Some(ref base) = base {
+            println!("Base: {}", base.describe());
+        }
+        match jail.landlock_abi {
+            Some(abi) => println!("Landlock: ABI v{} applied on last session", abi),
+            None => println!("Landlock: not applied"),
+        }
+        println!("Network: {}", NetworkPolicy::for_jail(jail, config).describe());
+        if !jail.ports.is_empty() {
+            let ports: Vec<String> = jail
+                .ports
+                .iter()
+                .map(|m| format!("127.0.0.1:{} -> {}", m.host, m.jail))
+                .collect();
+            println!("Ports: {}", ports.join(", "));
+        }
+        println!("Limits: {}", jail.limits);
+        if let Some(ref usage) = usage {
+            println!("Usage: {}", describe_usage(usage));
+        }
+        if let Some(detached) = jail.detached_session() {
+            println!("Detached: supervisor PID {}; attach with: robojail attach {}", detached.pid, name);
+        }
+        let sessions = jail.live_sessions();
+        if !sessions.is_empty() {
+            println!("Sessions:");
+            for session in sessions {
+                println!("  {}", session.describe());
+            }
+        }
+
         // Show diff if requested
         if show_diff {
             println!("\n--- Diff ---\n");
 
+            if jail.mode == JailMode::Overlay {
+                // Comparing the layers directly also covers new files
+                for (file, old, new) in overlay_pairs(jail, &modified, &added, &deleted) {
+                    let output = Command::new("git")
+                        .args(["diff", "--no-index", "--"])
+                        .args([&old, &new])
+                        .output();
+                    if let Ok(output) = output {
+                        // Show repository paths rather than where the layers live
+                        let mut diff = String::from_utf8_lossy(&output.stdout).to_string();
+                        for layer in [&old, &new].into_iter().filter(|path| !path.starts_with("/dev")) {
+                            let layer = layer.to_string_lossy();
+                            diff = diff.replace(layer.trim_start_matches('/'), file);
+                        }
+                        print!("{diff}");
+                    }
+                }
+                return Ok(());
+            }
+
+            let mut diff_args = vec!["diff"];
+            if since_base {
+                diff_args.extend(base.as_ref().map(|base| base.commit.as_str()));
+            }
             let diff_output = Command::new("git")
-                .args(["-C", jail.worktree_path.to_str().unwrap_or("."), "diff"])
+                .args(["-C", jail.worktree_path.to_str().unwrap_or(".")])
+                .args(&diff_args)
                 .output();
 
             if let Ok(output) = diff_output {
@@ -169,9 +320,10 @@ pub fn status(name: &str, json: bool, show_diff: bool) -> Result<()> {
             }
 
             // Also show untracked file contents
-            if !added.is_empty() {
+            let untracked = progress.as_ref().map(|progress| progress.untracked.as_slice()).unwrap_or_default();
+            if !untracked.is_empty() {
                 println!("\n--- New Files ---\n");
-                for file in &added {
+                for file in untracked {
                     let file_path = jail.worktree_path.join(file);
                     if let Ok(content) = std::fs::read_to_string(&file_path) {
                         println!("=== {} ===", file);
@@ -185,6 +337,166 @@ pub fn status(name: &str, json: bool, show_diff: bool) -> Result<()> {
     Ok(())
 }
 
+/// Print the commits and uncommitted changes of a worktree jail
+fn print_progress(progress: &Progress) {
+    let commits: Vec<String> = progress.commits.iter().map(|c| format!("{} {}", c.sha, c.subject)).collect();
+    let sections = [
+        ("Commits since base", &commits),
+        ("Staged", &progress.staged),
+        ("Unstaged", &progress.unstaged),
+        ("Untracked", &progress.untracked),
+    ];
+
+    for (title, lines) in sections {
+        if !lines.is_empty() {
+            println!("\n{}:", title);
+            for line in lines {
+                println!("  {}", line);
+            }
+        }
+    }
+}
+
+/// Changed files in a worktree jail relative to `base`, committed or not
+fn worktree_changes(jail: &JailInfo, base: &str, untracked: &[String]) -> Result<(Vec<String>, Vec<String>, Vec<String>)> {
+    let name_status = git(&jail.worktree_path, &["diff", "--name-status", "--no-renames", base])?;
+
+    let mut modified = Vec::new();
+    let mut added = untracked.to_vec();
+    let mut deleted = Vec::new();
+
+    for line in name_status.lines() {
+        let Some((code, file)) = line.split_once('\t') else {
+            continue;
+        };
+        match code {
+            "A" => added.push(file.to_string()),
+            "D" => deleted.push(file.to_string()),
+            _ => modified.push(file.to_string()),
+        }
+    }
+
+    added.sort();
+    Ok((modified, added, deleted))
+}
+
+/// Commits, index and working tree changes of a worktree jail on top of `base`
+fn worktree_progress(jail: &JailInfo, base: &str) -> Result<Progress> {
+    let worktree = &jail.worktree_path;
+    let lines = |output: String| -> Vec<String> { output.lines().map(|line| line.replace('\t', " ")).collect() };
+
+    let log = git(worktree, &["log", "--format=%h%x09%s", &format!("{base}..HEAD")])?;
+    let commits = log
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(sha, subject)| CommitSummary { sha: sha.to_string(), subject: subject.to_string() })
+        .collect();
+
+    Ok(Progress {
+        commits,
+        staged: lines(git(worktree, &["diff", "--cached", "--name-status"])?),
+        unstaged: lines(git(worktree, &["diff", "--name-status"])?),
+        untracked: lines(git(worktree, &["ls-files", "--others", "--exclude-standard"])?),
+    })
+}
+
+/// Diff stats of a worktree jail relative to `base`, counting untracked files as added
+fn worktree_stats(jail: &JailInfo, base: &str, untracked: &[String]) -> (u32, u32, u32) {
+    let stat = git(&jail.worktree_path, &["diff", "--stat", "--stat-width=1000", base]).unwrap_or_default();
+    let (mut insertions, deletions, mut files) = parse_diff_stats(&stat);
+
+    for file in untracked {
+        files += 1;
+        if let Ok(content) = std::fs::read_to_string(jail.worktree_path.join(file)) {
+            insertions += content.lines().count() as u32;
+        }
+    }
+
+    (insertions, deletions, files)
+}
+
+/// Where the jail started, and how far its branch has moved on the host since
+fn base_info(jail: &JailInfo, base: &str) -> BaseInfo {
+    let branch_ahead = jail.base_branch.as_ref().and_then(|branch| {
+        git(&jail.repo_path, &["rev-list", "--count", &format!("{base}..refs/heads/{branch}")])
+            .ok()
+            .and_then(|count| count.parse().ok())
+    });
+
+    BaseInfo { commit: base.to_string(), branch: jail.base_branch.clone(), branch_ahead }
+}
+
+/// Lower and upper version of each changed file in an overlay jail
+///
+/// Deleted files are compared against /dev/null rather than their whiteout.
+fn overlay_pairs<'a>(
+    jail: &JailInfo,
+    modified: &'a [String],
+    added: &'a [String],
+    deleted: &'a [String],
+) -> Vec<(&'a str, PathBuf, PathBuf)> {
+    let (lower, upper) = (&jail.repo_path, jail.upper_dir());
+    let null = PathBuf::from("/dev/null");
+
+    let mut pairs: Vec<(&str, PathBuf, PathBuf)> =
+        modified.iter().map(|file| (file.as_str(), lower.join(file), upper.join(file))).collect();
+    pairs.extend(added.iter().map(|file| (file.as_str(), null.clone(), upper.join(file))));
+    pairs.extend(deleted.iter().map(|file| (file.as_str(), lower.join(file), null.clone())));
+    pairs
+}
+
+/// Diff stats of an overlay jail, from `git diff --no-index --numstat` per file
+fn overlay_stats(pairs: &[(&str, PathBuf, PathBuf)]) -> (u32, u32, u32) {
+    let mut insertions = 0;
+    let mut deletions = 0;
+
+    for (_, old, new) in pairs {
+        let output = Command::new("git")
+            .args(["diff", "--no-index", "--numstat", "--"])
+            .args([old, new])
+            .output();
+        let Ok(output) = output else {
+            continue;
+        };
+
+        // Binary files show up as "-\t-"
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let mut fields = line.split('\t');
+            insertions += fields.next().and_then(|n| n.parse::<u32>().ok()).unwrap_or(0);
+            deletions += fields.next().and_then(|n| n.parse::<u32>().ok()).unwrap_or(0);
+        }
+    }
+
+    (insertions, deletions, pairs.len() as u32)
+}
+
+/// One-line summary of live resource usage
+fn describe_usage(usage: &ResourceUsage) -> String {
+    let mut parts = Vec::new();
+
+    if let Some(memory) = usage.memory_bytes {
+        match usage.memory_peak_bytes {
+            Some(peak) => parts.push(format!("memory {} (peak {})", format_size(memory), format_size(peak))),
+            None => parts.push(format!("memory {}", format_size(memory))),
+        }
+    }
+    if let Some(cpu) = usage.cpu_usec {
+        parts.push(format!("cpu {:.1}s", cpu as f64 / 1_000_000.0));
+    }
+    if let Some(pids) = usage.pids {
+        parts.push(format!("{} processes", pids));
+    }
+    if let (Some(read), Some(write)) = (usage.io_read_bytes, usage.io_write_bytes) {
+        parts.push(format!("io {} read / {} written", format_size(read), format_size(write)));
+    }
+
+    if parts.is_empty() {
+        "unavailable".to_string()
+    } else {
+        parts.join(", ")
+    }
+}
+
 /// Parse the summary line from git diff --stat
 fn parse_diff_stats(output: &str) -> (u32, u32, u32) {
     // Look for a line like: " 3 files changed, 42 insertions(+), 10 deletions(-)"
@@ -213,7 +525,7 @@ fn parse_diff_stats(output: &str) -> (u32, u32, u32) {
         // Parse "X deletions(-)"
         if let Some(idx) = summary.find("deletion") {
             let before = &summary[..idx];
-            if let Some(num_str) = before.split(',').last().and_then(|s| s.split_whitespace().last())
+            if let Some(num_str) = before.split(',').next_back().and_then(|s| s.split_whitespace().last())
             {
                 deletions = num_str.parse().unwrap_or(0);
             }
@@ -0,0 +1,108 @@
+use super::repo::{base_commit, check_branch_access, commit_pending, conflict_error, conflicted_files, git};
+use crate::error::{Error, Result};
+use crate::state::State;
+use serde::Serialize;
+
+/// Result of `robojail sync`, printed as JSON with `--json`
+#[derive(Serialize)]
+struct SyncOutput {
+    name: String,
+    onto: String,
+    /// "rebased", "up_to_date" or "conflict"
+    result: &'static str,
+    old_base: String,
+    /// The jail's base after the sync (unchanged on conflict)
+    new_base: String,
+    /// WIP commit made from uncommitted changes before rebasing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wip_commit: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    conflicts: Vec<String>,
+}
+
+/// Rebase a jail's branch onto the current tip of its base branch
+///
+/// Uncommitted work is committed as a WIP commit first. Only the host
+/// repository is involved; nothing is fetched.
+pub fn sync(name: &str, onto: Option<&str>, json: bool, force: bool) -> Result<()> {
+    let state = State::load()?;
+    let jail = state.get_jail(name)?.clone();
+
+    if jail.is_running() {
+        return Err(Error::JailRunning(name.to_string()));
+    }
+    check_branch_access(&jail, force)?;
+
+    let onto = match onto.or(jail.base_branch.as_deref()) {
+        Some(branch) => branch.to_string(),
+        None => {
+            return Err(Error::Config(format!(
+                "jail '{name}' didn't start from a branch; use --onto to choose one"
+            )))
+        }
+    };
+    let tip = git(&jail.repo_path, &["rev-parse", "--verify", "--quiet", &format!("refs/heads/{onto}^{{commit}}")])
+        .map_err(|_| Error::Config(format!("no branch named '{onto}' in {}", jail.repo_path.display())))?;
+    let old_base = base_commit(&jail)?;
+
+    let message = format!("WIP: uncommitted changes from jail '{}'", name);
+    let wip_commit = if commit_pending(&jail, &message)? {
+        Some(git(&jail.worktree_path, &["rev-parse", "HEAD"])?)
+    } else {
+        None
+    };
+
+    let mut output = SyncOutput {
+        name: name.to_string(),
+        onto: onto.clone(),
+        result: "up_to_date",
+        old_base: old_base.clone(),
+        new_base: old_base.clone(),
+        wip_commit,
+        conflicts: vec![],
+    };
+
+    if tip != old_base {
+        // Replay only the jail's own commits, whatever happened to the old base
+        let worktree = &jail.worktree_path;
+        match git(worktree, &["rebase", "--quiet", "--onto", &tip, &old_base]) {
+            Ok(_) => {
+                // The WIP commit is the branch tip, and was rewritten too
+                if output.wip_commit.is_some() {
+                    output.wip_commit = Some(git(worktree, &["rev-parse", "HEAD"])?);
+                }
+                output.result = "rebased";
+                output.new_base = tip.clone();
+                State::update(|state| state.set_base(name, tip, Some(onto.clone())))?;
+            }
+            Err(e) => {
+                output.conflicts = conflicted_files(worktree);
+                let _ = git(worktree, &["rebase", "--abort"]);
+                if output.conflicts.is_empty() {
+                    return Err(e);
+                }
+                output.result = "conflict";
+            }
+        }
+    } else if jail.base_branch.as_deref() != Some(onto.as_str()) {
+        State::update(|state| state.set_base(name, tip, Some(onto.clone())))?;
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    } else {
+        if let Some(ref commit) = output.wip_commit {
+            println!("Committed pending changes as {}", &commit[..12.min(commit.len())]);
+        }
+        match output.result {
+            "rebased" => println!("Rebased jail '{}' onto '{}' ({})", name, onto, &output.new_base[..12]),
+            "up_to_date" => println!("Jail '{}' is already based on the tip of '{}'", name, onto),
+            _ => {}
+        }
+    }
+
+    if output.result == "conflict" {
+        return Err(conflict_error(&jail.branch_name, &onto, &output.conflicts));
+    }
+    Ok(())
+}
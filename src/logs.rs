@@ -0,0 +1,265 @@
+//! Per-jail output logs
+//!
+//! Output with no terminal left to show it on (that of detached sessions,
+//! and of `run` with its output redirected) is kept in the jail's log
+//! directory, along with when each session started and how it ended. Each
+//! line holds a timestamp, a stream and the text:
+//!
+//! ```text
+//! 2025-01-01T12:00:00.000Z out Compiling robojail v0.1.0
+//! ```
+//!
+//! The streams are `out` and `err` for redirected output, `tty` for the
+//! terminal of a detached session and `run` for robojail's own notes. Logs
+//! are rotated by size into `output.log.1`, `output.log.2` and so on.
+
+use crate::config::{Config, LogSettings};
+use crate::error::{Error, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeDelta, Utc};
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Size at which logs are rotated, unless configured
+pub const DEFAULT_MAX_SIZE_KB: u64 = 10 * 1024;
+
+/// Rotated logs kept per jail, unless configured
+pub const DEFAULT_KEEP: usize = 3;
+
+/// Name of the current log in the log directory
+const FILE: &str = "output.log";
+
+/// A line longer than this is logged in pieces
+const MAX_LINE: usize = 16 * 1024;
+
+/// Where a logged line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Out,
+    Err,
+    Tty,
+    Run,
+}
+
+impl Stream {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stream::Out => "out",
+            Stream::Err => "err",
+            Stream::Tty => "tty",
+            Stream::Run => "run",
+        }
+    }
+}
+
+/// Appends to a jail's log, rotating it when it gets too big
+///
+/// The file is reopened for every write, so several processes can log to
+/// the same jail and follow each other's rotations.
+pub struct Log {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    /// Bytes of a line not yet ended, per stream
+    pending: Mutex<[Vec<u8>; 4]>,
+}
+
+impl Log {
+    /// Open a jail's log
+    pub fn open(name: &str, settings: &LogSettings) -> Result<Self> {
+        let dir = dir(name)?;
+        fs::create_dir_all(&dir)?;
+        Ok(Self {
+            path: dir.join(FILE),
+            max_size: settings.max_size_kb.unwrap_or(DEFAULT_MAX_SIZE_KB) * 1024,
+            keep: settings.keep.unwrap_or(DEFAULT_KEEP),
+            pending: Mutex::default(),
+        })
+    }
+
+    /// Log output; a line cut off at the end waits for the rest
+    pub fn write(&self, stream: Stream, data: &[u8]) {
+        let Ok(mut pending) = self.pending.lock() else { return };
+        let buf = &mut pending[stream as usize];
+        buf.extend_from_slice(data);
+
+        let end = match buf.iter().rposition(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None if buf.len() > MAX_LINE => buf.len(),
+            None => return,
+        };
+        let complete: Vec<u8> = buf.drain(..end).collect();
+        self.append(stream, String::from_utf8_lossy(&complete).lines());
+    }
+
+    /// Log a note of robojail's own, such as how a session ended
+    pub fn note(&self, text: &str) {
+        self.append(Stream::Run, std::iter::once(text));
+    }
+
+    /// Log the lines still waiting for their end
+    pub fn flush(&self) {
+        let Ok(mut pending) = self.pending.lock() else { return };
+        for (stream, buf) in [Stream::Out, Stream::Err, Stream::Tty, Stream::Run].into_iter().zip(pending.iter_mut()) {
+            if !buf.is_empty() {
+                self.append(stream, String::from_utf8_lossy(buf).lines());
+                buf.clear();
+            }
+        }
+    }
+
+    /// Write lines with the current time; a log that can't be written is
+    /// not worth failing the session for, so errors are dropped
+    fn append<'a>(&self, stream: Stream, lines: impl Iterator<Item = &'a str>) {
+        let time = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let mut text = String::new();
+        for line in lines {
+            let _ = writeln!(text, "{time} {} {}", stream.as_str(), line.trim_end_matches('\r'));
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        let _ = self.rotate();
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&self.path) {
+            let _ = file.write_all(text.as_bytes());
+        }
+    }
+
+    /// Move the log aside once it is full, dropping the oldest
+    fn rotate(&self) -> io::Result<()> {
+        if fs::metadata(&self.path).map_or(0, |meta| meta.len()) < self.max_size {
+            return Ok(());
+        }
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for n in (1..self.keep).rev() {
+            let _ = fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1));
+        }
+        fs::rename(&self.path, rotated(&self.path, 1))
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    path.with_extension(format!("log.{n}"))
+}
+
+/// Directory holding a jail's logs
+pub fn dir(name: &str) -> Result<PathBuf> {
+    Ok(Config::jail_state_dir(name)?.join("logs"))
+}
+
+/// Path of a jail's current log
+pub fn path(name: &str) -> Result<PathBuf> {
+    Ok(dir(name)?.join(FILE))
+}
+
+/// A jail's logs that exist, oldest first
+pub fn files(name: &str) -> Result<Vec<PathBuf>> {
+    let current = path(name)?;
+    let mut rotated_files: Vec<(usize, PathBuf)> = match fs::read_dir(dir(name)?) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let n = file_name.to_str()?.strip_prefix("output.log.")?.parse().ok()?;
+                Some((n, entry.path()))
+            })
+            .collect(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    rotated_files.sort_by_key(|(n, _)| std::cmp::Reverse(*n));
+
+    let mut files: Vec<PathBuf> = rotated_files.into_iter().map(|(_, path)| path).collect();
+    if current.exists() {
+        files.push(current);
+    }
+    Ok(files)
+}
+
+/// Time at the start of a log line
+pub fn line_time(line: &str) -> Option<DateTime<Utc>> {
+    let (time, _) = line.split_once(' ')?;
+    DateTime::parse_from_rfc3339(time).ok().map(|time| time.with_timezone(&Utc))
+}
+
+/// Parse a `--since` value
+///
+/// Either a time span back from `now` (`30s`, `10m`, `2h`, `1d`), an RFC 3339
+/// time, or a local date with an optional time (`2025-01-01 12:00`).
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let invalid = || {
+        Error::Config(format!(
+            "invalid --since '{value}': expected a span like 10m, 2h or 1d, or a time like 2025-01-01 12:00"
+        ))
+    };
+
+    let span = value.char_indices().last().and_then(|(at, unit)| Some((value[..at].parse::<i64>().ok()?, unit)));
+    if let Some((count, unit)) = span {
+        let span = match unit {
+            's' => TimeDelta::try_seconds(count),
+            'm' => TimeDelta::try_minutes(count),
+            'h' => TimeDelta::try_hours(count),
+            'd' => TimeDelta::try_days(count),
+            _ => None,
+        };
+        return span.and_then(|span| now.checked_sub_signed(span)).ok_or_else(invalid);
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let local = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+        .ok_or_else(invalid)?;
+    local
+        .and_local_timezone(Local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since() {
+        let now = DateTime::parse_from_rfc3339("2025-01-02T12:00:00Z").unwrap().with_timezone(&Utc);
+
+        assert_eq!(parse_since("90s", now).unwrap(), now - TimeDelta::seconds(90));
+        assert_eq!(parse_since("10m", now).unwrap(), now - TimeDelta::minutes(10));
+        assert_eq!(parse_since("1d", now).unwrap(), now - TimeDelta::days(1));
+        assert_eq!(
+            parse_since("2025-01-01T08:30:00+01:00", now).unwrap(),
+            DateTime::parse_from_rfc3339("2025-01-01T07:30:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_since("2025-01-01T07:30:00Z", now).unwrap(),
+            DateTime::parse_from_rfc3339("2025-01-01T07:30:00Z").unwrap()
+        );
+        assert!(parse_since("2025-01-01 08:30", now).is_ok());
+        assert!(parse_since("10x", now).is_err());
+        assert!(parse_since("m", now).is_err());
+        assert!(parse_since("yesterday", now).is_err());
+    }
+
+    #[test]
+    fn test_line_time() {
+        let line = "2025-01-01T12:00:00.250Z out hello world";
+        assert_eq!(line_time(line).unwrap().timestamp_millis(), 1735732800250);
+        assert_eq!(line_time("garbage"), None);
+    }
+}
@@ -1,13 +1,19 @@
+mod cgroup;
 mod cli;
 mod config;
 mod error;
 mod jail;
+mod logs;
+mod overlay;
+mod recording;
 mod sandbox;
+mod session;
 mod state;
+mod tamper;
 mod validation;
 
 use clap::Parser;
-use cli::{Cli, Command};
+use cli::{Cli, Command, PortAction};
 use error::Result;
 
 fn main() {
@@ -22,25 +28,109 @@ fn run() -> Result<()> {
     let config = config::Config::load()?;
 
     match cli.command {
-        Command::Create { name, repo, branch, entrypoint } => {
-            jail::create(&name, &repo, branch.as_deref(), entrypoint.as_deref(), &config)?;
+        Command::Create { name, repo, branch, mode, entrypoint, memory, memory_swap, cpus, pids, io_max, network, allow_domain, allow_net, publish } => {
+            let opts = jail::CreateOptions {
+                name: &name,
+                repo: &repo,
+                branch: branch.as_deref(),
+                mode,
+                entrypoint: entrypoint.as_deref(),
+                limits: cgroup::ResourceLimits {
+                    memory_max: memory,
+                    memory_swap_max: memory_swap,
+                    cpus,
+                    pids_max: pids,
+                    io_max,
+                },
+                network,
+                allow_domains: allow_domain,
+                allow_net,
+                ports: publish,
+            };
+            jail::create(opts, &config)?;
+        }
+        Command::Clone { source, name, force } => {
+            jail::clone(&source, &name, force)?;
+        }
+        Command::Rename { name, new_name, rename_branch } => {
+            jail::rename(&name, &new_name, rename_branch)?;
         }
         Command::List { json } => {
             jail::list(json)?;
         }
-        Command::Enter { name } => {
+        Command::Enter { name, detach: false } => {
             jail::enter(&name, &config)?;
         }
+        Command::Enter { name, detach: true } => {
+            jail::detach(&name, None, &config)?;
+        }
+        Command::Attach { name, read_only } => {
+            jail::attach(&name, read_only)?;
+        }
+        Command::Logs { name, follow, since, tail } => {
+            jail::logs(&name, follow, since.as_deref(), tail)?;
+        }
+        Command::Sessions { name, json } => {
+            jail::sessions(&name, json)?;
+        }
+        Command::Replay { name, session, speed, idle_limit } => {
+            jail::replay(&name, &session, speed, idle_limit)?;
+        }
         Command::Destroy { name, force } => {
             jail::destroy(&name, force)?;
         }
-        Command::Run { name, command } => {
+        Command::Run { name, detach: true, command } => {
+            jail::detach(&name, Some(&command), &config)?;
+        }
+        Command::Run { name, detach: false, command } => {
             let code = jail::run(&name, &command, &config)?;
             std::process::exit(code);
         }
-        Command::Status { name, json, diff } => {
-            jail::status(&name, json, diff)?;
+        Command::Status { name, json, diff, since_base } => {
+            jail::status(&name, json, diff || since_base, since_base, &config)?;
+        }
+        Command::Merge { name, into, strategy, message, verify, destroy, force } => {
+            let opts = jail::MergeOptions {
+                name: &name,
+                into: into.as_deref(),
+                strategy,
+                message: message.as_deref(),
+                verify: verify.as_deref(),
+                destroy,
+                force,
+            };
+            jail::merge(opts, &config)?;
+        }
+        Command::Checkpoint { name, message, include_ignored, force } => {
+            jail::checkpoint(&name, message.as_deref(), include_ignored, force)?;
+        }
+        Command::Checkpoints { name, json } => {
+            jail::checkpoints(&name, json)?;
+        }
+        Command::History { name, json, diff } => {
+            jail::history(&name, json, diff.map(|ids| (ids[0], ids[1])))?;
+        }
+        Command::Rollback { name, checkpoint, force } => {
+            jail::rollback(&name, checkpoint, force)?;
+        }
+        Command::Sync { name, onto, json, force } => {
+            jail::sync(&name, onto.as_deref(), json, force)?;
+        }
+        Command::Export { name, format, output, base, force } => {
+            let opts = jail::ExportOptions {
+                name: &name,
+                format,
+                output: &output,
+                base: base.as_deref(),
+                force,
+            };
+            jail::export(opts)?;
         }
+        Command::Port { name, action } => match action {
+            PortAction::Add { mappings } => jail::port_add(&name, &mappings)?,
+            PortAction::Remove { ports } => jail::port_remove(&name, &ports)?,
+            PortAction::List { json } => jail::port_list(&name, json)?,
+        },
     }
 
     Ok(())
@@ -0,0 +1,163 @@
+//! Copy-on-write jails on overlayfs
+//!
+//! An overlay jail sees the original checkout (including untracked and
+//! ignored files such as build caches) as the read-only lower layer of an
+//! overlayfs mounted inside the jail's user namespace. Everything the jail
+//! writes lands in a per-jail upper directory, so its changes can be read
+//! from there without mounting anything: regular entries are added or
+//! modified files, 0:0 character devices are whiteouts for deleted ones, and
+//! directories marked opaque hide everything below them in the lower layer.
+
+use crate::error::Result;
+use std::ffi::CString;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+/// Upper layer, relative to the jail directory
+pub const UPPER_DIR: &str = "upper";
+
+/// Overlayfs work directory, relative to the jail directory
+pub const WORK_DIR: &str = "work";
+
+/// Files an overlay jail changed relative to its lower layer
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    pub modified: Vec<String>,
+    pub added: Vec<String>,
+    pub deleted: Vec<String>,
+}
+
+/// Collect the changes recorded in `upper` on top of `lower`
+///
+/// The repository's `.git` directory is not reported.
+pub fn changes(lower: &Path, upper: &Path) -> Result<Changes> {
+    let mut changes = Changes::default();
+    walk(lower, upper, Path::new(""), &mut changes)?;
+
+    changes.modified.sort();
+    changes.added.sort();
+    changes.deleted.sort();
+    Ok(changes)
+}
+
+fn walk(lower: &Path, upper: &Path, rel: &Path, changes: &mut Changes) -> Result<()> {
+    for entry in fs::read_dir(upper.join(rel))? {
+        let entry = entry?;
+        if rel.as_os_str().is_empty() && entry.file_name() == ".git" {
+            continue;
+        }
+
+        let path = rel.join(entry.file_name());
+        let meta = entry.metadata()?;
+        let below = fs::symlink_metadata(lower.join(&path)).ok();
+        let lower_is_dir = below.as_ref().is_some_and(|m| m.is_dir());
+
+        if is_whiteout(&meta) {
+            if lower_is_dir {
+                changes.deleted.extend(lower_files(lower, &path));
+            } else if below.is_some() {
+                changes.deleted.push(display(&path));
+            }
+        } else if meta.is_dir() {
+            if lower_is_dir && is_opaque(&entry.path()) {
+                let gone = lower_files(lower, &path)
+                    .into_iter()
+                    .filter(|file| fs::symlink_metadata(upper.join(file)).is_err());
+                changes.deleted.extend(gone);
+            } else if below.is_some() && !lower_is_dir {
+                changes.deleted.push(display(&path));
+            }
+            walk(lower, upper, &path, changes)?;
+        } else {
+            match below {
+                Some(_) if lower_is_dir => {
+                    changes.deleted.extend(lower_files(lower, &path));
+                    changes.added.push(display(&path));
+                }
+                Some(_) => changes.modified.push(display(&path)),
+                None => changes.added.push(display(&path)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// All non-directory entries below `rel` in the lower layer
+fn lower_files(lower: &Path, rel: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut pending = vec![rel.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(lower.join(&dir)) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path: PathBuf = dir.join(entry.file_name());
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => pending.push(path),
+                Ok(_) => files.push(display(&path)),
+                Err(_) => {}
+            }
+        }
+    }
+
+    files
+}
+
+/// Whether an upper entry marks a deleted file
+fn is_whiteout(meta: &fs::Metadata) -> bool {
+    meta.file_type().is_char_device() && meta.rdev() == 0
+}
+
+/// Whether an upper directory hides the lower directory at the same path
+///
+/// Unprivileged overlays keep their metadata in `user.overlay.*` xattrs.
+fn is_opaque(dir: &Path) -> bool {
+    let Ok(path) = CString::new(dir.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut value = [0u8; 1];
+    let len = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            c"user.overlay.opaque".as_ptr(),
+            value.as_mut_ptr().cast(),
+            value.len(),
+        )
+    };
+    len == 1 && value[0] == b'y'
+}
+
+fn display(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changes() {
+        let root = std::env::temp_dir().join(format!("robojail-overlay-test-{}", std::process::id()));
+        let (lower, upper) = (root.join("lower"), root.join("upper"));
+        for dir in [lower.join("src/nested"), lower.join(".git"), upper.join("src"), upper.join(".git")] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        for file in ["README.md", "src/main.rs", "src/nested/mod.rs", ".git/index"] {
+            fs::write(lower.join(file), "lower").unwrap();
+        }
+        for file in ["src/main.rs", "src/new.rs", "build.rs", ".git/index"] {
+            fs::write(upper.join(file), "upper").unwrap();
+        }
+
+        let changes = changes(&lower, &upper).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(changes.modified, vec!["src/main.rs"]);
+        assert_eq!(changes.added, vec!["build.rs", "src/new.rs"]);
+        assert!(changes.deleted.is_empty());
+    }
+}
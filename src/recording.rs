@@ -0,0 +1,248 @@
+//! Terminal session recordings
+//!
+//! Sessions are recorded in the asciicast v2 format (as used by asciinema):
+//! a JSON header line followed by one `[seconds, kind, data]` line per event,
+//! where the kind is `o` for output, `i` for input and `r` for a resize to
+//! `COLSxROWS`. Recordings live in the jail's state directory, one file per
+//! session.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Header line of an asciicast v2 file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub width: u16,
+    pub height: u16,
+    /// Start of the session, in seconds since the epoch
+    pub timestamp: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+/// Writes the events of one session to its recording
+pub struct Recorder {
+    start: Instant,
+    input: bool,
+    inner: Mutex<Streams>,
+}
+
+/// The file, and bytes of a UTF-8 sequence split across reads, per kind
+struct Streams {
+    file: File,
+    output: Vec<u8>,
+    input: Vec<u8>,
+}
+
+impl Recorder {
+    /// Start a recording at `path`; keystrokes are only recorded with `input`
+    pub fn create(path: &Path, size: (u16, u16), command: &str, title: &str, input: bool) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = File::create(path)?;
+
+        let (height, width) = size;
+        let header = Header {
+            version: 2,
+            width,
+            height,
+            timestamp: Utc::now().timestamp(),
+            command: Some(command.to_string()),
+            title: Some(title.to_string()),
+        };
+        writeln!(file, "{}", serde_json::to_string(&header)?)?;
+
+        Ok(Self {
+            start: Instant::now(),
+            input,
+            inner: Mutex::new(Streams { file, output: vec![], input: vec![] }),
+        })
+    }
+
+    /// Record terminal output
+    pub fn output(&self, data: &[u8]) {
+        self.event("o", data);
+    }
+
+    /// Record keystrokes, if input capture is on
+    pub fn input(&self, data: &[u8]) {
+        if self.input {
+            self.event("i", data);
+        }
+    }
+
+    /// Record a terminal resize
+    pub fn resize(&self, rows: u16, cols: u16) {
+        self.event("r", format!("{cols}x{rows}").as_bytes());
+    }
+
+    /// Write one event; a recording that can't be written is not worth
+    /// failing the session for, so errors are dropped
+    fn event(&self, kind: &str, data: &[u8]) {
+        let Ok(mut streams) = self.inner.lock() else { return };
+        let Streams { file, output, input } = &mut *streams;
+
+        let text = match kind {
+            "o" => take_utf8(output, data),
+            "i" => take_utf8(input, data),
+            _ => String::from_utf8_lossy(data).into_owned(),
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        let time = self.start.elapsed().as_secs_f64();
+        if let Ok(line) = serde_json::to_string(&(time, kind, text)) {
+            let _ = writeln!(file, "{line}");
+        }
+    }
+}
+
+/// Append `data` to `pending` and take the complete UTF-8 text
+///
+/// A sequence cut off at the end is kept for the next call; invalid bytes
+/// become U+FFFD.
+fn take_utf8(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let incomplete = match std::str::from_utf8(pending) {
+        Err(e) if e.error_len().is_none() => pending.len() - e.valid_up_to(),
+        _ => 0,
+    };
+    let rest = pending.split_off(pending.len() - incomplete);
+    let text = String::from_utf8_lossy(pending).into_owned();
+    *pending = rest;
+    text
+}
+
+/// Directory holding a jail's recordings
+pub fn dir(name: &str) -> Result<PathBuf> {
+    Ok(Config::jail_state_dir(name)?.join("recordings"))
+}
+
+/// Path for a new recording of a jail, named after its start time
+pub fn new_path(name: &str) -> Result<PathBuf> {
+    let id = format!("{}-{}", Utc::now().format("%Y%m%d-%H%M%S"), std::process::id());
+    Ok(dir(name)?.join(format!("{id}.cast")))
+}
+
+/// A recording found on disk
+#[derive(Debug, Serialize)]
+pub struct Recording {
+    pub id: String,
+    #[serde(skip)]
+    pub path: PathBuf,
+    pub started: DateTime<Utc>,
+    /// Seconds from the start to the last event
+    pub duration: f64,
+    pub bytes: u64,
+    pub command: Option<String>,
+}
+
+/// A jail's recordings, oldest first
+pub fn list(name: &str) -> Result<Vec<Recording>> {
+    let dir = dir(name)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut recordings = vec![];
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "cast") {
+            continue;
+        }
+        let Ok((header, events)) = read(&path) else { continue };
+        recordings.push(Recording {
+            id: path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+            started: DateTime::from_timestamp(header.timestamp, 0).unwrap_or_default(),
+            duration: events.last().map_or(0.0, |event| event.0),
+            bytes: entry.metadata().map(|meta| meta.len()).unwrap_or(0),
+            command: header.command,
+            path,
+        });
+    }
+    recordings.sort_by(|a, b| a.started.cmp(&b.started).then_with(|| a.id.cmp(&b.id)));
+    Ok(recordings)
+}
+
+/// An event line: time, kind and data
+type Event = (f64, String, String);
+
+/// Read a recording's header and events
+///
+/// A last line cut off by a crash is skipped.
+fn read(path: &Path) -> Result<(Header, Vec<Event>)> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let invalid = |what: &str| Error::Config(format!("{}: {what}", path.display()));
+
+    let header: Header = match lines.next() {
+        Some(line) => serde_json::from_str(&line?).map_err(|e| invalid(&format!("invalid header: {e}")))?,
+        None => return Err(invalid("empty recording")),
+    };
+    if header.version != 2 {
+        return Err(invalid(&format!("unsupported asciicast version {}", header.version)));
+    }
+
+    let mut events = vec![];
+    for line in lines {
+        match serde_json::from_str::<Event>(&line?) {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    Ok((header, events))
+}
+
+/// Play a recording's output back in the terminal
+///
+/// `speed` scales the timing; pauses are shortened to `idle_limit` seconds.
+pub fn replay(path: &Path, speed: f64, idle_limit: Option<f64>) -> Result<()> {
+    let (_, events) = read(path)?;
+    let mut stdout = io::stdout();
+    let mut previous = 0.0;
+
+    for (time, kind, data) in events {
+        if kind != "o" {
+            continue;
+        }
+        let mut pause = (time - previous).max(0.0);
+        if let Some(limit) = idle_limit {
+            pause = pause.min(limit);
+        }
+        previous = time;
+
+        std::thread::sleep(Duration::from_secs_f64(pause / speed));
+        stdout.write_all(data.as_bytes())?;
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_utf8_keeps_split_sequences() {
+        let mut pending = vec![];
+        let euro = "€".as_bytes();
+
+        assert_eq!(take_utf8(&mut pending, &[b'a', euro[0], euro[1]]), "a");
+        assert_eq!(take_utf8(&mut pending, &euro[2..]), "€");
+        assert_eq!(take_utf8(&mut pending, &[0xff, b'b']), "\u{fffd}b");
+        assert!(pending.is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+//! Git access for worktree jails
+//!
+//! A worktree's `.git` is a file pointing at its gitdir inside the main
+//! repository, a host path the jail can't see. Rather than exposing the whole
+//! repository, the jail gets the repository's common gitdir read-only at
+//! /run/robojail/git, its own `.git` file pointing there, and writable mounts
+//! only where its work goes:
+//!
+//! - the worktree's own gitdir (HEAD, index, per-worktree reflog)
+//! - the directory holding the jail's branch (`refs/heads/robojail/<name>-<id>`)
+//!   and its reflogs; other jails' branches live in directories of their own
+//! - the object store, as an overlay whose upper layer lives in the
+//!   worktree's gitdir, so new objects never touch the shared store
+//!
+//! The main repository lists that upper layer as an alternate object
+//! directory, so commits made in the jail are visible on the host right away.
+//! When the jail is destroyed its objects are moved into the main store.
+//! Config, hooks and all other refs stay read-only, and so do the files in
+//! the worktree's gitdir that decide which repository and config host-side
+//! git uses for the worktree.
+
+use super::landlock::{Access, Ruleset};
+use super::mount;
+use crate::error::{Error, Result};
+use crate::state::State;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Where the common gitdir is mounted inside the jail
+pub const JAIL_GIT_DIR: &str = "/run/robojail/git";
+
+/// Files in the worktree's gitdir that host-side git trusts, kept read-only
+///
+/// `commondir` and `gitdir` say which repository the worktree belongs to and
+/// `config.worktree` is per-worktree config; rewriting any of them would let
+/// the jail choose the config git uses the next time it runs on the host.
+const PROTECTED_FILES: [&str; 3] = ["commondir", "gitdir", "config.worktree"];
+
+/// The gitdirs of a linked worktree
+#[derive(Debug, Clone)]
+pub struct GitLayout {
+    /// The main repository's gitdir (objects, refs, config, hooks)
+    common: PathBuf,
+    /// Path of the worktree's gitdir relative to `common`
+    worktree: PathBuf,
+    /// Directory of the jail's branch relative to `refs/heads`, if it has one
+    branch_dir: Option<PathBuf>,
+}
+
+impl GitLayout {
+    /// Find the gitdirs of the linked worktree at `path`
+    ///
+    /// Returns `None` if `path` isn't a linked worktree.
+    pub fn discover(path: &Path) -> Option<Self> {
+        let dotgit = fs::read_to_string(path.join(".git")).ok()?;
+        let gitdir = PathBuf::from(dotgit.strip_prefix("gitdir:")?.trim());
+        let commondir = fs::read_to_string(gitdir.join("commondir")).ok()?;
+
+        let common = gitdir.join(commondir.trim()).canonicalize().ok()?;
+        let worktree = gitdir.canonicalize().ok()?.strip_prefix(&common).ok()?.to_path_buf();
+        Some(Self { common, worktree, branch_dir: None })
+    }
+
+    /// Let the jail update `branch`, the branch its worktree was created on
+    ///
+    /// Only the branch's directory is made writable, so this needs a branch
+    /// with a directory of its own below `robojail/`. Jails created before
+    /// branches had one keep theirs read-only.
+    pub fn with_branch(mut self, branch: &str) -> Self {
+        let dir = Path::new(branch).parent().filter(|dir| dir.starts_with("robojail") && *dir != Path::new("robojail"));
+        match dir {
+            Some(dir) => self.branch_dir = Some(dir.to_path_buf()),
+            None => eprintln!(
+                "warning: branch '{branch}' has no directory of its own and stays read-only in the jail; \
+                 rename the jail with --rename-branch to move it"
+            ),
+        }
+        self
+    }
+
+    fn gitdir(&self) -> PathBuf {
+        self.common.join(&self.worktree)
+    }
+
+    /// Upper layer of the object store overlay
+    fn objects_upper(&self) -> PathBuf {
+        self.gitdir().join("robojail/objects")
+    }
+
+    fn objects_work(&self) -> PathBuf {
+        self.gitdir().join("robojail/work")
+    }
+
+    /// Ref and reflog directories the jail may write, relative to `common`
+    fn writable_refs(&self) -> Vec<PathBuf> {
+        let Some(ref dir) = self.branch_dir else {
+            return vec![];
+        };
+        vec![Path::new("refs/heads").join(dir), Path::new("logs/refs/heads").join(dir)]
+    }
+
+    fn alternates_file(&self) -> PathBuf {
+        self.common.join("objects/info/alternates")
+    }
+
+    /// The upper layer as an alternate, relative to the main object store
+    ///
+    /// Relative, so it resolves both on the host and inside the jail.
+    fn alternate(&self) -> String {
+        Path::new("..").join(&self.worktree).join("robojail/objects").to_string_lossy().to_string()
+    }
+
+    /// Create the writable directories and register the jail's objects with the main repository
+    pub fn prepare(&self) -> Result<()> {
+        self.create_dirs()?;
+
+        // Other jails of the repository register and unregister concurrently
+        let _lock = State::lock()?;
+        let file = self.alternates_file();
+        let alternates = fs::read_to_string(&file).unwrap_or_default();
+        if !alternates.lines().any(|line| line == self.alternate()) {
+            if let Some(dir) = file.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let mut out = fs::OpenOptions::new().create(true).append(true).open(&file)?;
+            if !alternates.is_empty() && !alternates.ends_with('\n') {
+                writeln!(out)?;
+            }
+            writeln!(out, "{}", self.alternate())?;
+        }
+
+        Ok(())
+    }
+
+    /// Create what the jail's writable mounts go on
+    fn create_dirs(&self) -> Result<()> {
+        fs::create_dir_all(self.objects_upper())?;
+        fs::create_dir_all(self.objects_work())?;
+        for dir in self.writable_refs() {
+            fs::create_dir_all(self.common.join(dir))?;
+        }
+        // Git ignores an empty config.worktree; it only exists to be mounted over
+        for name in PROTECTED_FILES {
+            let path = self.gitdir().join(name);
+            if !path.exists() {
+                fs::write(path, "")?;
+            }
+        }
+        // The jail's mount points get excluded in a copy mounted over this one
+        let exclude = self.common.join("info/exclude");
+        if !exclude.exists() {
+            fs::create_dir_all(self.common.join("info"))?;
+            fs::write(exclude, "")?;
+        }
+        Ok(())
+    }
+
+    /// Mount the repository into the jail's new root (before pivoting)
+    ///
+    /// `scratch` is a jail-side directory on a tmpfs for the replacement
+    /// `.git` file. Objects stay read-only if the overlay can't be mounted.
+    /// The writable mounts are added to `plan` as well.
+    pub fn mount(&self, new_root: &Path, scratch: &Path, plan: &mut Ruleset) -> Result<()> {
+        let jail_dir = Path::new(JAIL_GIT_DIR);
+        let target = new_root.join(JAIL_GIT_DIR.trim_start_matches('/'));
+        fs::create_dir_all(&target)?;
+        mount::bind_mount(&self.common, &target, true)?;
+        plan.allow(jail_dir, Access::ReadExec);
+
+        mount::bind_mount(&self.gitdir(), &target.join(&self.worktree), false)?;
+        for name in PROTECTED_FILES {
+            let file = target.join(&self.worktree).join(name);
+            mount::bind_mount(&file, &file, true)?;
+        }
+        plan.allow(jail_dir.join(&self.worktree), Access::ReadWrite);
+        for dir in self.writable_refs() {
+            mount::bind_mount(&self.common.join(&dir), &target.join(&dir), false)?;
+            plan.allow(jail_dir.join(dir), Access::ReadWrite);
+        }
+
+        let objects = self.common.join("objects");
+        match mount::mount_overlay(&objects, &self.objects_upper(), &self.objects_work(), &target.join("objects")) {
+            Ok(()) => plan.allow(jail_dir.join("objects"), Access::ReadWrite),
+            Err(e) => eprintln!("warning: {e}; git can't create objects inside the jail"),
+        }
+
+        // Point the worktree at the mounted gitdir
+        let dotgit = scratch.join("dotgit");
+        let gitdir = Path::new(JAIL_GIT_DIR).join(&self.worktree);
+        fs::write(&dotgit, format!("gitdir: {}\n", gitdir.display()))?;
+        mount::bind_mount(&dotgit, &new_root.join(".git"), true)?;
+        plan.allow("/.git", Access::ReadExec);
+
+        Ok(())
+    }
+
+    /// Move the jail's objects into the main store and unregister them
+    ///
+    /// Must run before the worktree's gitdir is removed, or commits on the
+    /// jail's branch lose their objects.
+    pub fn release(&self) -> Result<()> {
+        let upper = self.objects_upper();
+        if upper.exists() {
+            import_objects(&upper, &self.common.join("objects")).map_err(|e| {
+                Error::GitCommand(format!("failed to move the jail's git objects into the repository: {e}"))
+            })?;
+        }
+
+        let _lock = State::lock()?;
+        let file = self.alternates_file();
+        if let Ok(alternates) = fs::read_to_string(&file) {
+            let alternate = self.alternate();
+            let kept: Vec<&str> = alternates.lines().filter(|line| *line != alternate).collect();
+            if kept.is_empty() {
+                fs::remove_file(&file)?;
+            } else {
+                fs::write(&file, kept.join("\n") + "\n")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Copy objects the main store doesn't have yet
+///
+/// Pack indexes go last, so a pack is never visible before its data.
+/// Whiteouts left by the overlay are skipped, and so is `info`, which
+/// holds object store configuration rather than objects.
+fn import_objects(from: &Path, to: &Path) -> std::io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(from)?
+        .filter(|entry| entry.as_ref().map_or(true, |entry| entry.file_name() != "info"))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.path().extension().is_some_and(|ext| ext == "idx"));
+
+    for entry in entries {
+        let kind = entry.file_type()?;
+        let dest = to.join(entry.file_name());
+
+        if kind.is_dir() {
+            fs::create_dir_all(&dest)?;
+            import_objects(&entry.path(), &dest)?;
+        } else if kind.is_file() && !dest.exists() && fs::hard_link(entry.path(), &dest).is_err() {
+            fs::copy(entry.path(), &dest)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::tests::{spawn_jail, wait_jail};
+    use crate::sandbox::SandboxBuilder;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> Option<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    #[test]
+    fn test_jail_cannot_update_other_branches() {
+        let root = std::env::temp_dir().join(format!("robojail-git-test-{}", uuid::Uuid::new_v4()));
+        let (repo, own) = (root.join("repo"), root.join("own"));
+        fs::create_dir_all(&repo).unwrap();
+        let created = git(&repo, &["init", "-q"])
+            .and_then(|_| git(&repo, &["commit", "-q", "--allow-empty", "-m", "first"]))
+            .and_then(|_| git(&repo, &["commit", "-q", "--allow-empty", "-m", "second"]))
+            .and_then(|_| git(&repo, &["worktree", "add", "-q", "-b", "robojail/own-1111/work", "../own"]))
+            .and_then(|_| git(&repo, &["worktree", "add", "-q", "-b", "robojail/other-2222/work", "../other"]));
+        if created.is_none() {
+            let _ = fs::remove_dir_all(&root);
+            eprintln!("skipping: git is unavailable");
+            return;
+        }
+
+        let layout = GitLayout::discover(&own).unwrap().with_branch("robojail/own-1111/work");
+        layout.create_dirs().unwrap();
+        let script = "git update-ref refs/heads/robojail/other-2222/work HEAD~1 2>/dev/null && exit 10; \
+                      git update-ref refs/heads/robojail/own-1111/work HEAD~1 || exit 11";
+        let child = spawn_jail(|| SandboxBuilder::new(&own).git(layout).build(), &["sh", "-c", script]);
+
+        let code = wait_jail(child);
+        let rev = |rev: &str| git(&repo, &["rev-parse", rev]);
+        let (own_branch, other_branch) = (rev("robojail/own-1111/work"), rev("robojail/other-2222/work"));
+        let (first, second) = (rev("HEAD~1"), rev("HEAD"));
+        fs::remove_dir_all(&root).unwrap();
+        let Some(code) = code else { return };
+
+        assert_eq!(code, 0);
+        assert_eq!(own_branch, first);
+        assert_eq!(other_branch, second);
+    }
+
+    #[test]
+    fn test_legacy_branch_stays_read_only() {
+        let layout = GitLayout { common: "/repo/.git".into(), worktree: "worktrees/a".into(), branch_dir: None };
+        assert!(layout.clone().with_branch("robojail/a-1234").writable_refs().is_empty());
+        assert_eq!(
+            layout.with_branch("robojail/a-1234/work").writable_refs(),
+            [PathBuf::from("refs/heads/robojail/a-1234"), PathBuf::from("logs/refs/heads/robojail/a-1234")]
+        );
+    }
+
+    #[test]
+    fn test_import_objects() {
+        let root = std::env::temp_dir().join(format!("robojail-git-test-{}", std::process::id()));
+        let (from, to) = (root.join("from"), root.join("to"));
+        for dir in [from.join("ab"), from.join("info"), from.join("pack"), to.join("ab")] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(from.join("ab/cdef"), "new").unwrap();
+        fs::write(from.join("ab/0123"), "jail").unwrap();
+        fs::write(to.join("ab/0123"), "main").unwrap();
+        fs::write(from.join("info/alternates"), "/elsewhere").unwrap();
+        fs::write(from.join("pack/pack-1.pack"), "pack").unwrap();
+        fs::write(from.join("pack/pack-1.idx"), "idx").unwrap();
+
+        import_objects(&from, &to).unwrap();
+        let read = |path: &str| fs::read_to_string(to.join(path)).ok();
+        let results = (read("ab/cdef"), read("ab/0123"), read("pack/pack-1.idx"), to.join("info").exists());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(results.0.as_deref(), Some("new"));
+        assert_eq!(results.1.as_deref(), Some("main"));
+        assert_eq!(results.2.as_deref(), Some("idx"));
+        assert!(!results.3);
+    }
+}
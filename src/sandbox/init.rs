@@ -0,0 +1,102 @@
+//! Minimal init process for the sandbox PID namespace
+//!
+//! The first process in a new PID namespace becomes its init: orphaned
+//! processes are reparented to it, and the kernel only delivers signals to it
+//! for which it has installed a handler. Rather than running the jailed
+//! command as PID 1, we keep a tiny reaper that forwards signals to the
+//! command and collects zombies until the command exits.
+
+use crate::error::{Error, Result};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{fork, ForkResult, Pid};
+
+/// Signals forwarded from init to the jailed command
+const FORWARDED_SIGNALS: [Signal; 8] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGWINCH,
+    Signal::SIGCONT,
+];
+
+extern "C" fn noop_handler(_: libc::c_int) {}
+
+/// Run as PID 1: fork the command and reap until it exits
+///
+/// `exec_child` runs in the forked child with default signal dispositions
+/// restored; it is expected to exec and only returns on failure.
+/// `start_services` runs in init once the child is forked, so any threads it
+/// starts never exist in the child.
+pub fn run(exec_child: impl FnOnce() -> Result<()>, start_services: impl FnOnce(Pid)) -> Result<i32> {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGCHLD);
+    for sig in FORWARDED_SIGNALS {
+        mask.add(sig);
+    }
+
+    // Install handlers so the kernel doesn't drop these signals for PID 1,
+    // then block them so we can collect them synchronously with sigwait.
+    let handler = SigAction::new(SigHandler::Handler(noop_handler), SaFlags::empty(), SigSet::empty());
+    for sig in mask.iter() {
+        unsafe { sigaction(sig, &handler) }?;
+    }
+    mask.thread_block()?;
+
+    let child = match unsafe { fork() }? {
+        ForkResult::Parent { child } => {
+            // Release whatever the child's half holds on to
+            drop(exec_child);
+            child
+        }
+        ForkResult::Child => {
+            let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+            for sig in mask.iter() {
+                let _ = unsafe { sigaction(sig, &default) };
+            }
+            let _ = nix::sys::signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(&SigSet::empty()), None);
+
+            if let Err(e) = exec_child() {
+                eprintln!("sandbox setup failed: {e}");
+            }
+            std::process::exit(126);
+        }
+    };
+
+    start_services(child);
+
+    loop {
+        let sig = mask.wait().map_err(|e| Error::SandboxSetup(format!("sigwait failed: {e}")))?;
+
+        if sig != Signal::SIGCHLD {
+            let _ = nix::sys::signal::kill(child, sig);
+            continue;
+        }
+
+        if let Some(code) = reap(child)? {
+            return Ok(code);
+        }
+    }
+}
+
+/// Reap all exited children, returning the exit code if `child` was among them
+fn reap(child: Pid) -> Result<Option<i32>> {
+    let mut result = None;
+
+    loop {
+        match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::Exited(pid, code)) if pid == child => result = Some(code),
+            Ok(WaitStatus::Signaled(pid, sig, _)) if pid == child => result = Some(128 + sig as i32),
+            Ok(WaitStatus::StillAlive) => break,
+            Ok(_) => continue,
+            Err(nix::Error::EINTR) => continue,
+            Err(nix::Error::ECHILD) => break,
+            Err(e) => return Err(Error::Nix(e)),
+        }
+    }
+
+    Ok(result)
+}
@@ -0,0 +1,351 @@
+//! Landlock filesystem restrictions
+//!
+//! A second layer of isolation behind the mount namespace. While the jail's
+//! filesystem is assembled we record each mount together with the access it
+//! should allow; right before exec that plan is turned into a Landlock
+//! ruleset and enforced on the command.
+//!
+//! Landlock rights are inherited down the file hierarchy, including across
+//! mount points. The worktree is the jail's root and needs a read-write rule
+//! so that new entries at the top of the project are usable, and that rule
+//! reaches every mount below it as well. Those mounts are therefore made
+//! read-only at the mount level (the sandbox remounts its /etc, /run and /dev
+//! read-only once they are set up), and the rules on them only matter for
+//! what Landlock alone can keep from a read-write tree: device nodes can't be
+//! created, device ioctls are confined to /dev, and (ABI 6+) the jail can
+//! neither reach abstract Unix sockets nor signal processes outside of it.
+//!
+//! The ruleset is enforced in the jail, so the process that enforced it
+//! reports the ABI it applied back to the host through a pipe.
+
+use crate::error::{Error, Result};
+use nix::fcntl::{open, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::pipe2;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Filesystem access rights (ABI 1 unless noted)
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+/// ABI 2
+const ACCESS_FS_REFER: u64 = 1 << 13;
+/// ABI 3
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+/// ABI 5
+const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+/// Rights that may be granted on a non-directory
+const FILE_RIGHTS: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_IOCTL_DEV;
+
+// Scopes (ABI 6)
+const SCOPE_ABSTRACT_UNIX_SOCKET: u64 = 1 << 0;
+const SCOPE_SIGNAL: u64 = 1 << 1;
+
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+    handled_access_net: u64,
+    scoped: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// What a path in the mount plan may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// Project files, scratch space and read-write binds
+    ReadWrite,
+    /// System directories, /etc and read-only binds
+    ReadExec,
+    /// The minimal /dev (device files and their ioctls)
+    Device,
+    /// The jail's procfs
+    Proc,
+}
+
+impl Access {
+    fn rights(self) -> u64 {
+        match self {
+            Access::ReadWrite => {
+                ACCESS_FS_EXECUTE
+                    | ACCESS_FS_WRITE_FILE
+                    | ACCESS_FS_READ_FILE
+                    | ACCESS_FS_READ_DIR
+                    | ACCESS_FS_REMOVE_DIR
+                    | ACCESS_FS_REMOVE_FILE
+                    | ACCESS_FS_MAKE_DIR
+                    | ACCESS_FS_MAKE_REG
+                    | ACCESS_FS_MAKE_SOCK
+                    | ACCESS_FS_MAKE_FIFO
+                    | ACCESS_FS_MAKE_SYM
+                    | ACCESS_FS_REFER
+                    | ACCESS_FS_TRUNCATE
+            }
+            Access::ReadExec => ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR,
+            Access::Device => {
+                ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_DIR | ACCESS_FS_IOCTL_DEV
+            }
+            Access::Proc => ACCESS_FS_READ_FILE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_DIR,
+        }
+    }
+}
+
+/// Filesystem rights the kernel understands at a given ABI version
+fn handled_access_fs(abi: u32) -> u64 {
+    let mut access = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_READ_DIR
+        | ACCESS_FS_REMOVE_DIR
+        | ACCESS_FS_REMOVE_FILE
+        | ACCESS_FS_MAKE_CHAR
+        | ACCESS_FS_MAKE_DIR
+        | ACCESS_FS_MAKE_REG
+        | ACCESS_FS_MAKE_SOCK
+        | ACCESS_FS_MAKE_FIFO
+        | ACCESS_FS_MAKE_BLOCK
+        | ACCESS_FS_MAKE_SYM;
+
+    if abi >= 2 {
+        access |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_FS_TRUNCATE;
+    }
+    if abi >= 5 {
+        access |= ACCESS_FS_IOCTL_DEV;
+    }
+
+    access
+}
+
+/// Query the highest Landlock ABI version supported by the running kernel
+///
+/// Returns None if Landlock is not built in or disabled at boot.
+pub fn abi_version() -> Option<u32> {
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            CREATE_RULESET_VERSION,
+        )
+    };
+
+    if result > 0 {
+        Some(result as u32)
+    } else {
+        None
+    }
+}
+
+/// A set of paths and the access allowed beneath each, built from the mount plan
+#[derive(Debug, Clone, Default)]
+pub struct Ruleset {
+    rules: Vec<(PathBuf, Access)>,
+}
+
+impl Ruleset {
+    /// Create an empty ruleset
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow `access` to everything beneath `path` (a path inside the jail)
+    pub fn allow(&mut self, path: impl Into<PathBuf>, access: Access) {
+        self.rules.push((path.into(), access));
+    }
+
+    /// Enforce the ruleset on the calling thread and its future children
+    ///
+    /// Degrades gracefully: rights and scopes the kernel doesn't know about
+    /// are dropped, and nothing is enforced if Landlock is unavailable.
+    /// Returns the ABI version that was applied.
+    pub fn restrict_self(&self) -> Result<Option<u32>> {
+        let Some(abi) = abi_version() else {
+            return Ok(None);
+        };
+
+        let handled = handled_access_fs(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+            handled_access_net: 0,
+            scoped: if abi >= 6 { SCOPE_ABSTRACT_UNIX_SOCKET | SCOPE_SIGNAL } else { 0 },
+        };
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if fd < 0 {
+            return Err(landlock_error("landlock_create_ruleset"));
+        }
+        let ruleset_fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        for (path, access) in &self.rules {
+            // Plan entries for optional mounts may not exist on this host
+            let Ok(path_fd) = open_path(path) else {
+                continue;
+            };
+
+            let mut allowed = access.rights() & handled;
+            if !path.is_dir() {
+                allowed &= FILE_RIGHTS;
+            }
+
+            let rule = PathBeneathAttr {
+                allowed_access: allowed,
+                parent_fd: path_fd.as_raw_fd(),
+            };
+
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset_fd.as_raw_fd(),
+                    RULE_PATH_BENEATH,
+                    &rule as *const PathBeneathAttr,
+                    0u32,
+                )
+            };
+            if result != 0 {
+                return Err(landlock_error(&format!("landlock_add_rule for {}", path.display())));
+            }
+        }
+
+        let result = unsafe {
+            libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd.as_raw_fd(), 0u32)
+        };
+        if result != 0 {
+            return Err(landlock_error("landlock_restrict_self"));
+        }
+
+        Ok(Some(abi))
+    }
+}
+
+/// Called on the host with the ABI applied to a jail's command, if any
+#[derive(Clone)]
+pub struct Report(Arc<dyn Fn(Option<u32>) + Send + Sync>);
+
+impl fmt::Debug for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Report").finish_non_exhaustive()
+    }
+}
+
+impl Report {
+    pub fn new(report: impl Fn(Option<u32>) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(report))
+    }
+
+    pub fn send(&self, abi: Option<u32>) {
+        (self.0)(abi)
+    }
+}
+
+/// Create the pipe through which the jail tells the host what it applied
+pub fn status_pair() -> Result<(HostStatus, JailStatus)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+    Ok((HostStatus(File::from(read)), JailStatus(File::from(write))))
+}
+
+/// Host end of the applied ABI report
+#[derive(Debug)]
+pub struct HostStatus(File);
+
+impl HostStatus {
+    /// Wait for the applied ABI
+    ///
+    /// None if Landlock wasn't applied, including when the jail failed
+    /// before getting to it.
+    pub fn wait(mut self) -> Option<u32> {
+        let mut abi = [0u8; 4];
+        self.0.read_exact(&mut abi).ok()?;
+        Some(u32::from_le_bytes(abi)).filter(|&abi| abi > 0)
+    }
+}
+
+/// Jail end of the applied ABI report
+#[derive(Debug)]
+pub struct JailStatus(File);
+
+impl JailStatus {
+    pub fn send(mut self, abi: Option<u32>) {
+        let _ = self.0.write_all(&abi.unwrap_or(0).to_le_bytes());
+    }
+}
+
+fn open_path(path: &Path) -> Result<OwnedFd> {
+    let fd = open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty())?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn landlock_error(what: &str) -> Error {
+    Error::SandboxSetup(format!("{what} failed: {}", std::io::Error::last_os_error()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handled_access_by_abi() {
+        assert_eq!(handled_access_fs(1) & ACCESS_FS_REFER, 0);
+        assert_ne!(handled_access_fs(2) & ACCESS_FS_REFER, 0);
+        assert_eq!(handled_access_fs(4) & ACCESS_FS_IOCTL_DEV, 0);
+        assert_ne!(handled_access_fs(5) & ACCESS_FS_IOCTL_DEV, 0);
+    }
+
+    #[test]
+    fn test_status_reports_applied_abi() {
+        let (host, jail) = status_pair().unwrap();
+        jail.send(Some(4));
+        assert_eq!(host.wait(), Some(4));
+
+        let (host, jail) = status_pair().unwrap();
+        jail.send(None);
+        assert_eq!(host.wait(), None);
+
+        let (host, jail) = status_pair().unwrap();
+        drop(jail);
+        assert_eq!(host.wait(), None);
+    }
+
+    #[test]
+    fn test_worktree_cannot_create_devices() {
+        let rights = Access::ReadWrite.rights();
+        assert_eq!(rights & (ACCESS_FS_MAKE_CHAR | ACCESS_FS_MAKE_BLOCK), 0);
+        assert_eq!(rights & ACCESS_FS_IOCTL_DEV, 0);
+    }
+}
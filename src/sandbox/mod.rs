@@ -1,21 +1,57 @@
+mod git;
+mod init;
+mod landlock;
 mod mount;
 mod namespace;
+mod netguard;
+mod ports;
+mod proxy;
+mod seccomp;
 mod security;
+mod slirp;
+mod ticker;
 
-use crate::config::Config;
+use crate::cgroup::{self, Cgroup, ResourceLimits};
+use crate::config::{Config, NetworkMode, SeccompProfile};
 use crate::error::{Error, Result};
+use crate::state::{JailInfo, JailMode, State};
+use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd::{fork, ForkResult, Pid};
 use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+pub use git::GitLayout;
+pub use netguard::{NetworkPolicy, DEFAULT_BLOCKED, DEFAULT_EXCEPTIONS};
+pub use ports::{check_unique as check_unique_ports, update_running as update_running_ports, PortMapping};
+pub use ticker::Periodic;
 
 /// Sandbox configuration builder
 #[derive(Debug, Clone)]
 pub struct SandboxBuilder {
     /// Root directory of the sandbox (the worktree)
     root: PathBuf,
-    /// Whether to share network with host
-    share_net: bool,
+    /// How the sandbox is connected to the network
+    network: NetworkMode,
+    /// Proxy socket and allowlist for the proxy network mode
+    proxy: Option<ProxySettings>,
+    /// Destinations blocked in the filtered network mode, and exceptions
+    network_rules: (Vec<String>, Vec<String>),
+    /// Where proxied and denied connections are logged
+    network_log: Option<PathBuf>,
+    /// Control socket and initial mappings of the published ports
+    ports: Option<PortSettings>,
+    /// Lower, upper and work directories when the root is an overlay
+    overlay: Option<(PathBuf, PathBuf, PathBuf)>,
+    /// Repository pieces to mount so git works inside a worktree jail
+    git: Option<GitLayout>,
     /// Additional read-only bind mounts
     ro_binds: Vec<(PathBuf, PathBuf)>,
     /// Additional read-write bind mounts
@@ -24,6 +60,20 @@ pub struct SandboxBuilder {
     env: Vec<(String, String)>,
     /// Working directory inside sandbox
     workdir: PathBuf,
+    /// Built-in seccomp profile
+    seccomp_profile: SeccompProfile,
+    /// Additional syscalls to deny
+    seccomp_deny: Vec<String>,
+    /// Whether to enforce a Landlock ruleset built from the mount plan
+    landlock: bool,
+    /// Told which Landlock ABI the command runs under
+    landlock_report: Option<landlock::Report>,
+    /// Name of the jail cgroup to run in
+    cgroup: Option<String>,
+    /// Resource limits applied to the cgroup
+    limits: ResourceLimits,
+    /// Host-side task repeated while the jail runs
+    periodic: Option<Periodic>,
 }
 
 impl SandboxBuilder {
@@ -31,17 +81,33 @@ impl SandboxBuilder {
     pub fn new(root: impl Into<PathBuf>) -> Self {
         Self {
             root: root.into(),
-            share_net: true,
+            network: NetworkMode::Host,
+            proxy: None,
+            network_rules: (vec![], vec![]),
+            network_log: None,
+            ports: None,
+            overlay: None,
+            git: None,
             ro_binds: vec![],
             rw_binds: vec![],
             env: vec![],
             workdir: PathBuf::from("/"),
+            seccomp_profile: SeccompProfile::Default,
+            seccomp_deny: vec![],
+            landlock: false,
+            landlock_report: None,
+            cgroup: None,
+            limits: ResourceLimits::default(),
+            periodic: None,
         }
     }
 
     /// Configure from a Config object
     pub fn with_config(mut self, config: &Config) -> Self {
-        self.share_net = config.network_enabled;
+        self.network = config.network_mode();
+        self.seccomp_profile = config.seccomp_profile;
+        self.seccomp_deny = config.seccomp_deny.clone();
+        self.landlock = config.landlock;
 
         // Add extra binds from config
         for path in &config.extra_ro_binds {
@@ -64,7 +130,69 @@ impl SandboxBuilder {
     /// Set whether to share network
     #[allow(dead_code)]
     pub fn share_net(mut self, share: bool) -> Self {
-        self.share_net = share;
+        self.network = if share { NetworkMode::Host } else { NetworkMode::None };
+        self
+    }
+
+    /// Set the network mode
+    pub fn network(mut self, mode: NetworkMode) -> Self {
+        self.network = mode;
+        self
+    }
+
+    /// Serve the proxy network mode from a socket in `dir`, allowing only `allow`
+    ///
+    /// `dir` is mounted into the jail, and `lock` picks the session serving it.
+    pub fn proxy(mut self, dir: impl Into<PathBuf>, lock: impl Into<PathBuf>, allow: Vec<String>) -> Self {
+        self.proxy = Some(ProxySettings {
+            dir: dir.into(),
+            lock: lock.into(),
+            allow,
+        });
+        self
+    }
+
+    /// Set the destinations the filtered network mode blocks, and exceptions
+    pub fn network_rules(mut self, blocked: Vec<String>, exceptions: Vec<String>) -> Self {
+        self.network_rules = (blocked, exceptions);
+        self
+    }
+
+    /// Log proxied and denied connections to `path`
+    pub fn network_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.network_log = Some(path.into());
+        self
+    }
+
+    /// Publish jail ports on the host, taking updates on the `socket` control socket
+    ///
+    /// `lock` picks the session publishing them, which gets the ports to
+    /// publish from `mappings` when it starts to.
+    pub fn publish(
+        mut self,
+        socket: impl Into<PathBuf>,
+        lock: impl Into<PathBuf>,
+        mappings: impl Fn() -> Vec<PortMapping> + Send + Sync + 'static,
+    ) -> Self {
+        self.ports = Some(PortSettings {
+            socket: socket.into(),
+            lock: lock.into(),
+            mappings: ports::Mappings::new(mappings),
+        });
+        self
+    }
+
+    /// Use a copy-on-write overlay of `lower` as the root instead of binding it
+    ///
+    /// Changes are written to `upper`; `work` is overlayfs scratch space.
+    pub fn overlay(mut self, lower: impl Into<PathBuf>, upper: impl Into<PathBuf>, work: impl Into<PathBuf>) -> Self {
+        self.overlay = Some((lower.into(), upper.into(), work.into()));
+        self
+    }
+
+    /// Give the jail git access to the worktree's repository
+    pub fn git(mut self, layout: GitLayout) -> Self {
+        self.git = Some(layout);
         self
     }
 
@@ -82,6 +210,25 @@ impl SandboxBuilder {
         self
     }
 
+    /// Report the Landlock ABI applied to the command, or None, once it is known
+    pub fn on_landlock(mut self, report: impl Fn(Option<u32>) + Send + Sync + 'static) -> Self {
+        self.landlock_report = Some(landlock::Report::new(report));
+        self
+    }
+
+    /// Run in the named jail cgroup with the given limits
+    pub fn cgroup(mut self, name: impl Into<String>, limits: ResourceLimits) -> Self {
+        self.cgroup = Some(name.into());
+        self.limits = limits;
+        self
+    }
+
+    /// Run a task on the host at a fixed interval while the jail runs
+    pub fn every(mut self, periodic: Periodic) -> Self {
+        self.periodic = Some(periodic);
+        self
+    }
+
     /// Set an environment variable
     pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
         self.env.push((key.into(), value.into()));
@@ -98,24 +245,66 @@ impl SandboxBuilder {
     pub fn build(self) -> Sandbox {
         Sandbox {
             root: self.root,
-            share_net: self.share_net,
+            network: self.network,
+            proxy: self.proxy,
+            network_rules: self.network_rules,
+            network_log: self.network_log,
+            ports: self.ports,
+            overlay: self.overlay,
+            git: self.git,
             ro_binds: self.ro_binds,
             rw_binds: self.rw_binds,
             env: self.env,
             workdir: self.workdir,
+            seccomp_profile: self.seccomp_profile,
+            seccomp_deny: self.seccomp_deny,
+            landlock: self.landlock,
+            landlock_report: self.landlock_report,
+            cgroup: self.cgroup,
+            limits: self.limits,
+            periodic: self.periodic,
         }
     }
 }
 
+/// Host side of the proxy network mode
+#[derive(Debug, Clone)]
+struct ProxySettings {
+    dir: PathBuf,
+    lock: PathBuf,
+    allow: Vec<String>,
+}
+
+/// Host side of the published ports
+#[derive(Debug, Clone)]
+struct PortSettings {
+    socket: PathBuf,
+    lock: PathBuf,
+    mappings: ports::Mappings,
+}
+
 /// A configured sandbox ready to run
 #[derive(Debug)]
 pub struct Sandbox {
     root: PathBuf,
-    share_net: bool,
+    network: NetworkMode,
+    proxy: Option<ProxySettings>,
+    network_rules: (Vec<String>, Vec<String>),
+    network_log: Option<PathBuf>,
+    ports: Option<PortSettings>,
+    overlay: Option<(PathBuf, PathBuf, PathBuf)>,
+    git: Option<GitLayout>,
     ro_binds: Vec<(PathBuf, PathBuf)>,
     rw_binds: Vec<(PathBuf, PathBuf)>,
     env: Vec<(String, String)>,
     workdir: PathBuf,
+    seccomp_profile: SeccompProfile,
+    seccomp_deny: Vec<String>,
+    landlock: bool,
+    landlock_report: Option<landlock::Report>,
+    cgroup: Option<String>,
+    limits: ResourceLimits,
+    periodic: Option<Periodic>,
 }
 
 impl Sandbox {
@@ -136,24 +325,199 @@ impl Sandbox {
             return Err(Error::SandboxSetup("no command specified".to_string()));
         }
 
+        // Compile the seccomp filter up front so config errors surface here
+        let filter = seccomp::Filter::new(self.seccomp_profile, &self.seccomp_deny)?;
+
+        // Create the jail cgroup and apply limits before anything is forked
+        let cgroup = self.prepare_cgroup()?;
+
+        // The jail's object layer must be registered before git runs on either side
+        if let Some(ref git) = self.git {
+            git.prepare()?;
+        }
+
+        // Bind the proxy socket now so it exists before the jail looks for it
+        let proxy = self.bind_proxy()?;
+
+        // Published ports are served from out here as well
+        let (forwarder, dialer) = self.bind_ports()?;
+
+        // Userspace networking is attached from out here once the jail has
+        // its network namespace
+        let network_sync = match self.network {
+            NetworkMode::Slirp => Some((slirp::find_helper()?, slirp::sync_pair()?)),
+            _ => None,
+        };
+
+        // The command's process reports back which Landlock ABI it applied
+        let landlock_status = if self.landlock { Some(landlock::status_pair()?) } else { None };
+
+        // Like system(3), ignore keyboard interrupts while the jail runs; the
+        // jail's init gets them from the terminal and forwards them. This is
+        // done before forking so that none of the processes between us and
+        // init can be killed by them, which would take the whole jail down.
+        let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        let old_int = unsafe { sigaction(Signal::SIGINT, &ignore) }?;
+        let old_quit = unsafe { sigaction(Signal::SIGQUIT, &ignore) }?;
+        let restore = || -> Result<()> {
+            unsafe {
+                sigaction(Signal::SIGINT, &old_int)?;
+                sigaction(Signal::SIGQUIT, &old_quit)?;
+            }
+            Ok(())
+        };
+
         // Fork the process
         match unsafe { fork() } {
             Ok(ForkResult::Parent { child }) => {
+                let proxy = proxy.map(|mut proxy| {
+                    proxy.spawn();
+                    proxy
+                });
+                drop(dialer);
+                let forwarder = forwarder.map(|mut forwarder| {
+                    if let Some(ref settings) = self.ports {
+                        forwarder.spawn(settings.mappings.clone());
+                    }
+                    forwarder
+                });
+                let ticker = self.periodic.as_ref().map(Periodic::spawn);
+
+                let network = match network_sync {
+                    Some((helper, (host, jail))) => {
+                        drop(jail);
+                        attach_slirp(&helper, host, child)
+                    }
+                    None => Ok(None),
+                };
+
+                let abi = landlock_status.and_then(|(host, jail)| {
+                    drop(jail);
+                    host.wait()
+                });
+                if let Some(ref report) = self.landlock_report {
+                    report.send(abi);
+                }
+
                 // Parent: wait for child
-                self.wait_for_child(child)
+                let result = self.wait_for_child(child);
+                if let Some(ticker) = ticker {
+                    ticker.stop();
+                }
+                restore()?;
+
+                if let Some(ref cgroup) = cgroup {
+                    cgroup.remove();
+                }
+                if let Some(proxy) = proxy {
+                    proxy.stop();
+                }
+                if let Some(forwarder) = forwarder {
+                    forwarder.stop();
+                }
+                network?;
+                result
             }
             Ok(ForkResult::Child) => {
+                // The proxy and published ports are served by the parent only
+                drop(proxy);
+                drop(forwarder);
+                let network_sync = network_sync.map(|(_, (host, jail))| {
+                    drop(host);
+                    jail
+                });
+                let landlock_status = landlock_status.map(|(host, jail)| {
+                    drop(host);
+                    jail
+                });
+
                 // Child: set up sandbox and exec
-                if let Err(e) = self.setup_and_exec(args) {
-                    eprintln!("sandbox setup failed: {e}");
-                    std::process::exit(126);
+                match self.setup_and_exec(args, filter.as_ref(), cgroup.as_ref(), network_sync, dialer, landlock_status) {
+                    Ok(code) => std::process::exit(code),
+                    Err(e) => {
+                        eprintln!("sandbox setup failed: {e}");
+                        std::process::exit(126);
+                    }
                 }
-                unreachable!()
             }
-            Err(e) => Err(Error::Nix(e)),
+            Err(e) => {
+                restore()?;
+                Err(Error::Nix(e))
+            }
         }
     }
 
+    /// Create the jail cgroup and apply its limits
+    ///
+    /// Limits are a safety feature, so failing to apply them is an error,
+    /// unless systemd delegates no cgroup to the user at all: then the jail
+    /// runs without them, with a warning. Without limits the cgroup only
+    /// serves usage accounting and is skipped if it can't be set up.
+    fn prepare_cgroup(&self) -> Result<Option<Cgroup>> {
+        let Some(ref name) = self.cgroup else {
+            return Ok(None);
+        };
+
+        let result = match Cgroup::for_jail(name) {
+            // Started outside of the user's service manager, e.g. over SSH
+            Err(Error::CgroupNotDelegated(_)) if !self.limits.is_empty() => {
+                cgroup::enter_user_scope().and_then(|()| Cgroup::for_jail(name))
+            }
+            result => result,
+        };
+        let result = result.and_then(|cgroup| {
+            cgroup.create(&self.limits)?;
+            Ok(cgroup)
+        });
+
+        match result {
+            Ok(cgroup) => Ok(Some(cgroup)),
+            Err(_) if self.limits.is_empty() => Ok(None),
+            Err(Error::CgroupNotDelegated(reason)) => {
+                eprintln!("warning: resource limits not applied ({}): {reason}", self.limits);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Bind the host side of the proxy network mode
+    fn bind_proxy(&self) -> Result<Option<proxy::ProxyServer>> {
+        if self.network != NetworkMode::Proxy {
+            return Ok(None);
+        }
+
+        let settings = self.proxy.as_ref().ok_or_else(|| {
+            Error::SandboxSetup("proxy network mode requires a proxy socket".to_string())
+        })?;
+        let server = proxy::ProxyServer::bind(&settings.dir, &settings.lock, settings.allow.clone(), self.network_log.as_deref())?;
+        Ok(Some(server))
+    }
+
+    /// Bind the control socket of the published ports
+    ///
+    /// Jails with a private network namespace also get a dial channel,
+    /// whose jail end is served by the jail's init. Every session sets both
+    /// up, so that it can take the ports over from the session publishing them.
+    fn bind_ports(&self) -> Result<(Option<ports::Forwarder>, Option<ports::JailDialer>)> {
+        let Some(ref settings) = self.ports else {
+            return Ok((None, None));
+        };
+
+        let (client, dialer) = if self.shares_host_network() {
+            (None, None)
+        } else {
+            let (client, dialer) = ports::dial_pair()?;
+            (Some(client), Some(dialer))
+        };
+        Ok((Some(ports::Forwarder::bind(&settings.socket, &settings.lock, client)?), dialer))
+    }
+
+    /// Whether the jail stays in the host's network namespace
+    fn shares_host_network(&self) -> bool {
+        matches!(self.network, NetworkMode::Host | NetworkMode::Filtered)
+    }
+
     /// Wait for child process and return exit code
     fn wait_for_child(&self, child: Pid) -> Result<i32> {
         loop {
@@ -170,24 +534,133 @@ impl Sandbox {
         }
     }
 
-    /// Set up the sandbox and exec the command (runs in child process)
-    fn setup_and_exec(&self, args: &[&str]) -> Result<()> {
+    /// Set up the sandbox and run the command (runs in child process)
+    ///
+    /// Creates the namespaces, then forks again so that the jail gets a
+    /// process that is PID 1 in its new PID namespace. Returns the exit
+    /// code of the jailed command.
+    fn setup_and_exec(
+        &self,
+        args: &[&str],
+        filter: Option<&seccomp::Filter>,
+        cgroup: Option<&Cgroup>,
+        network_sync: Option<slirp::JailSync>,
+        dialer: Option<ports::JailDialer>,
+        landlock_status: Option<landlock::JailStatus>,
+    ) -> Result<i32> {
+        // Tear the jail down if the robojail process that started it dies
+        namespace::die_with_parent()?;
+
+        // Join the jail cgroup while we still have our host credentials
+        if let Some(cgroup) = cgroup {
+            if let Err(e) = cgroup.add_self() {
+                if !self.limits.is_empty() {
+                    return Err(e);
+                }
+            }
+        }
+
         // Step 1: Create user namespace and set up UID/GID mapping
         namespace::setup_user_namespace()?;
 
-        // Step 2: Create other namespaces
-        namespace::setup_other_namespaces(self.share_net)?;
+        // Step 2: Create other namespaces (the PID namespace applies to our children)
+        let share_net = self.shares_host_network();
+        namespace::setup_other_namespaces(share_net)?;
+
+        // Private network namespaces start with loopback down
+        if !share_net {
+            namespace::bring_up_loopback()?;
+        }
+
+        // Wait for the host to attach userspace networking
+        if let Some(sync) = network_sync {
+            sync.wait_for_network()?;
+        }
+
+        // Step 3: Fork the jail's init process
+        match unsafe { fork() }? {
+            ForkResult::Parent { child } => {
+                drop(landlock_status);
+                self.wait_for_child(child)
+            }
+            ForkResult::Child => {
+                let code = match self.run_init(args, filter, dialer, landlock_status) {
+                    Ok(code) => code,
+                    Err(e) => {
+                        eprintln!("sandbox setup failed: {e}");
+                        126
+                    }
+                };
+                std::process::exit(code);
+            }
+        }
+    }
+
+    /// Run as PID 1 inside the jail: build the filesystem, then supervise the command
+    fn run_init(
+        &self,
+        args: &[&str],
+        filter: Option<&seccomp::Filter>,
+        dialer: Option<ports::JailDialer>,
+        landlock_status: Option<landlock::JailStatus>,
+    ) -> Result<i32> {
+        namespace::die_with_parent()?;
 
-        // Step 3: Set up mount namespace with filesystem
-        self.setup_filesystem()?;
+        // In filtered mode init supervises the command's socket syscalls;
+        // the log has to be opened while the host filesystem is visible
+        let guard = match self.network {
+            NetworkMode::Filtered => {
+                let (blocked, exceptions) = &self.network_rules;
+                let policy = netguard::NetworkPolicy::new(blocked, exceptions)?;
+                let log = self.network_log.as_deref().map(proxy::open_log).transpose()?;
+                Some(netguard::channel(policy, log)?)
+            }
+            _ => None,
+        };
+        let (supervisor, handoff) = guard.unzip();
 
-        // Step 4: Apply security hardening
+        // Step 4: Set up mount namespace with filesystem (including a private /proc)
+        let ruleset = self.setup_filesystem()?;
+        let landlock = landlock_status.map(|status| (ruleset, status));
+
+        // Bind the in-jail end of the proxy before the command can use it
+        let relay = match self.network {
+            NetworkMode::Proxy => Some(proxy::JailRelay::bind()?),
+            _ => None,
+        };
+
+        // Step 5: Fork the command under a minimal init
+        init::run(
+            move || self.exec_command(args, landlock, filter, handoff),
+            |child| {
+                if let Some(relay) = relay {
+                    relay.spawn();
+                }
+                if let Some(supervisor) = supervisor {
+                    supervisor.spawn(child);
+                }
+                if let Some(dialer) = dialer {
+                    dialer.spawn();
+                }
+            },
+        )
+    }
+
+    /// Harden the process and exec the command (runs as a child of the jail's init)
+    fn exec_command(
+        &self,
+        args: &[&str],
+        landlock: Option<(landlock::Ruleset, landlock::JailStatus)>,
+        filter: Option<&seccomp::Filter>,
+        handoff: Option<netguard::Handoff>,
+    ) -> Result<()> {
+        // Step 6: Apply security hardening
         security::apply_security_restrictions()?;
 
-        // Step 5: Change to working directory
+        // Step 7: Change to working directory
         std::env::set_current_dir(&self.workdir)?;
 
-        // Step 6: Set up environment
+        // Step 8: Set up environment
         // Clear environment first for security
         for (key, _) in std::env::vars() {
             std::env::remove_var(&key);
@@ -204,7 +677,7 @@ impl Sandbox {
             std::env::set_var(key, value);
         }
 
-        // Step 7: Exec the command
+        // Step 9: Prepare the command
         let program = CString::new(args[0]).map_err(|e| {
             Error::SandboxSetup(format!("invalid command: {e}"))
         })?;
@@ -215,6 +688,19 @@ impl Sandbox {
             .collect::<std::result::Result<Vec<_>, _>>()
             .map_err(|e| Error::SandboxSetup(format!("invalid argument: {e}")))?;
 
+        // Step 10: Restrict filesystem access with Landlock
+        if let Some((ruleset, status)) = landlock {
+            status.send(ruleset.restrict_self()?);
+        }
+
+        // Step 11: Install the syscall filter as the very last thing before exec
+        if let Some(filter) = filter {
+            filter.install()?;
+        }
+        if let Some(handoff) = handoff {
+            handoff.install(&seccomp::Filter::network_supervised())?;
+        }
+
         // Use execvp to search PATH
         nix::unistd::execvp(&program, &c_args)?;
 
@@ -222,7 +708,11 @@ impl Sandbox {
     }
 
     /// Set up the sandbox filesystem
-    fn setup_filesystem(&self) -> Result<()> {
+    ///
+    /// Returns the mount plan as a Landlock ruleset of jail-side paths.
+    fn setup_filesystem(&self) -> Result<landlock::Ruleset> {
+        let mut plan = landlock::Ruleset::new();
+
         // Make all mounts private first
         mount::make_mounts_private()?;
 
@@ -237,7 +727,16 @@ impl Sandbox {
         // First, bind mount the worktree as the base (this becomes /)
         // We do this by copying the worktree contents' view into the tmpfs
         // Actually, we need to bind mount the worktree content at the root
-        mount::bind_mount(&self.root, new_root, false)?;
+        // (or, for overlay jails, a copy-on-write view of the checkout)
+        match self.overlay {
+            Some((ref lower, ref upper, ref work)) => mount::mount_overlay(lower, upper, work, new_root)?,
+            None => mount::bind_mount(&self.root, new_root, false)?,
+        }
+
+        // New entries at the top of the project only get rights from a rule
+        // on the root, which reaches every mount below it too; those mounts
+        // are made read-only instead (see the end)
+        plan.allow("/", landlock::Access::ReadWrite);
 
         // Now overlay the system directories on top
         // These are read-only bind mounts
@@ -257,6 +756,7 @@ impl Sandbox {
                 let dst = new_root.join(dir.trim_start_matches('/'));
                 std::fs::create_dir_all(&dst)?;
                 mount::bind_mount(src, &dst, true)?;
+                plan.allow(*dir, landlock::Access::ReadExec);
             }
         }
 
@@ -264,9 +764,16 @@ impl Sandbox {
         let etc_dst = new_root.join("etc");
         std::fs::create_dir_all(&etc_dst)?;
         mount::mount_tmpfs(&etc_dst)?;
+        plan.allow("/etc", landlock::Access::ReadExec);
 
         // Copy essential etc files (but not passwd/group - we create our own)
         for file in &["resolv.conf", "hosts", "nsswitch.conf"] {
+            // The host's resolver is usually on its loopback, out of reach
+            if *file == "resolv.conf" && self.network == NetworkMode::Slirp {
+                std::fs::write(etc_dst.join(file), format!("nameserver {}\n", slirp::NAMESERVER))?;
+                continue;
+            }
+
             let src = Path::new("/etc").join(file);
             let dst = etc_dst.join(file);
             if src.exists() {
@@ -287,6 +794,7 @@ impl Sandbox {
         // Create home directory for the jail user
         let home_dst = new_root.join("home/user");
         std::fs::create_dir_all(&home_dst)?;
+        plan.allow("/home/user", landlock::Access::ReadWrite);
 
         // Bind mount /etc/ssl for TLS
         let ssl_src = Path::new("/etc/ssl");
@@ -304,20 +812,47 @@ impl Sandbox {
             mount::bind_mount(ca_src, &ca_dst, true)?;
         }
 
-        // Mount /proc
+        // Mount a fresh /proc that only shows the jail's PID namespace
         let proc_dst = new_root.join("proc");
         std::fs::create_dir_all(&proc_dst)?;
         mount::mount_proc(&proc_dst)?;
+        plan.allow("/proc", landlock::Access::Proc);
 
         // Mount /dev with minimal devices
         let dev_dst = new_root.join("dev");
         std::fs::create_dir_all(&dev_dst)?;
         mount::setup_dev(&dev_dst)?;
+        plan.allow("/dev", landlock::Access::Device);
+        plan.allow("/dev/shm", landlock::Access::ReadWrite);
 
         // Mount /tmp
         let tmp_dst = new_root.join("tmp");
         std::fs::create_dir_all(&tmp_dst)?;
         mount::mount_tmpfs(&tmp_dst)?;
+        plan.allow("/tmp", landlock::Access::ReadWrite);
+
+        // A private /run for the proxy socket and the repository, so only
+        // its own mount point is created in the worktree for them
+        let run_dst = new_root.join("run");
+        std::fs::create_dir_all(&run_dst)?;
+        mount::mount_tmpfs(&run_dst)?;
+        plan.allow("/run", landlock::Access::ReadExec);
+
+        let scratch = run_dst.join("robojail");
+        std::fs::create_dir_all(&scratch)?;
+        if let Some(ref git) = self.git {
+            git.mount(new_root, &scratch, &mut plan)?;
+        }
+
+        if let Some(ref settings) = self.proxy {
+            if self.network == NetworkMode::Proxy {
+                // The directory rather than the socket, which is replaced
+                // when another session takes the proxy over
+                let socket_dst = new_root.join(proxy::JAIL_SOCKET_DIR.trim_start_matches('/'));
+                std::fs::create_dir_all(&socket_dst)?;
+                mount::bind_mount(&settings.dir, &socket_dst, true)?;
+            }
+        }
 
         // Additional read-only binds
         for (src, dst) in &self.ro_binds {
@@ -333,6 +868,7 @@ impl Sandbox {
                     std::fs::write(&full_dst, "")?;
                 }
                 mount::bind_mount(src, &full_dst, true)?;
+                plan.allow(dst, landlock::Access::ReadExec);
             }
         }
 
@@ -350,27 +886,240 @@ impl Sandbox {
                     std::fs::write(&full_dst, "")?;
                 }
                 mount::bind_mount(src, &full_dst, false)?;
+                plan.allow(dst, landlock::Access::ReadWrite);
             }
         }
 
+        self.exclude_mount_points(new_root, &scratch)?;
+
+        // The tmpfs mounts that were written above stay as they are; the
+        // system directories and read-only binds already are
+        for dir in ["etc", "run", "dev"] {
+            mount::remount_readonly(&new_root.join(dir))?;
+        }
+
         // Pivot to new root
         mount::pivot_root(new_root)?;
 
-        Ok(())
+        Ok(plan)
+    }
+
+    /// Keep the jail's mount points in the worktree out of git inside the jail
+    ///
+    /// The jail sees a copy of the repository's exclude file that lists them
+    /// too; the file itself is left as it is. On the host they are empty
+    /// directories, which git doesn't track.
+    fn exclude_mount_points(&self, new_root: &Path, scratch: &Path) -> Result<()> {
+        let gitdir = match (&self.git, &self.overlay) {
+            (Some(_), _) => new_root.join(git::JAIL_GIT_DIR.trim_start_matches('/')),
+            (None, Some(_)) => new_root.join(".git"),
+            (None, None) => return Ok(()),
+        };
+        let exclude = gitdir.join("info/exclude");
+        if !exclude.is_file() {
+            return Ok(());
+        }
+
+        let mut content = std::fs::read_to_string(&exclude).unwrap_or_default();
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str("# robojail mount points\n");
+        for path in mount::top_level_mounts(new_root)?.iter().filter(|path| *path != Path::new("/.git")) {
+            let slash = if new_root.join(path.strip_prefix("/").unwrap_or(path)).is_dir() { "/" } else { "" };
+            content.push_str(&format!("{}{slash}\n", path.display()));
+        }
+
+        let copy = scratch.join("exclude");
+        std::fs::write(&copy, content)?;
+        mount::bind_mount(&copy, &exclude, true)
+    }
+}
+
+/// A socket shared by all sessions of a jail, served by one of them at a time
+///
+/// The serving session holds a lock next to the socket until it exits. The
+/// others wait on that lock and take the socket over, so it stays served for
+/// as long as any session of the jail runs.
+#[derive(Debug)]
+struct SharedSocket {
+    path: PathBuf,
+    what: &'static str,
+    /// Set once this session serves the socket
+    serving: Arc<AtomicBool>,
+    /// The held lock and bound socket, or the lock file to wait on
+    claim: Option<std::result::Result<(File, UnixListener), File>>,
+}
+
+impl SharedSocket {
+    /// Bind the socket at `path` unless another session serves it
+    fn open(path: &Path, lock: &Path, what: &'static str) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(lock)?;
+
+        let serving = Arc::new(AtomicBool::new(false));
+        let claim = match lock_file(&file, libc::LOCK_NB) {
+            Ok(()) => {
+                let listener = Self::bind(path, what)?;
+                serving.store(true, Ordering::SeqCst);
+                Ok((file, listener))
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(file),
+            Err(e) => {
+                return Err(Error::SandboxSetup(format!("failed to lock {}: {e}", lock.display())));
+            }
+        };
+
+        Ok(Self { path: path.to_path_buf(), what, serving, claim: Some(claim) })
+    }
+
+    /// Replace whatever a session that died left at `path` with a fresh socket
+    fn bind(path: &Path, what: &str) -> Result<UnixListener> {
+        let _ = std::fs::remove_file(path);
+        UnixListener::bind(path).map_err(|e| {
+            Error::SandboxSetup(format!("failed to bind {what} socket {}: {e}", path.display()))
+        })
+    }
+
+    /// Call `serve` on a background thread once this session serves the socket
+    fn serve(&mut self, serve: impl FnOnce(UnixListener) + Send + 'static) {
+        let Some(claim) = self.claim.take() else {
+            return;
+        };
+        let (path, what, serving) = (self.path.clone(), self.what, Arc::clone(&self.serving));
+
+        thread::spawn(move || {
+            let (_lock, listener) = match claim {
+                Ok(claim) => claim,
+                Err(file) => {
+                    if lock_file(&file, 0).is_err() {
+                        return;
+                    }
+                    match Self::bind(&path, what) {
+                        Ok(listener) => (file, listener),
+                        Err(e) => {
+                            eprintln!("warning: {e}");
+                            return;
+                        }
+                    }
+                }
+            };
+            serving.store(true, Ordering::SeqCst);
+            serve(listener);
+        });
+    }
+
+    /// Remove the socket if this session serves it
+    ///
+    /// The lock is only released when the process exits, after everything
+    /// it served has been closed.
+    fn close(&self) {
+        if self.serving.load(Ordering::SeqCst) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Take an exclusive lock on `file`, with `flags` such as `LOCK_NB`
+///
+/// Unlike a [`nix::fcntl::Flock`], the lock isn't dropped explicitly, so a
+/// forked child closing its copy of the file leaves it to the parent.
+fn lock_file(file: &File, flags: libc::c_int) -> io::Result<()> {
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | flags) } == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Attach slirp4netns to the jail once its network namespace exists
+fn attach_slirp(helper: &Path, mut sync: slirp::HostSync, child: Pid) -> Result<Option<slirp::Slirp>> {
+    // The jail failed before unsharing; its own error is reported
+    if !sync.wait_ready() {
+        return Ok(None);
     }
+
+    // On failure `sync` is dropped and the jail aborts its setup
+    let slirp = slirp::Slirp::start(helper, child)?;
+    sync.release();
+
+    Ok(Some(slirp))
 }
 
-/// Create a default sandbox for a jail
-pub fn create_jail_sandbox(worktree_path: &Path, config: &Config, entrypoint: Option<&[String]>) -> Sandbox {
-    let mut builder = SandboxBuilder::new(worktree_path)
+/// Control socket through which a running jail's published ports are updated
+pub fn ports_socket(name: &str) -> Result<PathBuf> {
+    Ok(Config::jail_runtime_dir(name)?.join("ports.sock"))
+}
+
+/// Configure the default sandbox for a jail
+pub fn jail_sandbox_builder(jail: &JailInfo, config: &Config) -> Result<SandboxBuilder> {
+    jail.check_idle()?;
+    let network = jail.network_mode(config);
+    let mut builder = SandboxBuilder::new(&jail.worktree_path)
         .with_config(config)
+        .network(network)
         .env("HOME", "/home/user")
         .env("USER", "user")
-        .workdir("/");
+        .workdir("/")
+        .cgroup(&jail.name, jail.limits.clone());
+
+    let name = jail.name.clone();
+    builder = builder.on_landlock(move |abi| {
+        if let Err(e) = State::update(|state| state.set_landlock_abi(&name, abi)) {
+            eprintln!("warning: failed to record the applied Landlock ABI: {e}");
+        }
+    });
+
+    match jail.mode {
+        JailMode::Worktree => {
+            if let Some(layout) = GitLayout::discover(&jail.worktree_path) {
+                builder = builder.git(layout.with_branch(&jail.branch_name));
+            }
+        }
+        JailMode::Overlay => {
+            builder = builder.overlay(&jail.repo_path, jail.upper_dir(), jail.work_dir());
+        }
+    }
+
+    if matches!(network, NetworkMode::Proxy | NetworkMode::Filtered) {
+        builder = builder.network_log(Config::jail_state_dir(&jail.name)?.join("network.log"));
+    }
+
+    // A session taking the ports over publishes them as they are by then
+    let (name, ports) = (jail.name.clone(), jail.ports.clone());
+    builder = builder.publish(ports_socket(&jail.name)?, Config::jail_runtime_dir(&jail.name)?.join("ports.lock"), move || {
+        State::load()
+            .and_then(|state| Ok(state.get_jail(&name)?.ports.clone()))
+            .unwrap_or_else(|_| ports.clone())
+    });
+
+    if network == NetworkMode::Filtered {
+        builder = builder.network_rules(config.network_blocked.clone(), jail.network_exceptions(config));
+    }
+
+    // In proxy mode all egress goes through the host-side proxy
+    if network == NetworkMode::Proxy {
+        let runtime_dir = Config::jail_runtime_dir(&jail.name)?;
+        builder = builder.proxy(runtime_dir.join("proxy"), runtime_dir.join("proxy.lock"), jail.network_allowlist(config));
+
+        let proxy_url = format!("http://127.0.0.1:{}", proxy::JAIL_PROXY_PORT);
+        for var in ["http_proxy", "https_proxy", "HTTP_PROXY", "HTTPS_PROXY"] {
+            builder = builder.env(var, &proxy_url);
+        }
+        for var in ["no_proxy", "NO_PROXY"] {
+            builder = builder.env(var, "localhost,127.0.0.1,::1");
+        }
+    }
 
     // If entrypoint is specified and not in a standard system path,
     // bind-mount it to make it accessible inside the jail
-    if let Some(ep) = entrypoint {
+    if let Some(ref ep) = jail.entrypoint {
         if let Some(cmd) = ep.first() {
             let ep_path = Path::new(cmd);
             // Check if the binary is outside standard system paths
@@ -386,5 +1135,143 @@ pub fn create_jail_sandbox(worktree_path: &Path, config: &Config, entrypoint: Op
         }
     }
 
-    builder.build()
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+    use nix::pty::openpty;
+    use nix::unistd::{dup2, setsid};
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::fd::{AsFd, AsRawFd};
+    use std::time::{Duration, Instant};
+
+    /// Exit code of the test's jail process when namespaces are unavailable
+    const NO_NAMESPACES: i32 = 77;
+
+    /// Run `args` in the sandbox `setup` returns, in a forked child
+    ///
+    /// `setup` runs in the child, which is where the sandbox has to be built.
+    pub(super) fn spawn_jail(setup: impl FnOnce() -> Sandbox, args: &[&str]) -> Pid {
+        match unsafe { fork() }.unwrap() {
+            ForkResult::Parent { child } => child,
+            ForkResult::Child => {
+                let code = match setup().run_command(args) {
+                    Ok(code) => code,
+                    Err(Error::NamespacesUnavailable) => NO_NAMESPACES,
+                    Err(_) => 1,
+                };
+                std::process::exit(code);
+            }
+        }
+    }
+
+    /// Exit code of a child from [`spawn_jail`], or `None` without namespaces
+    pub(super) fn wait_jail(child: Pid) -> Option<i32> {
+        match waitpid(child, None).unwrap() {
+            WaitStatus::Exited(_, NO_NAMESPACES) => {
+                eprintln!("skipping: user namespaces are unavailable");
+                None
+            }
+            WaitStatus::Exited(_, code) => Some(code),
+            status => panic!("jail process ended with {status:?}"),
+        }
+    }
+
+    #[test]
+    fn test_shared_socket_handed_over() {
+        let dir = std::env::temp_dir().join(format!("robojail-test-{}", uuid::Uuid::new_v4()));
+        let (path, lock) = (dir.join("test.sock"), dir.join("test.lock"));
+
+        // The first session serves one connection, then exits
+        let mut first = SharedSocket::open(&path, &lock, "test").unwrap();
+        first.serve(|listener| {
+            let _ = listener.accept();
+        });
+        let mut second = SharedSocket::open(&path, &lock, "test").unwrap();
+        let (took_over, handed_over) = std::sync::mpsc::channel();
+        second.serve(move |listener| {
+            let _ = took_over.send(());
+            let _ = listener.accept();
+        });
+        assert!(handed_over.recv_timeout(Duration::from_millis(200)).is_err());
+
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+        handed_over.recv_timeout(Duration::from_secs(5)).unwrap();
+        std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_landlock_allows_new_top_level_entries() {
+        if landlock::abi_version().is_none() {
+            eprintln!("skipping: Landlock is unavailable");
+            return;
+        }
+        let root = std::env::temp_dir().join(format!("robojail-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let config = Config { landlock: true, ..Config::default() };
+        let script = "mkdir /target && echo built > /target/out && touch /newfile && rm /newfile \
+                      && ! touch /etc/new 2>/dev/null && ! touch /run/new 2>/dev/null && ! touch /usr/new 2>/dev/null";
+
+        let child = spawn_jail(|| SandboxBuilder::new(&root).with_config(&config).build(), &["sh", "-c", script]);
+        let code = wait_jail(child);
+        let built = std::fs::read_to_string(root.join("target/out")).ok();
+        let _ = std::fs::remove_dir_all(&root);
+        let Some(code) = code else { return };
+
+        assert_eq!(code, 0);
+        assert_eq!(built.as_deref(), Some("built\n"));
+    }
+
+    #[test]
+    fn test_interrupt_reaches_command() {
+        let root = std::env::temp_dir().join(format!("robojail-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let script = "trap 'echo got-INT; exit 3' INT; echo ready; sleep 10 & wait";
+
+        let pty = openpty(None, None).unwrap();
+        let (master, slave) = (pty.master.as_raw_fd(), pty.slave.as_raw_fd());
+        let setup = || {
+            unsafe { libc::close(master) };
+            setsid().unwrap();
+            unsafe { libc::ioctl(slave, libc::TIOCSCTTY, 0) };
+            for fd in 0..=2 {
+                dup2(slave, fd).unwrap();
+            }
+            SandboxBuilder::new(&root).build()
+        };
+        let child = spawn_jail(setup, &["sh", "-c", script]);
+        drop(pty.slave);
+
+        let mut master = File::from(pty.master);
+        let mut output = String::new();
+        let mut interrupted = false;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            let mut fds = [PollFd::new(master.as_fd(), PollFlags::POLLIN)];
+            if poll(&mut fds, PollTimeout::from(100u8)).unwrap() == 0 {
+                continue;
+            }
+            let mut buf = [0u8; 1024];
+            match master.read(&mut buf) {
+                Ok(n @ 1..) => output.push_str(&String::from_utf8_lossy(&buf[..n])),
+                _ => break,
+            }
+            if !interrupted && output.contains("ready") {
+                master.write_all(b"\x03").unwrap();
+                interrupted = true;
+            }
+        }
+
+        let code = wait_jail(child);
+        let _ = std::fs::remove_dir_all(&root);
+        let Some(code) = code else { return };
+
+        assert!(output.contains("got-INT"), "trap didn't run: {output:?}");
+        assert_eq!(code, 3);
+    }
 }
@@ -4,9 +4,10 @@
 
 use crate::error::{Error, Result};
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::sys::statvfs::{statvfs, FsFlags};
 use nix::unistd::{chdir, pivot_root as nix_pivot_root};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 /// Make all mounts private to prevent propagation
 pub fn make_mounts_private() -> Result<()> {
@@ -74,14 +75,79 @@ pub fn bind_mount(source: &Path, target: &Path, readonly: bool) -> Result<()> {
     Ok(())
 }
 
-/// Mount proc filesystem (bind mount from host)
+/// Make an existing mount read-only, keeping its other flags
 ///
-/// We bind-mount /proc from the host because mounting a new procfs requires
-/// being PID 1 in a new PID namespace, which requires an additional fork.
-/// The bind-mounted /proc still works for most purposes.
+/// Only the mount at `target` changes, not the ones below it.
+pub fn remount_readonly(target: &Path) -> Result<()> {
+    let current = statvfs(target).map_err(|e| Error::MountFailed {
+        path: target.to_path_buf(),
+        reason: format!("failed to read mount flags: {e}"),
+    })?;
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY;
+    for (kept, flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+    ] {
+        if current.flags().contains(kept) {
+            flags |= flag;
+        }
+    }
+
+    mount(None::<&str>, target, None::<&str>, flags, None::<&str>).map_err(|e| Error::MountFailed {
+        path: target.to_path_buf(),
+        reason: format!("failed to make mount read-only: {e}"),
+    })
+}
+
+/// Mount a copy-on-write overlay of `lower` at the given path
+///
+/// Only works inside a user namespace on Linux 5.11+, which is what the
+/// `userxattr` option (overlay metadata in `user.*` xattrs) is for.
+pub fn mount_overlay(lower: &Path, upper: &Path, work: &Path, target: &Path) -> Result<()> {
+    let paths = [lower, upper, work];
+    if let Some(bad) = paths.iter().find(|p| p.to_string_lossy().contains([',', ':'])) {
+        return Err(Error::MountFailed {
+            path: target.to_path_buf(),
+            reason: format!("overlay paths may not contain ',' or ':': {}", bad.display()),
+        });
+    }
+
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={},userxattr",
+        lower.display(),
+        upper.display(),
+        work.display()
+    );
+    mount(
+        Some("overlay"),
+        target,
+        Some("overlay"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(options.as_str()),
+    )
+    .map_err(|e| Error::MountFailed {
+        path: target.to_path_buf(),
+        reason: format!("failed to mount overlay (requires Linux 5.11+): {e}"),
+    })
+}
+
+/// Mount a fresh proc filesystem
+///
+/// Must be called from inside the jail's PID namespace, so that the new
+/// procfs only shows processes belonging to the jail rather than the host.
 pub fn mount_proc(target: &Path) -> Result<()> {
-    // /proc cannot be remounted read-only, so we bind it writable
-    bind_mount(Path::new("/proc"), target, false)
+    mount(
+        Some("proc"),
+        target,
+        Some("proc"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        None::<&str>,
+    )
+    .map_err(|e| Error::MountFailed {
+        path: target.to_path_buf(),
+        reason: format!("failed to mount proc: {e}"),
+    })
 }
 
 /// Set up /dev with minimal devices
@@ -162,6 +228,19 @@ pub fn setup_dev(target: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Top-level entries of `root` that have something mounted on them, as paths below `/`
+pub fn top_level_mounts(root: &Path) -> Result<Vec<PathBuf>> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let mut entries: Vec<PathBuf> = mountinfo
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .filter_map(|point| Some(Path::new("/").join(Path::new(point).strip_prefix(root).ok()?.components().next()?)))
+        .collect();
+    entries.sort();
+    entries.dedup();
+    Ok(entries)
+}
+
 /// Pivot root to the new root filesystem
 pub fn pivot_root(new_root: &Path) -> Result<()> {
     // Change to new root
@@ -8,6 +8,7 @@ use nix::sched::{unshare, CloneFlags};
 use nix::unistd::{getgid, getuid};
 use std::fs;
 use std::io::Write;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
 
 /// Set up user namespace with UID/GID mapping
 ///
@@ -44,17 +45,17 @@ pub fn setup_user_namespace() -> Result<()> {
     Ok(())
 }
 
-/// Set up mount and IPC namespaces
+/// Set up mount, IPC, UTS and PID namespaces
 ///
 /// Must be called after setup_user_namespace().
 ///
-/// Note: We skip CLONE_NEWPID because mounting /proc for a new PID namespace
-/// requires being PID 1 in that namespace (which requires an additional fork).
-/// For simplicity, we rely on mount namespace isolation instead.
+/// CLONE_NEWPID does not move the caller into the new PID namespace; the
+/// next child we fork becomes PID 1 there and must be the one to mount /proc.
 pub fn setup_other_namespaces(share_net: bool) -> Result<()> {
     let mut flags = CloneFlags::CLONE_NEWNS   // Mount namespace
                   | CloneFlags::CLONE_NEWIPC  // IPC namespace
-                  | CloneFlags::CLONE_NEWUTS; // UTS namespace (hostname)
+                  | CloneFlags::CLONE_NEWUTS  // UTS namespace (hostname)
+                  | CloneFlags::CLONE_NEWPID; // PID namespace (applies to children)
 
     if !share_net {
         flags |= CloneFlags::CLONE_NEWNET; // Network namespace
@@ -70,6 +71,64 @@ pub fn setup_other_namespaces(share_net: bool) -> Result<()> {
     Ok(())
 }
 
+/// Bring up the loopback interface of a fresh network namespace
+///
+/// New network namespaces start with `lo` down. Needs CAP_NET_ADMIN in the
+/// user namespace that owns the network namespace.
+pub fn bring_up_loopback() -> Result<()> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(Error::SandboxSetup(format!(
+            "failed to create socket: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+    let sock = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in req.ifr_name.iter_mut().zip(b"lo\0") {
+        *dst = *src as libc::c_char;
+    }
+
+    if unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCGIFFLAGS as _, &mut req) } < 0 {
+        return Err(Error::SandboxSetup(format!(
+            "failed to read loopback flags: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    unsafe { req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short };
+
+    if unsafe { libc::ioctl(sock.as_raw_fd(), libc::SIOCSIFFLAGS as _, &req) } < 0 {
+        return Err(Error::SandboxSetup(format!(
+            "failed to bring up loopback: {}",
+            std::io::Error::last_os_error()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Ask the kernel to SIGKILL us when our parent process exits
+///
+/// Chained through every sandbox process, so killing the robojail process
+/// that started a jail tears down the whole PID namespace.
+pub fn die_with_parent() -> Result<()> {
+    let parent = nix::unistd::getppid();
+
+    let result = unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0) };
+    if result != 0 {
+        return Err(Error::SandboxSetup("failed to set PR_SET_PDEATHSIG".to_string()));
+    }
+
+    // The parent may have exited before the prctl took effect
+    if nix::unistd::getppid() != parent {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
 /// Helper to write to a /proc file
 fn write_to_proc_file(path: &str, content: &str) -> Result<()> {
     let mut file = fs::OpenOptions::new()
@@ -0,0 +1,685 @@
+//! Destination filtering for the `filtered` network mode
+//!
+//! Jails in filtered mode share the host's network namespace, so they keep
+//! the host's internet access, but must not reach services that are only
+//! meant for the host or the local network. The command runs under a seccomp
+//! filter that turns `connect`, `sendto` with an address, `sendmsg` and
+//! `sendmmsg` into user notifications, which the jail's init answers.
+//!
+//! Checking an address and then letting the kernel carry on would be racy:
+//! another thread of the jailed process could rewrite the address in between.
+//! Instead the supervisor copies the address once, checks the copy, and
+//! performs the call itself on a duplicate of the jailed process's socket
+//! (pidfd_getfd), returning the result as the syscall's. This needs Linux 5.6.
+
+use crate::error::{Error, Result};
+use chrono::Utc;
+use nix::unistd::Pid;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Destinations blocked by default: loopback, private, link-local and CGNAT ranges
+pub const DEFAULT_BLOCKED: &[&str] = &[
+    "0.0.0.0/8",
+    "127.0.0.0/8",
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+    "100.64.0.0/10",
+    "::/128",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+/// Exceptions by default: the systemd-resolved stub resolver
+pub const DEFAULT_EXCEPTIONS: &[&str] = &["127.0.0.53:53"];
+
+// ioctls on the seccomp notification listener
+const SECCOMP_IOCTL_NOTIF_RECV: libc::c_ulong = 0xc050_2100;
+const SECCOMP_IOCTL_NOTIF_SEND: libc::c_ulong = 0xc018_2101;
+const SECCOMP_IOCTL_NOTIF_ID_VALID: libc::c_ulong = 0x4008_2102;
+
+/// Largest payload we copy out of the jail for one send
+const MAX_PAYLOAD: usize = 4 * 1024 * 1024;
+/// Largest ancillary data we copy out of the jail for one sendmsg
+const MAX_CONTROL: usize = 64 * 1024;
+/// Kernel limit on iovecs and sendmmsg batch size
+const UIO_MAXIOV: usize = 1024;
+/// Size of struct sockaddr_storage
+const SOCKADDR_MAX: usize = 128;
+
+/// An address range, optionally restricted to one port
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+    port: Option<u16>,
+}
+
+impl Cidr {
+    /// Parse "10.0.0.0/8", "127.0.0.53:53", "::1", "[::1]:8080" or "[fd00::/8]:53"
+    fn parse(spec: &str, allow_port: bool) -> Option<Self> {
+        let spec = spec.trim();
+        let (range, port) = if let Some(rest) = spec.strip_prefix('[') {
+            let (range, after) = rest.split_once(']')?;
+            match after {
+                "" => (range, None),
+                _ => (range, Some(after.strip_prefix(':')?.parse().ok()?)),
+            }
+        } else if spec.matches(':').count() == 1 {
+            let (range, port) = spec.split_once(':')?;
+            (range, Some(port.parse().ok()?))
+        } else {
+            (spec, None)
+        };
+
+        if port.is_some() && !allow_port {
+            return None;
+        }
+
+        let (addr, prefix) = match range.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (range.parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+
+        Some(Self { addr, prefix, port })
+    }
+
+    fn contains(&self, dest: &SocketAddr) -> bool {
+        if self.port.is_some_and(|port| port != dest.port()) {
+            return false;
+        }
+
+        match (self.addr, unmap(dest.ip())) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Treat IPv4-mapped IPv6 addresses as the IPv4 address they carry
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        ip => ip,
+    }
+}
+
+/// Which destinations a filtered jail may reach
+#[derive(Debug, Clone)]
+pub struct NetworkPolicy {
+    blocked: Vec<Cidr>,
+    exceptions: Vec<Cidr>,
+}
+
+impl NetworkPolicy {
+    /// Parse the blocked ranges and the exceptions to them
+    pub fn new(blocked: &[String], exceptions: &[String]) -> Result<Self> {
+        let parse = |spec: &String, allow_port: bool| {
+            Cidr::parse(spec, allow_port).ok_or_else(|| {
+                Error::Config(format!("invalid network range: '{spec}'"))
+            })
+        };
+
+        Ok(Self {
+            blocked: blocked.iter().map(|s| parse(s, false)).collect::<Result<_>>()?,
+            exceptions: exceptions.iter().map(|s| parse(s, true)).collect::<Result<_>>()?,
+        })
+    }
+
+    /// Whether the jail may send to `dest`
+    pub fn allows(&self, dest: &SocketAddr) -> bool {
+        !self.blocked.iter().any(|cidr| cidr.contains(dest))
+            || self.exceptions.iter().any(|cidr| cidr.contains(dest))
+    }
+}
+
+/// Create the two ends of the listener handover
+///
+/// The jailed command installs the filter and announces the listener's fd
+/// number; init copies the fd out of it with pidfd_getfd. (Passing it over
+/// SCM_RIGHTS would need sendmsg, which the filter just started to trap.)
+pub fn channel(policy: NetworkPolicy, log: Option<File>) -> Result<(Supervisor, Handoff)> {
+    let (init_end, child_end) = UnixStream::pair()?;
+
+    Ok((
+        Supervisor {
+            socket: init_end,
+            policy,
+            log: log.map(Mutex::new),
+        },
+        Handoff { socket: child_end },
+    ))
+}
+
+/// Command side of the handover
+#[derive(Debug)]
+pub struct Handoff {
+    socket: UnixStream,
+}
+
+impl Handoff {
+    /// Install the notification filter and wait until init has its listener
+    pub fn install(mut self, filter: &super::seccomp::Filter) -> Result<()> {
+        let listener = filter.install_with_listener()?;
+        self.socket.write_all(&listener.as_raw_fd().to_ne_bytes())?;
+
+        let mut ack = [0u8; 1];
+        match self.socket.read(&mut ack) {
+            Ok(1) => Ok(()),
+            _ => Err(Error::SandboxSetup("network supervisor did not start".to_string())),
+        }
+    }
+}
+
+/// Init side of the handover, and the supervisor itself
+#[derive(Debug)]
+pub struct Supervisor {
+    socket: UnixStream,
+    policy: NetworkPolicy,
+    log: Option<Mutex<File>>,
+}
+
+impl Supervisor {
+    /// Take over the listener from `child` and serve it on a background thread
+    pub fn spawn(self, child: Pid) {
+        thread::spawn(move || {
+            let Some(listener) = self.receive_listener(child) else {
+                return;
+            };
+            let this = Arc::new(self);
+            let listener = Arc::new(listener);
+
+            loop {
+                let mut req: libc::seccomp_notif = unsafe { std::mem::zeroed() };
+                let result = unsafe {
+                    libc::ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_RECV as _, &mut req)
+                };
+                if result < 0 {
+                    match errno() {
+                        libc::EINTR | libc::ENOENT => continue,
+                        _ => return,
+                    }
+                }
+
+                let this = Arc::clone(&this);
+                let listener = Arc::clone(&listener);
+                thread::spawn(move || {
+                    let result = this.handle(&listener, &req);
+                    respond(&listener, req.id, result);
+                });
+            }
+        });
+    }
+
+    fn receive_listener(&self, child: Pid) -> Option<OwnedFd> {
+        let mut socket = &self.socket;
+        let mut buf = [0u8; 4];
+        socket.read_exact(&mut buf).ok()?;
+        let fd = RawFd::from_ne_bytes(buf);
+
+        let pidfd = pidfd_open(child.as_raw()).ok()?;
+        let listener = pidfd_getfd(&pidfd, fd).ok()?;
+        socket.write_all(b"1").ok()?;
+
+        Some(listener)
+    }
+
+    /// Carry out one trapped syscall, returning its result or errno
+    fn handle(&self, listener: &OwnedFd, req: &libc::seccomp_notif) -> SysResult {
+        let target = Target::open(req.pid as i32)?;
+
+        // The pidfd must refer to the task that is still waiting on us
+        if !id_valid(listener, req.id) {
+            return Err(libc::ENOENT);
+        }
+
+        let args = req.data.args;
+        match req.data.nr as libc::c_long {
+            libc::SYS_connect => self.connect(&target, args),
+            libc::SYS_sendto => self.sendto(&target, args),
+            libc::SYS_sendmsg => {
+                let sock = target.fd(args[0] as i32)?;
+                self.sendmsg(&target, &sock, args[1], args[2] as i32)
+            }
+            libc::SYS_sendmmsg => self.sendmmsg(&target, args),
+            _ => Err(libc::ENOSYS),
+        }
+    }
+
+    fn connect(&self, target: &Target, args: [u64; 6]) -> SysResult {
+        let addr = self.read_addr(target, args[1], args[2], "connect")?;
+        let sock = target.fd(args[0] as i32)?;
+
+        let result = unsafe {
+            libc::connect(
+                sock.as_raw_fd(),
+                addr.as_ptr() as *const libc::sockaddr,
+                addr.len() as libc::socklen_t,
+            )
+        };
+        check(result as i64)
+    }
+
+    fn sendto(&self, target: &Target, args: [u64; 6]) -> SysResult {
+        let addr = self.read_addr(target, args[4], args[5], "sendto")?;
+        let sock = target.fd(args[0] as i32)?;
+        let data = read_payload(target, &sock, args[1], args[2] as usize)?;
+
+        let result = unsafe {
+            libc::sendto(
+                sock.as_raw_fd(),
+                data.as_ptr() as *const libc::c_void,
+                data.len(),
+                args[3] as i32,
+                addr.as_ptr() as *const libc::sockaddr,
+                addr.len() as libc::socklen_t,
+            )
+        };
+        check(result as i64)
+    }
+
+    fn sendmsg(&self, target: &Target, sock: &OwnedFd, msg_ptr: u64, flags: i32) -> SysResult {
+        let raw = target.read(msg_ptr, std::mem::size_of::<libc::msghdr>())?;
+        let msg: libc::msghdr = unsafe { std::ptr::read_unaligned(raw.as_ptr() as *const _) };
+
+        let mut addr = if msg.msg_name.is_null() {
+            Vec::new()
+        } else {
+            self.read_addr(target, msg.msg_name as u64, msg.msg_namelen as u64, "sendmsg")?
+        };
+
+        // Gather the payload into one buffer
+        if msg.msg_iovlen > UIO_MAXIOV {
+            return Err(libc::EMSGSIZE);
+        }
+        let iov_size = std::mem::size_of::<libc::iovec>();
+        let iovs = target.read(msg.msg_iov as u64, msg.msg_iovlen * iov_size)?;
+        let mut total = 0usize;
+        let mut regions = Vec::with_capacity(msg.msg_iovlen);
+        for chunk in iovs.chunks_exact(iov_size) {
+            let iov: libc::iovec = unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const _) };
+            regions.push((iov.iov_base as u64, iov.iov_len));
+            total = total.saturating_add(iov.iov_len);
+        }
+        let mut data = Vec::new();
+        for (base, len) in regions {
+            let len = len.min(MAX_PAYLOAD - data.len());
+            data.extend(target.read(base, len)?);
+            if data.len() == MAX_PAYLOAD {
+                break;
+            }
+        }
+        if total > data.len() && sock_type(sock) != libc::SOCK_STREAM {
+            return Err(libc::EMSGSIZE);
+        }
+
+        // Copy ancillary data, duplicating any file descriptors it passes
+        let mut control = Vec::new();
+        let mut passed_fds = Vec::new();
+        if !msg.msg_control.is_null() && msg.msg_controllen > 0 {
+            if msg.msg_controllen > MAX_CONTROL {
+                return Err(libc::ENOBUFS);
+            }
+            control = target.read(msg.msg_control as u64, msg.msg_controllen)?;
+            passed_fds = translate_rights(target, &mut control)?;
+        }
+
+        let mut iov = libc::iovec {
+            iov_base: data.as_mut_ptr() as *mut libc::c_void,
+            iov_len: data.len(),
+        };
+        let mut local: libc::msghdr = unsafe { std::mem::zeroed() };
+        if !addr.is_empty() {
+            local.msg_name = addr.as_mut_ptr() as *mut libc::c_void;
+            local.msg_namelen = addr.len() as libc::socklen_t;
+        }
+        local.msg_iov = &mut iov;
+        local.msg_iovlen = 1;
+        if !control.is_empty() {
+            local.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            local.msg_controllen = control.len();
+        }
+
+        let result = unsafe { libc::sendmsg(sock.as_raw_fd(), &local, flags) };
+        drop(passed_fds);
+        check(result as i64)
+    }
+
+    fn sendmmsg(&self, target: &Target, args: [u64; 6]) -> SysResult {
+        let sock = target.fd(args[0] as i32)?;
+        let count = (args[2] as usize).min(UIO_MAXIOV);
+        let flags = args[3] as i32;
+        let entry_size = std::mem::size_of::<libc::mmsghdr>();
+        let len_offset = std::mem::offset_of!(libc::mmsghdr, msg_len);
+
+        let mut sent: i64 = 0;
+        for i in 0..count {
+            let entry = args[1] + (i * entry_size) as u64;
+            match self.sendmsg(target, &sock, entry, flags) {
+                Ok(n) => {
+                    target.write(entry + len_offset as u64, &(n as u32).to_ne_bytes())?;
+                    sent += 1;
+                }
+                Err(e) if sent == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Copy a socket address out of the jail and check it against the policy
+    ///
+    /// Relative Unix socket paths are resolved against the caller's working
+    /// directory, since ours differs.
+    fn read_addr(&self, target: &Target, ptr: u64, len: u64, call: &str) -> std::result::Result<Vec<u8>, i32> {
+        if len as usize > SOCKADDR_MAX {
+            return Err(libc::EINVAL);
+        }
+        let addr = target.read(ptr, len as usize)?;
+
+        match decode(&addr) {
+            Destination::Inet(dest) if !self.policy.allows(&dest) => {
+                self.log_denial(call, &dest.to_string());
+                Err(libc::EPERM)
+            }
+            Destination::UnixAbstract(name) => {
+                self.log_denial(call, &format!("@{name}"));
+                Err(libc::EPERM)
+            }
+            Destination::UnixRelative(path) => {
+                let path = format!("/proc/{}/cwd/{}", target.tgid, path);
+                unix_addr(path.as_bytes()).ok_or(libc::ENAMETOOLONG)
+            }
+            _ => Ok(addr),
+        }
+    }
+
+    fn log_denial(&self, call: &str, dest: &str) {
+        if let Some(log) = self.log.as_ref().and_then(|log| log.lock().ok()) {
+            let mut log = &*log;
+            let _ = writeln!(log, "{} DENY {} {}", Utc::now().format("%Y-%m-%dT%H:%M:%SZ"), call, dest);
+        }
+    }
+}
+
+/// Return value or errno of an emulated syscall
+type SysResult = std::result::Result<i64, i32>;
+
+fn check(result: i64) -> SysResult {
+    if result < 0 {
+        Err(errno())
+    } else {
+        Ok(result)
+    }
+}
+
+fn errno() -> i32 {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+}
+
+fn respond(listener: &OwnedFd, id: u64, result: SysResult) {
+    let mut resp: libc::seccomp_notif_resp = unsafe { std::mem::zeroed() };
+    resp.id = id;
+    match result {
+        Ok(val) => resp.val = val,
+        Err(e) => resp.error = -e,
+    }
+    // Fails if the caller was interrupted meanwhile, which is fine
+    unsafe { libc::ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_SEND as _, &mut resp) };
+}
+
+fn id_valid(listener: &OwnedFd, id: u64) -> bool {
+    unsafe { libc::ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_ID_VALID as _, &id) == 0 }
+}
+
+/// Where a socket address points
+#[derive(Debug, PartialEq)]
+enum Destination {
+    Inet(SocketAddr),
+    UnixAbstract(String),
+    UnixRelative(String),
+    /// Absolute Unix paths, unnamed sockets and other families
+    Other,
+}
+
+fn decode(addr: &[u8]) -> Destination {
+    if addr.len() < 2 {
+        return Destination::Other;
+    }
+    let family = u16::from_ne_bytes([addr[0], addr[1]]) as i32;
+
+    match family {
+        libc::AF_INET if addr.len() >= 8 => {
+            let port = u16::from_be_bytes([addr[2], addr[3]]);
+            let ip = Ipv4Addr::new(addr[4], addr[5], addr[6], addr[7]);
+            Destination::Inet(SocketAddr::new(IpAddr::V4(ip), port))
+        }
+        libc::AF_INET6 if addr.len() >= 24 => {
+            let port = u16::from_be_bytes([addr[2], addr[3]]);
+            let octets: [u8; 16] = addr[8..24].try_into().unwrap_or_default();
+            Destination::Inet(SocketAddr::new(IpAddr::V6(Ipv6Addr::from(octets)), port))
+        }
+        libc::AF_UNIX if addr.len() > 2 => {
+            let path = &addr[2..];
+            if path[0] == 0 {
+                Destination::UnixAbstract(String::from_utf8_lossy(&path[1..]).to_string())
+            } else if path[0] != b'/' {
+                let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+                Destination::UnixRelative(String::from_utf8_lossy(&path[..end]).to_string())
+            } else {
+                Destination::Other
+            }
+        }
+        _ => Destination::Other,
+    }
+}
+
+/// Build a sockaddr_un for a path
+fn unix_addr(path: &[u8]) -> Option<Vec<u8>> {
+    if path.len() >= 108 {
+        return None;
+    }
+    let mut addr = (libc::AF_UNIX as u16).to_ne_bytes().to_vec();
+    addr.extend_from_slice(path);
+    addr.push(0);
+    Some(addr)
+}
+
+/// Copy payload bytes out of the jail
+///
+/// Stream sockets get a short write if the payload is larger than we copy;
+/// datagrams can't be split, so those fail instead.
+fn read_payload(target: &Target, sock: &OwnedFd, ptr: u64, len: usize) -> std::result::Result<Vec<u8>, i32> {
+    if len > MAX_PAYLOAD && sock_type(sock) != libc::SOCK_STREAM {
+        return Err(libc::EMSGSIZE);
+    }
+    target.read(ptr, len.min(MAX_PAYLOAD))
+}
+
+/// Replace the jail's fd numbers in SCM_RIGHTS messages with our duplicates
+fn translate_rights(target: &Target, control: &mut [u8]) -> std::result::Result<Vec<OwnedFd>, i32> {
+    let header = std::mem::size_of::<libc::cmsghdr>();
+    let align = std::mem::size_of::<usize>();
+    let mut fds = Vec::new();
+    let mut offset = 0;
+
+    while offset + header <= control.len() {
+        let cmsg: libc::cmsghdr = unsafe { std::ptr::read_unaligned(control[offset..].as_ptr() as *const _) };
+        if cmsg.cmsg_len < header || offset + cmsg.cmsg_len > control.len() {
+            return Err(libc::EINVAL);
+        }
+
+        if cmsg.cmsg_level == libc::SOL_SOCKET && cmsg.cmsg_type == libc::SCM_RIGHTS {
+            let data = &mut control[offset + header..offset + cmsg.cmsg_len];
+            for slot in data.chunks_exact_mut(4) {
+                let fd = target.fd(i32::from_ne_bytes([slot[0], slot[1], slot[2], slot[3]]))?;
+                slot.copy_from_slice(&fd.as_raw_fd().to_ne_bytes());
+                fds.push(fd);
+            }
+        }
+
+        offset += cmsg.cmsg_len.div_ceil(align) * align;
+    }
+
+    Ok(fds)
+}
+
+fn sock_type(sock: &OwnedFd) -> i32 {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    unsafe {
+        libc::getsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TYPE,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    value
+}
+
+/// A jailed task waiting on the supervisor
+struct Target {
+    tid: i32,
+    tgid: i32,
+    pidfd: OwnedFd,
+}
+
+impl Target {
+    fn open(tid: i32) -> std::result::Result<Self, i32> {
+        // pidfds refer to thread groups; the fd table is shared by all threads
+        let status = std::fs::read_to_string(format!("/proc/{tid}/status")).map_err(|_| libc::ESRCH)?;
+        let tgid = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Tgid:"))
+            .and_then(|tgid| tgid.trim().parse().ok())
+            .ok_or(libc::ESRCH)?;
+
+        Ok(Self { tid, tgid, pidfd: pidfd_open(tgid)? })
+    }
+
+    fn fd(&self, fd: i32) -> std::result::Result<OwnedFd, i32> {
+        pidfd_getfd(&self.pidfd, fd)
+    }
+
+    fn read(&self, ptr: u64, len: usize) -> std::result::Result<Vec<u8>, i32> {
+        let mut buf = vec![0u8; len];
+        if len == 0 {
+            return Ok(buf);
+        }
+
+        let local = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: len };
+        let remote = libc::iovec { iov_base: ptr as *mut libc::c_void, iov_len: len };
+        let n = unsafe { libc::process_vm_readv(self.tid, &local, 1, &remote, 1, 0) };
+
+        if n as usize != len {
+            return Err(libc::EFAULT);
+        }
+        Ok(buf)
+    }
+
+    fn write(&self, ptr: u64, data: &[u8]) -> std::result::Result<(), i32> {
+        let local = libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() };
+        let remote = libc::iovec { iov_base: ptr as *mut libc::c_void, iov_len: data.len() };
+        let n = unsafe { libc::process_vm_writev(self.tid, &local, 1, &remote, 1, 0) };
+
+        if n as usize != data.len() {
+            return Err(libc::EFAULT);
+        }
+        Ok(())
+    }
+}
+
+fn pidfd_open(pid: i32) -> std::result::Result<OwnedFd, i32> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(errno());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+fn pidfd_getfd(pidfd: &OwnedFd, fd: i32) -> std::result::Result<OwnedFd, i32> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_getfd, pidfd.as_raw_fd(), fd, 0) };
+    if fd < 0 {
+        return Err(errno());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(blocked: &[&str], exceptions: &[&str]) -> NetworkPolicy {
+        let strings = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        NetworkPolicy::new(&strings(blocked), &strings(exceptions)).unwrap()
+    }
+
+    #[test]
+    fn test_default_policy() {
+        let policy = policy(DEFAULT_BLOCKED, DEFAULT_EXCEPTIONS);
+
+        assert!(policy.allows(&"1.1.1.1:443".parse().unwrap()));
+        assert!(policy.allows(&"[2606:4700::1111]:443".parse().unwrap()));
+        assert!(!policy.allows(&"127.0.0.1:5432".parse().unwrap()));
+        assert!(!policy.allows(&"0.0.0.0:8080".parse().unwrap()));
+        assert!(!policy.allows(&"192.168.1.10:80".parse().unwrap()));
+        assert!(!policy.allows(&"[::1]:2375".parse().unwrap()));
+        assert!(!policy.allows(&"[::ffff:127.0.0.1]:2375".parse().unwrap()));
+        assert!(!policy.allows(&"[fe80::1]:22".parse().unwrap()));
+        assert!(policy.allows(&"127.0.0.53:53".parse().unwrap()));
+        assert!(!policy.allows(&"127.0.0.53:80".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert!(Cidr::parse("10.1.0.0/16:8080", true).is_some());
+        assert!(Cidr::parse("[fd00::/8]:53", true).is_some());
+        assert!(Cidr::parse("fd00::1", true).is_some());
+        assert!(Cidr::parse("10.0.0.0/33", true).is_none());
+        assert!(Cidr::parse("127.0.0.1:80", false).is_none());
+        assert!(Cidr::parse("localhost", true).is_none());
+
+        let policy = policy(&["10.0.0.0/8"], &["10.1.0.0/16:8080"]);
+        assert!(policy.allows(&"10.1.2.3:8080".parse().unwrap()));
+        assert!(!policy.allows(&"10.1.2.3:8081".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_decode_addresses() {
+        let mut inet = vec![0u8; 16];
+        inet[..2].copy_from_slice(&(libc::AF_INET as u16).to_ne_bytes());
+        inet[2..4].copy_from_slice(&5432u16.to_be_bytes());
+        inet[4..8].copy_from_slice(&[127, 0, 0, 1]);
+        assert_eq!(decode(&inet), Destination::Inet("127.0.0.1:5432".parse().unwrap()));
+
+        let abstract_addr = [&(libc::AF_UNIX as u16).to_ne_bytes()[..], b"\0dbus"].concat();
+        assert_eq!(decode(&abstract_addr), Destination::UnixAbstract("dbus".to_string()));
+
+        let relative = unix_addr(b"sock").unwrap();
+        assert_eq!(decode(&relative), Destination::UnixRelative("sock".to_string()));
+        assert_eq!(decode(&unix_addr(b"/run/x.sock").unwrap()), Destination::Other);
+    }
+}
@@ -0,0 +1,470 @@
+//! Publishing jail ports on the host
+//!
+//! Published ports are served by the robojail process that runs the jail:
+//! it listens on 127.0.0.1 on the host and relays every connection to the
+//! jail's loopback. With a shared network the jail's servers are already
+//! on the host's loopback, so connections are made directly. A private
+//! network namespace can't be reached from the host, so the jail's init
+//! dials the port from inside and hands the connected socket back over a
+//! socketpair.
+//!
+//! `robojail port` updates the mappings of a running jail through a control
+//! socket in the jail's runtime directory. A host port can only be bound
+//! once, so one session of a jail publishes its ports and serves that socket
+//! at a time. When it exits, another running session takes both over and
+//! relays into its own network.
+
+use super::{proxy, SharedSocket};
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A jail port published on a host port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PortMapping {
+    /// Port on the host's 127.0.0.1
+    pub host: u16,
+    /// Port on the jail's loopback
+    pub jail: u16,
+}
+
+impl FromStr for PortMapping {
+    type Err = Error;
+
+    /// Parse `HOST:JAIL`, or a single port used on both sides
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Config(format!("invalid port mapping '{s}' (expected 'HOST:JAIL')"));
+        let port = |p: &str| p.parse::<u16>().ok().filter(|&p| p != 0).ok_or_else(invalid);
+
+        let (host, jail) = match s.split_once(':') {
+            Some((host, jail)) => (port(host)?, port(jail)?),
+            None => (port(s)?, port(s)?),
+        };
+        Ok(Self { host, jail })
+    }
+}
+
+impl TryFrom<String> for PortMapping {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<PortMapping> for String {
+    fn from(mapping: PortMapping) -> Self {
+        mapping.to_string()
+    }
+}
+
+impl fmt::Display for PortMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.jail)
+    }
+}
+
+/// Check that no host port is published twice
+pub fn check_unique(mappings: &[PortMapping]) -> Result<()> {
+    for (i, mapping) in mappings.iter().enumerate() {
+        if mappings[..i].iter().any(|m| m.host == mapping.host) {
+            return Err(Error::Config(format!("host port {} is published twice", mapping.host)));
+        }
+    }
+    Ok(())
+}
+
+/// Create the channel through which the host dials into a private network namespace
+pub fn dial_pair() -> Result<(DialClient, JailDialer)> {
+    let mut fds = [0; 2];
+    let ret = unsafe {
+        libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC, 0, fds.as_mut_ptr())
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let (host, jail) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    Ok((DialClient { channel: Mutex::new(host) }, JailDialer { channel: jail }))
+}
+
+/// Host end of the dial channel
+#[derive(Debug)]
+pub struct DialClient {
+    channel: Mutex<OwnedFd>,
+}
+
+impl DialClient {
+    /// Connect to `port` on the jail's loopback
+    fn dial(&self, port: u16) -> io::Result<TcpStream> {
+        let channel = self.channel.lock().map_err(|_| io::Error::other("dial channel poisoned"))?;
+        send_packet(&channel, &port.to_be_bytes(), None)?;
+
+        let mut reply = [0u8; 4];
+        let (len, fd) = recv_packet(&channel, &mut reply)?;
+        match (len, fd) {
+            (4, Some(fd)) if reply == [0; 4] => Ok(TcpStream::from(fd)),
+            (4, _) => Err(io::Error::from_raw_os_error(i32::from_ne_bytes(reply))),
+            _ => Err(io::Error::new(io::ErrorKind::BrokenPipe, "jail has exited")),
+        }
+    }
+}
+
+/// Jail end of the dial channel, served by the jail's init
+#[derive(Debug)]
+pub struct JailDialer {
+    channel: OwnedFd,
+}
+
+impl JailDialer {
+    /// Answer dial requests on a background thread until the host hangs up
+    pub fn spawn(self) {
+        thread::spawn(move || {
+            let mut request = [0u8; 2];
+            while let Ok((2, _)) = recv_packet(&self.channel, &mut request) {
+                let port = u16::from_be_bytes(request);
+                let sent = match connect_loopback(port) {
+                    Ok(stream) => send_packet(&self.channel, &[0; 4], Some(stream.as_raw_fd())),
+                    Err(e) => {
+                        let errno = e.raw_os_error().unwrap_or(libc::ECONNREFUSED);
+                        send_packet(&self.channel, &errno.to_ne_bytes(), None)
+                    }
+                };
+                if sent.is_err() {
+                    return;
+                }
+            }
+        });
+    }
+}
+
+/// Connect to a server on loopback, whichever address family it listens on
+fn connect_loopback(port: u16) -> io::Result<TcpStream> {
+    TcpStream::connect(("127.0.0.1", port)).or_else(|_| TcpStream::connect(("::1", port)))
+}
+
+/// Current mappings of a jail, read when a session starts publishing them
+#[derive(Clone)]
+pub struct Mappings(Arc<dyn Fn() -> Vec<PortMapping> + Send + Sync>);
+
+impl fmt::Debug for Mappings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Mappings").finish_non_exhaustive()
+    }
+}
+
+impl Mappings {
+    pub fn new(mappings: impl Fn() -> Vec<PortMapping> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(mappings))
+    }
+
+    fn get(&self) -> Vec<PortMapping> {
+        (self.0)()
+    }
+}
+
+/// Host side of the published ports
+#[derive(Debug)]
+pub struct Forwarder {
+    control: SharedSocket,
+    routes: Arc<Routes>,
+}
+
+#[derive(Debug)]
+struct Routes {
+    /// Dial channel into a private network namespace, if the jail has one
+    jail: Option<DialClient>,
+    /// Jail port for each published host port
+    table: Mutex<HashMap<u16, u16>>,
+}
+
+impl Forwarder {
+    /// Bind the control socket, unless another session of the jail publishes its ports
+    ///
+    /// `jail` is the dial channel for jails with a private network namespace.
+    pub fn bind(socket: &Path, lock: &Path, jail: Option<DialClient>) -> Result<Self> {
+        Ok(Self {
+            control: SharedSocket::open(socket, lock, "port control")?,
+            routes: Arc::new(Routes { jail, table: Mutex::new(HashMap::new()) }),
+        })
+    }
+
+    /// Publish `mappings` and accept updates on a background thread, once this session serves them
+    ///
+    /// Ports that can't be published are reported as warnings.
+    pub fn spawn(&mut self, mappings: Mappings) {
+        let routes = Arc::clone(&self.routes);
+        self.control.serve(move |control| {
+            for problem in routes.apply(&mappings.get()) {
+                eprintln!("warning: {problem}");
+            }
+            for stream in control.incoming().flatten() {
+                let _ = handle_update(stream, &routes);
+            }
+        });
+    }
+
+    /// Unpublish all ports and remove the control socket
+    pub fn stop(self) {
+        self.routes.apply(&[]);
+        self.control.close();
+    }
+}
+
+impl Routes {
+    /// Make the published ports match `mappings`, returning what failed
+    fn apply(self: &Arc<Self>, mappings: &[PortMapping]) -> Vec<String> {
+        let mut problems = vec![];
+        let mut table = match self.table.lock() {
+            Ok(table) => table,
+            Err(_) => return vec!["port table poisoned".to_string()],
+        };
+
+        let stale: Vec<u16> = table
+            .keys()
+            .filter(|&&port| !mappings.iter().any(|m| m.host == port))
+            .copied()
+            .collect();
+        for port in &stale {
+            table.remove(port);
+        }
+
+        for mapping in mappings {
+            // A server in a shared network namespace is already listening there
+            if self.jail.is_none() && mapping.host == mapping.jail {
+                continue;
+            }
+            if let Some(jail) = table.get_mut(&mapping.host) {
+                *jail = mapping.jail;
+                continue;
+            }
+
+            match TcpListener::bind(("127.0.0.1", mapping.host)) {
+                Ok(listener) => {
+                    table.insert(mapping.host, mapping.jail);
+                    let routes = Arc::clone(self);
+                    let host = mapping.host;
+                    thread::spawn(move || routes.serve(host, listener));
+                }
+                Err(e) => problems.push(format!("cannot publish port {}: {e}", mapping.host)),
+            }
+        }
+        drop(table);
+
+        // Listeners notice they were removed on their next connection
+        for port in stale {
+            let _ = TcpStream::connect(("127.0.0.1", port));
+        }
+
+        problems
+    }
+
+    /// Relay connections to a host port until it is unpublished
+    fn serve(self: Arc<Self>, host: u16, listener: TcpListener) {
+        for stream in listener.incoming() {
+            let Some(jail) = self.table.lock().ok().and_then(|table| table.get(&host).copied()) else {
+                return;
+            };
+            let Ok(stream) = stream else {
+                continue;
+            };
+
+            let routes = Arc::clone(&self);
+            thread::spawn(move || {
+                let upstream = match routes.jail {
+                    Some(ref client) => client.dial(jail),
+                    None => connect_loopback(jail),
+                };
+                if let Ok(upstream) = upstream {
+                    let _ = proxy::relay(stream, upstream);
+                }
+            });
+        }
+    }
+}
+
+/// Line that ends an update, so a connection closed early changes nothing
+const END_OF_UPDATE: &str = "end";
+
+/// Read a full mapping list from `robojail port` and apply it
+fn handle_update(mut stream: UnixStream, routes: &Arc<Routes>) -> io::Result<()> {
+    let mut request = String::new();
+    stream.read_to_string(&mut request)?;
+    let Some(request) = request.strip_suffix(&format!("{END_OF_UPDATE}\n")) else {
+        return Ok(());
+    };
+
+    let mut mappings = vec![];
+    let mut problems = vec![];
+    for line in request.lines().filter(|line| !line.is_empty()) {
+        match line.parse::<PortMapping>() {
+            Ok(mapping) => mappings.push(mapping),
+            Err(e) => problems.push(e.to_string()),
+        }
+    }
+    problems.extend(routes.apply(&mappings));
+
+    for problem in problems {
+        writeln!(stream, "{problem}")?;
+    }
+    Ok(())
+}
+
+/// Send the full mapping list to the robojail process running a jail
+///
+/// Returns `None` if the jail isn't running, otherwise the ports that
+/// could not be published.
+pub fn update_running(socket: &Path, mappings: &[PortMapping]) -> Result<Option<Vec<String>>> {
+    let mut stream = match UnixStream::connect(socket) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    for mapping in mappings {
+        writeln!(stream, "{mapping}")?;
+    }
+    writeln!(stream, "{END_OF_UPDATE}")?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(Some(reply.lines().map(str::to_string).collect()))
+}
+
+/// Send one packet, optionally carrying a file descriptor
+fn send_packet(channel: &OwnedFd, data: &[u8], fd: Option<RawFd>) -> io::Result<()> {
+    let mut iov = libc::iovec { iov_base: data.as_ptr() as *mut libc::c_void, iov_len: data.len() };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+
+    if let Some(fd) = fd {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<RawFd>() as u32) as usize;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>(), fd);
+        }
+    }
+
+    if unsafe { libc::sendmsg(channel.as_raw_fd(), &msg, libc::MSG_NOSIGNAL) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive one packet and the file descriptor it carries, if any
+///
+/// A length of 0 means the other end hung up.
+fn recv_packet(channel: &OwnedFd, buf: &mut [u8]) -> io::Result<(usize, Option<OwnedFd>)> {
+    let mut iov = libc::iovec { iov_base: buf.as_mut_ptr().cast(), iov_len: buf.len() };
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control);
+
+    let len = loop {
+        let len = unsafe { libc::recvmsg(channel.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+        if len >= 0 {
+            break len as usize;
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    };
+
+    let mut fd = None;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if !cmsg.is_null() && (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+            let raw = std::ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>());
+            fd = Some(OwnedFd::from_raw_fd(raw));
+        }
+    }
+
+    Ok((len, fd))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mapping() {
+        assert_eq!("3000:8080".parse::<PortMapping>().unwrap(), PortMapping { host: 3000, jail: 8080 });
+        assert_eq!("8080".parse::<PortMapping>().unwrap(), PortMapping { host: 8080, jail: 8080 });
+        assert!("0:80".parse::<PortMapping>().is_err());
+        assert!("3000:".parse::<PortMapping>().is_err());
+        assert!("70000".parse::<PortMapping>().is_err());
+        assert!("localhost:80".parse::<PortMapping>().is_err());
+
+        let json = serde_json::to_string(&PortMapping { host: 3000, jail: 8080 }).unwrap();
+        assert_eq!(json, "\"3000:8080\"");
+        assert_eq!(serde_json::from_str::<PortMapping>(&json).unwrap().jail, 8080);
+    }
+
+    #[test]
+    fn test_check_unique() {
+        let mappings = ["3000:80".parse().unwrap(), "3001:80".parse().unwrap()];
+        assert!(check_unique(&mappings).is_ok());
+        let mappings = ["3000:80".parse().unwrap(), "3000:81".parse().unwrap()];
+        assert!(check_unique(&mappings).is_err());
+    }
+
+    #[test]
+    fn test_update_needs_end() {
+        let routes = Arc::new(Routes { jail: None, table: Mutex::new(HashMap::from([(40000, 80)])) });
+        let update = |request: &[u8]| {
+            let (mut client, server) = UnixStream::pair().unwrap();
+            client.write_all(request).unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+            handle_update(server, &routes).unwrap();
+            routes.table.lock().unwrap().len()
+        };
+
+        // Such as a session checking whether the socket is served
+        assert_eq!(update(b""), 1);
+        assert_eq!(update(b"40001:80\n"), 1);
+        assert_eq!(update(b"end\n"), 0);
+    }
+
+    #[test]
+    fn test_dial_passes_connected_socket() {
+        let server = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = server.local_addr().unwrap().port();
+
+        let (client, dialer) = dial_pair().unwrap();
+        dialer.spawn();
+
+        let mut stream = client.dial(port).unwrap();
+        let (mut accepted, _) = server.accept().unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut buf = [0u8; 4];
+        accepted.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ping");
+
+        drop(server);
+        assert!(client.dial(port).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+//! Allowlisting HTTP(S) proxy for the `proxy` network mode
+//!
+//! Jails in proxy mode get a private network namespace whose only way out is
+//! this proxy. The host side listens on a Unix socket whose directory is
+//! bind-mounted into the jail; inside, the jail's init relays 127.0.0.1:3128
+//! to that socket, and the standard proxy environment variables point at it.
+//! Like published ports, the proxy of a jail is served by one of its sessions
+//! at a time; mounting the directory lets the jail see the socket again when
+//! another session takes it over.
+//!
+//! Only `CONNECT` (for HTTPS) and plain HTTP requests with an absolute URI
+//! are supported. Every decision is appended to the jail's network log.
+
+use super::SharedSocket;
+use crate::error::{Error, Result};
+use chrono::Utc;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Port the in-jail relay listens on
+pub const JAIL_PROXY_PORT: u16 = 3128;
+
+/// Name of the proxy socket in its directory
+pub const SOCKET_NAME: &str = "proxy.sock";
+
+/// Where the directory of the proxy socket is mounted inside the jail
+pub const JAIL_SOCKET_DIR: &str = "/run/robojail/proxy";
+
+/// Maximum size of a request head we are willing to buffer
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Timeout for connecting to upstream servers
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Host side of the proxy
+#[derive(Debug)]
+pub struct ProxyServer {
+    socket: SharedSocket,
+    policy: Arc<Policy>,
+}
+
+#[derive(Debug)]
+struct Policy {
+    allow: Vec<String>,
+    log: Option<Mutex<File>>,
+}
+
+impl ProxyServer {
+    /// Bind the proxy socket in `dir` and open the log
+    ///
+    /// One proxy serves all sessions of a jail; if another session runs it,
+    /// the socket is left to that session until it exits.
+    pub fn bind(dir: &Path, lock: &Path, allow: Vec<String>, log_path: Option<&Path>) -> Result<Self> {
+        let socket = SharedSocket::open(&dir.join(SOCKET_NAME), lock, "proxy")?;
+        let log = log_path.map(open_log).transpose()?;
+
+        Ok(Self {
+            socket,
+            policy: Arc::new(Policy {
+                allow,
+                log: log.map(Mutex::new),
+            }),
+        })
+    }
+
+    /// Serve connections on a background thread, once this session serves the proxy
+    pub fn spawn(&mut self) {
+        let policy = Arc::clone(&self.policy);
+        self.socket.serve(move |listener| {
+            for stream in listener.incoming().flatten() {
+                let policy = Arc::clone(&policy);
+                thread::spawn(move || {
+                    let _ = handle_client(stream, &policy);
+                });
+            }
+        });
+    }
+
+    /// Remove the proxy socket if this session serves it
+    pub fn stop(self) {
+        self.socket.close();
+    }
+}
+
+impl Policy {
+    fn log(&self, verdict: &str, method: &str, host: &str, port: u16) {
+        if let Some(mut log) = self.log.as_ref().and_then(|log| log.lock().ok()) {
+            let _ = writeln!(
+                log,
+                "{} {} {} {}:{}",
+                Utc::now().format("%Y-%m-%dT%H:%M:%SZ"),
+                verdict,
+                method,
+                host,
+                port
+            );
+        }
+    }
+}
+
+/// Open a network log for appending
+pub fn open_log(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(OpenOptions::new().create(true).append(true).open(path)?)
+}
+
+/// Handle one proxied request from the jail
+fn handle_client(mut client: UnixStream, policy: &Policy) -> io::Result<()> {
+    let (head, rest) = read_head(&mut client)?;
+    let head_str = String::from_utf8_lossy(&head).to_string();
+
+    let mut lines = head_str.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (method, target, version) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(m), Some(t), Some(v)) => (m, t, v),
+        _ => return respond(&mut client, "400 Bad Request", "malformed request"),
+    };
+
+    let Some(request) = parse_target(method, target) else {
+        return respond(&mut client, "400 Bad Request", "unsupported proxy request");
+    };
+
+    if !is_allowed(&request.host, &policy.allow) {
+        policy.log("DENY", method, &request.host, request.port);
+        return respond(&mut client, "403 Forbidden", "destination not in the jail's network allowlist");
+    }
+
+    let upstream = match connect_upstream(&request.host, request.port) {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            policy.log("ERROR", method, &request.host, request.port);
+            return respond(&mut client, "502 Bad Gateway", &e.to_string());
+        }
+    };
+    policy.log("ALLOW", method, &request.host, request.port);
+
+    let mut upstream_write = upstream.try_clone()?;
+    if method.eq_ignore_ascii_case("CONNECT") {
+        client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+    } else {
+        // Forward the request in origin form, one request per connection so
+        // later requests can't be smuggled to a different host
+        let mut forwarded = format!("{} {} {}\r\n", method, request.path, version);
+        for line in lines.filter(|l| !l.is_empty()) {
+            let name = line.split(':').next().unwrap_or_default().trim().to_ascii_lowercase();
+            let hop_by_hop = matches!(
+                name.as_str(),
+                "connection" | "proxy-connection" | "proxy-authorization" | "keep-alive"
+            );
+            if !hop_by_hop {
+                forwarded.push_str(line);
+                forwarded.push_str("\r\n");
+            }
+        }
+        forwarded.push_str("Connection: close\r\n\r\n");
+        upstream_write.write_all(forwarded.as_bytes())?;
+    }
+    upstream_write.write_all(&rest)?;
+
+    relay(client, upstream)
+}
+
+/// Destination of a proxied request
+#[derive(Debug, PartialEq)]
+struct Target {
+    host: String,
+    port: u16,
+    /// Origin-form path for plain HTTP requests
+    path: String,
+}
+
+/// Parse a CONNECT authority or an absolute http:// URI
+fn parse_target(method: &str, target: &str) -> Option<Target> {
+    if method.eq_ignore_ascii_case("CONNECT") {
+        let (host, port) = split_host_port(target)?;
+        return Some(Target { host, port: port?, path: String::new() });
+    }
+
+    let rest = target.strip_prefix("http://")?;
+    let (authority, path) = match rest.find('/') {
+        Some(idx) => (&rest[..idx], &rest[idx..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = split_host_port(authority)?;
+    Some(Target { host, port: port.unwrap_or(80), path: path.to_string() })
+}
+
+/// Split "host:port" (with optional [v6] brackets), lower-casing the host
+fn split_host_port(authority: &str) -> Option<(String, Option<u16>)> {
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(p) => Some(p.parse().ok()?),
+        None => None,
+    };
+    Some((host.trim_end_matches('.').to_ascii_lowercase(), port))
+}
+
+/// Check a host against the allowlist
+///
+/// Entries match exactly; `*.example.com` matches any subdomain and
+/// `.example.com` matches the domain and all of its subdomains.
+fn is_allowed(host: &str, allow: &[String]) -> bool {
+    allow.iter().any(|entry| {
+        let entry = entry.trim().trim_end_matches('.').to_ascii_lowercase();
+        if let Some(suffix) = entry.strip_prefix("*.") {
+            host.ends_with(&format!(".{suffix}"))
+        } else if let Some(suffix) = entry.strip_prefix('.') {
+            host == suffix || host.ends_with(&entry)
+        } else {
+            host == entry
+        }
+    })
+}
+
+/// Connect to an allowed destination
+///
+/// Hostnames that resolve to loopback or link-local addresses are refused,
+/// so an allowed name can't be used to reach services on the host itself.
+fn connect_upstream(host: &str, port: u16) -> io::Result<TcpStream> {
+    let literal = host.parse::<IpAddr>().is_ok();
+    let addrs: Vec<SocketAddr> = (host, port).to_socket_addrs()?.collect();
+
+    let mut last_err = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+    for addr in addrs {
+        if !literal && is_local(addr.ip()) {
+            last_err = io::Error::new(io::ErrorKind::PermissionDenied, "resolves to a local address");
+            continue;
+        }
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+fn is_local(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_link_local() || v4.is_unspecified(),
+        IpAddr::V6(v6) => {
+            v6.is_loopback() || v6.is_unspecified() || (v6.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// Read up to the end of the request head, returning the head and any extra bytes
+fn read_head(stream: &mut impl Read) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = stream.read(&mut chunk)?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+
+        if let Some(idx) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            let rest = buf.split_off(idx + 4);
+            return Ok((buf, rest));
+        }
+        if buf.len() > MAX_HEAD_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request head too large"));
+        }
+    }
+}
+
+fn respond(client: &mut UnixStream, status: &str, message: &str) -> io::Result<()> {
+    let body = format!("robojail: {message}\n");
+    write!(
+        client,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// A stream that can be half-closed
+pub(super) trait HalfClose: Read + Write + Send + 'static {
+    fn shutdown_write(&self);
+}
+
+impl HalfClose for TcpStream {
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+impl HalfClose for UnixStream {
+    fn shutdown_write(&self) {
+        let _ = self.shutdown(Shutdown::Write);
+    }
+}
+
+/// Copy data both ways until each side has closed
+pub(super) fn relay<A, B>(a: A, b: B) -> io::Result<()>
+where
+    A: HalfClose + TryClone,
+    B: HalfClose + TryClone,
+{
+    let (mut a_read, mut a_write) = (a.try_clone()?, a);
+    let (mut b_read, mut b_write) = (b.try_clone()?, b);
+
+    let upstream = thread::spawn(move || {
+        let _ = io::copy(&mut a_read, &mut b_write);
+        b_write.shutdown_write();
+    });
+
+    let _ = io::copy(&mut b_read, &mut a_write);
+    a_write.shutdown_write();
+    let _ = upstream.join();
+
+    Ok(())
+}
+
+pub(super) trait TryClone: Sized {
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl TryClone for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
+
+impl TryClone for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+}
+
+/// Jail side of the proxy: relays 127.0.0.1:3128 to the proxy socket
+#[derive(Debug)]
+pub struct JailRelay {
+    listener: TcpListener,
+}
+
+impl JailRelay {
+    /// Bind the relay port (loopback must already be up)
+    pub fn bind() -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", JAIL_PROXY_PORT)).map_err(|e| {
+            Error::SandboxSetup(format!("failed to bind proxy relay on port {JAIL_PROXY_PORT}: {e}"))
+        })?;
+        Ok(Self { listener })
+    }
+
+    /// Relay connections on a background thread
+    pub fn spawn(self) {
+        thread::spawn(move || {
+            for stream in self.listener.incoming().flatten() {
+                thread::spawn(move || {
+                    if let Ok(socket) = UnixStream::connect(Path::new(JAIL_SOCKET_DIR).join(SOCKET_NAME)) {
+                        let _ = relay(stream, socket);
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            parse_target("CONNECT", "api.anthropic.com:443"),
+            Some(Target { host: "api.anthropic.com".to_string(), port: 443, path: String::new() })
+        );
+        assert_eq!(
+            parse_target("GET", "http://Example.COM/index.html?q=1"),
+            Some(Target { host: "example.com".to_string(), port: 80, path: "/index.html?q=1".to_string() })
+        );
+        assert_eq!(
+            parse_target("GET", "http://[::1]:8080"),
+            Some(Target { host: "::1".to_string(), port: 8080, path: "/".to_string() })
+        );
+        assert_eq!(parse_target("CONNECT", "example.com"), None);
+        assert_eq!(parse_target("GET", "/relative"), None);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let allow = vec![
+            "crates.io".to_string(),
+            "*.githubusercontent.com".to_string(),
+            ".github.com".to_string(),
+        ];
+
+        assert!(is_allowed("crates.io", &allow));
+        assert!(!is_allowed("static.crates.io", &allow));
+        assert!(is_allowed("raw.githubusercontent.com", &allow));
+        assert!(!is_allowed("githubusercontent.com", &allow));
+        assert!(is_allowed("github.com", &allow));
+        assert!(is_allowed("api.github.com", &allow));
+        assert!(!is_allowed("evilgithub.com", &allow));
+    }
+
+    #[test]
+    fn test_read_head_keeps_body() {
+        let mut input: &[u8] = b"POST http://a/ HTTP/1.1\r\nHost: a\r\n\r\nbody";
+        let (head, rest) = read_head(&mut input).unwrap();
+        assert!(head.ends_with(b"\r\n\r\n"));
+        assert_eq!(rest, b"body");
+    }
+}
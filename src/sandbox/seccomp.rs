@@ -0,0 +1,439 @@
+//! Seccomp-BPF syscall filtering
+//!
+//! Builds a classic BPF program that makes a denylist of syscalls fail with
+//! EPERM, and installs it right before exec. The denylist targets kernel
+//! attack surface and sandbox-escape primitives (keyrings, BPF, kexec,
+//! handle-based opens, terminal injection, nested user namespaces) that
+//! development tools have no business using.
+//!
+//! The `filtered` network mode adds a second filter that hands socket
+//! syscalls carrying a destination address to a supervisor through a seccomp
+//! user notification listener.
+
+use crate::config::SeccompProfile;
+use crate::error::{Error, Result};
+use libc::sock_filter;
+use std::os::fd::{FromRawFd, OwnedFd};
+use syscalls::Sysno;
+
+/// AUDIT_ARCH_* value for the architecture we were built for
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+/// Syscall numbers with this bit set belong to the x32 ABI on x86_64
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+// ptrace requests that attach to a process we did not spawn
+const PTRACE_ATTACH: u32 = 16;
+const PTRACE_SEIZE: u32 = 0x4206;
+
+/// A single filter rule
+#[derive(Debug, Clone, Copy)]
+enum Rule {
+    /// Fail the syscall with `errno`
+    Deny { nr: i64, errno: i32 },
+    /// Fail with EPERM when the low 32 bits of argument `arg` equal `value`
+    DenyArgEq { nr: i64, arg: u32, value: u32 },
+    /// Fail with EPERM when argument `arg` has any bit of `mask` set
+    DenyArgMask { nr: i64, arg: u32, mask: u32 },
+    /// Ask the supervisor
+    Notify { nr: i64 },
+    /// Ask the supervisor when the 64-bit argument `arg` is non-zero
+    NotifyArgSet { nr: i64, arg: u32 },
+}
+
+fn deny(nr: libc::c_long) -> Rule {
+    Rule::Deny { nr, errno: libc::EPERM }
+}
+
+/// Syscalls denied by the default profile
+const DEFAULT_DENY: &[&str] = &[
+    // Kernel keyrings are not namespaced
+    "keyctl",
+    "add_key",
+    "request_key",
+    // Large kernel attack surface
+    "bpf",
+    "perf_event_open",
+    "userfaultfd",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+    // Loading kernels and modules, host-level administration
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "reboot",
+    "swapon",
+    "swapoff",
+    "acct",
+    "quotactl",
+    "syslog",
+    "vhangup",
+    "lookup_dcookie",
+    "nfsservctl",
+    // Opening files by handle bypasses the mount namespace
+    "open_by_handle_at",
+    // Setting the host clock
+    "settimeofday",
+    "clock_settime",
+    "clock_adjtime",
+    "adjtimex",
+    #[cfg(target_arch = "x86_64")]
+    "iopl",
+    #[cfg(target_arch = "x86_64")]
+    "ioperm",
+    #[cfg(target_arch = "x86_64")]
+    "uselib",
+];
+
+/// Additional syscalls denied by the strict profile
+const STRICT_DENY: &[&str] = &[
+    "ptrace",
+    "process_vm_readv",
+    "process_vm_writev",
+    "kcmp",
+    "personality",
+    "name_to_handle_at",
+    "fanotify_init",
+    "mount",
+    "umount2",
+    "pivot_root",
+    "chroot",
+    "setns",
+    "unshare",
+    "mount_setattr",
+    "move_mount",
+    "open_tree",
+    "fsopen",
+    "fsconfig",
+    "fsmount",
+    "fspick",
+];
+
+/// A compiled seccomp filter
+#[derive(Debug, Clone)]
+pub struct Filter {
+    program: Vec<sock_filter>,
+}
+
+impl Filter {
+    /// Build the filter for a profile plus user-supplied extra syscalls
+    ///
+    /// Returns `None` when there is nothing to filter (the `off` profile
+    /// with no extra syscalls).
+    pub fn new(profile: SeccompProfile, extra_deny: &[String]) -> Result<Option<Self>> {
+        let mut rules = Vec::new();
+
+        match profile {
+            SeccompProfile::Off => {}
+            SeccompProfile::Default => {
+                rules.extend(DEFAULT_DENY.iter().map(|name| named_rule(name)));
+                rules.extend(default_arg_rules());
+            }
+            SeccompProfile::Strict => {
+                rules.extend(DEFAULT_DENY.iter().map(|name| named_rule(name)));
+                rules.extend(STRICT_DENY.iter().map(|name| named_rule(name)));
+                rules.extend(default_arg_rules());
+            }
+        }
+
+        for name in extra_deny {
+            let nr = syscall_number(name).ok_or_else(|| {
+                Error::Config(format!("unknown syscall in seccomp_deny: '{name}'"))
+            })?;
+            rules.push(deny(nr));
+        }
+
+        if rules.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self { program: compile(&rules) }))
+    }
+
+    /// Build the filter for the `filtered` network mode
+    ///
+    /// Every syscall that can name a destination is sent to the supervisor,
+    /// and io_uring, which could connect without a syscall, is denied.
+    pub fn network_supervised() -> Self {
+        let rules = [
+            deny(libc::SYS_io_uring_setup),
+            deny(libc::SYS_io_uring_enter),
+            deny(libc::SYS_io_uring_register),
+            Rule::Notify { nr: libc::SYS_connect },
+            Rule::NotifyArgSet { nr: libc::SYS_sendto, arg: 4 },
+            Rule::Notify { nr: libc::SYS_sendmsg },
+            Rule::Notify { nr: libc::SYS_sendmmsg },
+        ];
+
+        Self { program: compile(&rules) }
+    }
+
+    /// Install the filter on the calling thread
+    ///
+    /// Requires PR_SET_NO_NEW_PRIVS to already be set. The filter is
+    /// inherited across fork and exec and cannot be removed.
+    pub fn install(&self) -> Result<()> {
+        let prog = libc::sock_fprog {
+            len: self.program.len() as u16,
+            filter: self.program.as_ptr() as *mut sock_filter,
+        };
+
+        let result = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+                0,
+                0,
+            )
+        };
+
+        if result != 0 {
+            return Err(Error::SandboxSetup(format!(
+                "failed to install seccomp filter: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Install the filter and return its user notification listener
+    ///
+    /// Same requirements as `install`; needs Linux 5.0 or later.
+    pub fn install_with_listener(&self) -> Result<OwnedFd> {
+        let prog = libc::sock_fprog {
+            len: self.program.len() as u16,
+            filter: self.program.as_ptr() as *mut sock_filter,
+        };
+
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                libc::SECCOMP_FILTER_FLAG_NEW_LISTENER,
+                &prog as *const libc::sock_fprog,
+            )
+        };
+
+        if fd < 0 {
+            return Err(Error::SandboxSetup(format!(
+                "failed to install seccomp notification filter: {}",
+                std::io::Error::last_os_error()
+            )));
+        }
+
+        Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+    }
+}
+
+/// Resolve a rule for a name from the built-in lists (always known)
+fn named_rule(name: &str) -> Rule {
+    deny(syscall_number(name).expect("built-in seccomp denylist contains unknown syscall"))
+}
+
+/// Argument-sensitive rules shared by the default and strict profiles
+fn default_arg_rules() -> Vec<Rule> {
+    vec![
+        // Injecting input into (or poking at) the controlling terminal
+        Rule::DenyArgEq { nr: libc::SYS_ioctl, arg: 1, value: libc::TIOCSTI as u32 },
+        Rule::DenyArgEq { nr: libc::SYS_ioctl, arg: 1, value: libc::TIOCLINUX as u32 },
+        // Tracing processes we didn't spawn (PTRACE_TRACEME from a child still works)
+        Rule::DenyArgEq { nr: libc::SYS_ptrace, arg: 0, value: PTRACE_ATTACH },
+        Rule::DenyArgEq { nr: libc::SYS_ptrace, arg: 0, value: PTRACE_SEIZE },
+        // Nested user namespaces would hand the jail a fresh set of capabilities
+        Rule::DenyArgMask { nr: libc::SYS_unshare, arg: 0, mask: libc::CLONE_NEWUSER as u32 },
+        Rule::DenyArgMask { nr: libc::SYS_clone, arg: 0, mask: libc::CLONE_NEWUSER as u32 },
+        // clone3 passes its flags in memory we can't inspect; ENOSYS makes
+        // libc fall back to clone, which we can
+        Rule::Deny { nr: libc::SYS_clone3, errno: libc::ENOSYS },
+    ]
+}
+
+const fn stmt(code: u32, k: u32) -> sock_filter {
+    sock_filter { code: code as u16, jt: 0, jf: 0, k }
+}
+
+const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code: code as u16, jt, jf, k }
+}
+
+/// Offset of the syscall number in struct seccomp_data
+const NR_OFFSET: u32 = 0;
+/// Offset of the architecture in struct seccomp_data
+const ARCH_OFFSET: u32 = 4;
+
+/// Offset of the low 32 bits of argument `arg` in struct seccomp_data
+const fn arg_low_offset(arg: u32) -> u32 {
+    let offset = 16 + 8 * arg;
+    if cfg!(target_endian = "little") {
+        offset
+    } else {
+        offset + 4
+    }
+}
+
+/// Offset of the high 32 bits of argument `arg` in struct seccomp_data
+const fn arg_high_offset(arg: u32) -> u32 {
+    let offset = 16 + 8 * arg;
+    if cfg!(target_endian = "little") {
+        offset + 4
+    } else {
+        offset
+    }
+}
+
+/// Compile rules into a BPF program
+///
+/// Each rule block leaves the syscall number in the accumulator so the next
+/// block can compare against it.
+fn compile(rules: &[Rule]) -> Vec<sock_filter> {
+    use libc::{BPF_ABS, BPF_JEQ, BPF_JMP, BPF_JSET, BPF_K, BPF_LD, BPF_RET, BPF_W};
+
+    let ret_errno = |errno: i32| stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA));
+    let load_nr = stmt(BPF_LD | BPF_W | BPF_ABS, NR_OFFSET);
+    let ret_notify = stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_USER_NOTIF);
+
+    let mut program = vec![
+        // Kill anything using a syscall ABI other than our own
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        load_nr,
+    ];
+
+    #[cfg(target_arch = "x86_64")]
+    program.extend([
+        jump(BPF_JMP | libc::BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+        ret_errno(libc::EPERM),
+    ]);
+
+    for rule in rules {
+        match *rule {
+            Rule::Deny { nr, errno } => program.extend([
+                jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1),
+                ret_errno(errno),
+            ]),
+            Rule::DenyArgEq { nr, arg, value } => program.extend([
+                jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 4),
+                stmt(BPF_LD | BPF_W | BPF_ABS, arg_low_offset(arg)),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, value, 0, 1),
+                ret_errno(libc::EPERM),
+                load_nr,
+            ]),
+            Rule::DenyArgMask { nr, arg, mask } => program.extend([
+                jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 4),
+                stmt(BPF_LD | BPF_W | BPF_ABS, arg_low_offset(arg)),
+                jump(BPF_JMP | BPF_JSET | BPF_K, mask, 0, 1),
+                ret_errno(libc::EPERM),
+                load_nr,
+            ]),
+            Rule::Notify { nr } => program.extend([
+                jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1),
+                ret_notify,
+            ]),
+            Rule::NotifyArgSet { nr, arg } => program.extend([
+                jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 6),
+                stmt(BPF_LD | BPF_W | BPF_ABS, arg_low_offset(arg)),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, 0, 0, 2),
+                stmt(BPF_LD | BPF_W | BPF_ABS, arg_high_offset(arg)),
+                jump(BPF_JMP | BPF_JEQ | BPF_K, 0, 1, 0),
+                ret_notify,
+                load_nr,
+            ]),
+        }
+    }
+
+    program.push(stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+    program
+}
+
+/// Look up a syscall number by name (or accept a raw number)
+///
+/// Names come from the full table of the architecture we were built for;
+/// numbers past its last syscall are rejected.
+fn syscall_number(name: &str) -> Option<libc::c_long> {
+    let Ok(nr) = name.parse::<u32>() else {
+        return name.parse::<Sysno>().ok().map(|sysno| sysno.id().into());
+    };
+    (nr <= Sysno::last().id() as u32).then_some(nr.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_profiles_resolve() {
+        assert!(Filter::new(SeccompProfile::Default, &[]).unwrap().is_some());
+        assert!(Filter::new(SeccompProfile::Strict, &[]).unwrap().is_some());
+        assert!(Filter::new(SeccompProfile::Off, &[]).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_extra_deny() {
+        let extra = vec!["socket".to_string(), "321".to_string()];
+        assert!(Filter::new(SeccompProfile::Off, &extra).unwrap().is_some());
+
+        let unknown = vec!["not_a_syscall".to_string()];
+        assert!(Filter::new(SeccompProfile::Default, &unknown).is_err());
+    }
+
+    #[test]
+    fn test_syscall_number() {
+        assert_eq!(syscall_number("getcwd"), Some(libc::SYS_getcwd));
+        assert_eq!(syscall_number("futex_waitv"), Some(libc::SYS_futex_waitv));
+        assert_eq!(syscall_number("0"), Some(0));
+
+        let last = Sysno::last().id();
+        assert_eq!(syscall_number(&last.to_string()), Some(last.into()));
+        for invalid in [(last + 1).to_string(), "-1".to_string(), "4294967296".to_string(), "SYS_getcwd".to_string()] {
+            assert_eq!(syscall_number(&invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_filter_denies_with_eperm() {
+        let deny = vec!["getcwd".to_string()];
+        let filter = Filter::new(SeccompProfile::Default, &deny).unwrap().unwrap();
+
+        match unsafe { nix::unistd::fork() }.unwrap() {
+            nix::unistd::ForkResult::Child => {
+                let errno = || std::io::Error::last_os_error().raw_os_error();
+                let mut buf = [0u8; 256];
+                let code = unsafe {
+                    libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0);
+                    if filter.install().is_err() {
+                        1
+                    } else if libc::syscall(libc::SYS_getcwd, buf.as_mut_ptr(), buf.len()) != -1 || errno() != Some(libc::EPERM) {
+                        2
+                    } else if libc::syscall(libc::SYS_keyctl, 0, 0, 0, 0, 0) != -1 || errno() != Some(libc::EPERM) {
+                        3
+                    } else if libc::syscall(libc::SYS_getpid) <= 0 {
+                        4
+                    } else {
+                        0
+                    }
+                };
+                unsafe { libc::_exit(code) };
+            }
+            nix::unistd::ForkResult::Parent { child } => {
+                let status = nix::sys::wait::waitpid(child, None).unwrap();
+                assert_eq!(status, nix::sys::wait::WaitStatus::Exited(child, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_program_fits_bpf_limit() {
+        let filter = Filter::new(SeccompProfile::Strict, &[]).unwrap().unwrap();
+        assert!(filter.program.len() <= libc::BPF_MAXINSNS as usize);
+    }
+}
@@ -0,0 +1,168 @@
+//! Userspace networking for the `slirp` network mode
+//!
+//! The jail keeps its own network namespace, but slirp4netns, running on the
+//! host side as the invoking user, attaches a tap device to it and translates
+//! the jail's traffic into ordinary host sockets. Host loopback is not
+//! reachable through the gateway, so services bound to 127.0.0.1 on the host
+//! stay out of reach while the internet does not.
+//!
+//! slirp4netns needs the PID of a process in the namespace, so the jail waits
+//! on a pipe after unsharing until the host side has attached the network.
+//!
+//! The network stack itself is not part of robojail; slirp4netns is an
+//! external dependency of this mode, installed from the distribution.
+
+use crate::error::{Error, Result};
+use nix::fcntl::OFlag;
+use nix::unistd::{pipe2, Pid};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+
+/// Name of the userspace network helper
+const HELPER: &str = "slirp4netns";
+
+/// DNS forwarder provided by slirp4netns
+pub const NAMESERVER: &str = "10.0.2.3";
+
+/// How to install the helper on common distributions
+const INSTALL_HINT: &str = "pacman -S slirp4netns, apt install slirp4netns or dnf install slirp4netns";
+
+/// Find the helper binary in PATH
+pub fn find_helper() -> Result<PathBuf> {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(HELPER))
+        .find(|candidate| candidate.is_file())
+        .ok_or_else(|| {
+            Error::SandboxSetup(format!(
+                "the slirp network mode requires {HELPER}, which was not found in PATH; install it with your package manager ({INSTALL_HINT}) or use another network mode"
+            ))
+        })
+}
+
+/// A running slirp4netns attached to a jail's network namespace
+#[derive(Debug)]
+pub struct Slirp {
+    child: Child,
+    /// slirp4netns exits once this is closed, even if we die first
+    exit: Option<OwnedFd>,
+}
+
+impl Slirp {
+    /// Attach userspace networking to the network namespace of `pid`
+    ///
+    /// Returns once the tap device is configured inside the namespace.
+    pub fn start(helper: &Path, pid: Pid) -> Result<Self> {
+        let (ready_rx, ready_tx) = pipe2(OFlag::O_CLOEXEC)?;
+        let (exit_rx, exit_tx) = pipe2(OFlag::O_CLOEXEC)?;
+
+        // Only slirp4netns's own ends may survive exec
+        let inherited = [ready_tx.as_raw_fd(), exit_rx.as_raw_fd()];
+        let mut command = Command::new(helper);
+        unsafe {
+            command.pre_exec(move || {
+                for fd in inherited {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) < 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+
+        let child = command
+            .arg("--configure")
+            .arg("--mtu=65520")
+            .arg("--disable-host-loopback")
+            .arg(format!("--ready-fd={}", ready_tx.as_raw_fd()))
+            .arg(format!("--exit-fd={}", exit_rx.as_raw_fd()))
+            .arg(pid.to_string())
+            .arg("tap0")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| Error::SandboxSetup(format!("failed to start {HELPER}: {e}")))?;
+
+        drop(ready_tx);
+        drop(exit_rx);
+
+        let mut slirp = Self { child, exit: Some(exit_tx) };
+
+        let mut byte = [0u8; 1];
+        match File::from(ready_rx).read(&mut byte) {
+            Ok(1) => Ok(slirp),
+            _ => {
+                slirp.stop();
+                Err(Error::SandboxSetup(format!("{HELPER} failed to configure the jail network")))
+            }
+        }
+    }
+
+    fn stop(&mut self) {
+        drop(self.exit.take());
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for Slirp {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Create the pipes the jail and the host use to hand over the network setup
+pub fn sync_pair() -> Result<(HostSync, JailSync)> {
+    let (ready_rx, ready_tx) = pipe2(OFlag::O_CLOEXEC)?;
+    let (go_rx, go_tx) = pipe2(OFlag::O_CLOEXEC)?;
+
+    Ok((
+        HostSync { ready: File::from(ready_rx), go: File::from(go_tx) },
+        JailSync { ready: File::from(ready_tx), go: File::from(go_rx) },
+    ))
+}
+
+/// Host end of the network handover
+#[derive(Debug)]
+pub struct HostSync {
+    ready: File,
+    go: File,
+}
+
+impl HostSync {
+    /// Wait until the jail's network namespace exists
+    ///
+    /// Returns false if the jail exited before getting there.
+    pub fn wait_ready(&mut self) -> bool {
+        let mut byte = [0u8; 1];
+        matches!(self.ready.read(&mut byte), Ok(1))
+    }
+
+    /// Let the jail continue with its network attached
+    pub fn release(mut self) {
+        let _ = self.go.write_all(b"1");
+    }
+}
+
+/// Jail end of the network handover
+#[derive(Debug)]
+pub struct JailSync {
+    ready: File,
+    go: File,
+}
+
+impl JailSync {
+    /// Tell the host our network namespace exists and wait for it to attach
+    pub fn wait_for_network(mut self) -> Result<()> {
+        self.ready.write_all(b"1")?;
+
+        let mut byte = [0u8; 1];
+        match self.go.read(&mut byte) {
+            Ok(1) => Ok(()),
+            _ => Err(Error::SandboxSetup("network setup was aborted".to_string())),
+        }
+    }
+}
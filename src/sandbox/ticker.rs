@@ -0,0 +1,57 @@
+//! Host-side work repeated while a jail runs
+//!
+//! The thread is only started in the parent after the jail is forked, like
+//! the proxy and port forwarder, so the child never inherits it mid-call.
+
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A task to run at a fixed interval during a session
+#[derive(Clone)]
+pub struct Periodic {
+    interval: Duration,
+    task: Arc<dyn Fn() + Send + Sync>,
+}
+
+impl fmt::Debug for Periodic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Periodic").field("interval", &self.interval).finish_non_exhaustive()
+    }
+}
+
+impl Periodic {
+    pub fn new(interval: Duration, task: impl Fn() + Send + Sync + 'static) -> Self {
+        Self { interval, task: Arc::new(task) }
+    }
+
+    /// Start running the task; the first run is one interval from now
+    pub(super) fn spawn(&self) -> Ticker {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let (interval, task) = (self.interval, self.task.clone());
+
+        let handle = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                task();
+            }
+        });
+
+        Ticker { stop, handle }
+    }
+}
+
+/// A running `Periodic` task
+pub(super) struct Ticker {
+    stop: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Ticker {
+    /// Stop the task, waiting for a run in progress to finish
+    pub(super) fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.handle.join();
+    }
+}
@@ -0,0 +1,419 @@
+//! Detached jail sessions
+//!
+//! A detached session runs under a supervisor process that owns the master
+//! side of a pseudo-terminal; the jail gets the slave side as its terminal.
+//! The supervisor keeps the recent output and relays the terminal over a
+//! Unix socket to any number of `robojail attach` clients. Clients that
+//! attached read-only only receive output.
+//!
+//! Foreground sessions that are recorded get a pseudo-terminal too, relayed
+//! to the terminal they were started from.
+//!
+//! Clients send frames of a kind byte, a big-endian `u16` length and the
+//! payload; the supervisor sends the raw terminal output. The first frame
+//! says whether the client wants to type (`a`) or only watch (`v`).
+
+use crate::error::{Error, Result};
+use crate::logs::{Log, Stream};
+use crate::recording::Recorder;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::pty::{openpty, Winsize};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use nix::unistd::{dup2, isatty, setsid};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Frame kinds sent by clients
+const FRAME_ATTACH: u8 = b'a';
+const FRAME_VIEW: u8 = b'v';
+const FRAME_INPUT: u8 = b'd';
+const FRAME_RESIZE: u8 = b'w';
+
+/// Output kept for clients that attach later
+const SCROLLBACK: usize = 64 * 1024;
+
+/// Ctrl-P Ctrl-Q, as in docker
+const DETACH_KEYS: [u8; 2] = [0x10, 0x11];
+
+/// How long a stalled client may hold up the jail's output
+const CLIENT_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Both sides of the session's pseudo-terminal
+#[derive(Debug)]
+pub struct Pty {
+    master: OwnedFd,
+    slave: OwnedFd,
+}
+
+impl Pty {
+    /// Open a pseudo-terminal of 80x24; attaching clients resize it
+    pub fn open() -> Result<Self> {
+        let size = Winsize { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 };
+        let pty = openpty(&size, None)?;
+        for fd in [&pty.master, &pty.slave] {
+            set_cloexec(fd.as_raw_fd())?;
+        }
+        Ok(Self { master: pty.master, slave: pty.slave })
+    }
+
+    /// Make the slave the calling process's controlling terminal and stdio
+    ///
+    /// For the process that runs the jail; starts a new session.
+    pub fn into_terminal(self) -> Result<()> {
+        drop(self.master);
+        setsid()?;
+        if unsafe { libc::ioctl(self.slave.as_raw_fd(), libc::TIOCSCTTY, 0) } < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        for fd in 0..=2 {
+            dup2(self.slave.as_raw_fd(), fd)?;
+        }
+        Ok(())
+    }
+
+    /// Keep only the master side, for the supervisor
+    pub fn into_master(self) -> OwnedFd {
+        self.master
+    }
+}
+
+/// Bind the session socket (replacing a stale one)
+pub fn bind(socket: &Path) -> Result<UnixListener> {
+    if let Some(dir) = socket.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let _ = fs::remove_file(socket);
+    UnixListener::bind(socket).map_err(|e| {
+        Error::SandboxSetup(format!("failed to bind session socket {}: {e}", socket.display()))
+    })
+}
+
+/// Output shared between the terminal reader and the clients
+#[derive(Default)]
+struct Output {
+    scrollback: Vec<u8>,
+    viewers: Vec<UnixStream>,
+}
+
+/// Relay the terminal to clients until every process using it has exited
+pub fn serve(master: OwnedFd, listener: UnixListener, recorder: Option<Arc<Recorder>>, log: Option<Log>) {
+    let master = Arc::new(File::from(master));
+    let output = Arc::new(Mutex::new(Output::default()));
+
+    {
+        let (master, output, recorder) = (Arc::clone(&master), Arc::clone(&output), recorder.clone());
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = accept(stream, &master, &output, recorder.clone());
+            }
+        });
+    }
+
+    // Reads fail with EIO once the last slave descriptor is closed
+    let mut buf = [0u8; 8192];
+    while let Ok(n @ 1..) = (&*master).read(&mut buf) {
+        if let Some(ref recorder) = recorder {
+            recorder.output(&buf[..n]);
+        }
+        if let Some(ref log) = log {
+            log.write(Stream::Tty, &buf[..n]);
+        }
+        let Ok(mut output) = output.lock() else { break };
+        output.scrollback.extend_from_slice(&buf[..n]);
+        let excess = output.scrollback.len().saturating_sub(SCROLLBACK);
+        output.scrollback.drain(..excess);
+        output.viewers.retain_mut(|viewer| viewer.write_all(&buf[..n]).is_ok());
+    }
+
+    if let Ok(output) = output.lock() {
+        for viewer in &output.viewers {
+            let _ = viewer.shutdown(std::net::Shutdown::Both);
+        }
+    };
+}
+
+/// Register a new client, and relay its input if it may type
+fn accept(
+    mut stream: UnixStream,
+    master: &Arc<File>,
+    output: &Mutex<Output>,
+    recorder: Option<Arc<Recorder>>,
+) -> io::Result<()> {
+    let (kind, _) = read_frame(&mut stream)?;
+    if kind != FRAME_ATTACH && kind != FRAME_VIEW {
+        return Ok(());
+    }
+    stream.set_write_timeout(Some(CLIENT_WRITE_TIMEOUT))?;
+
+    let viewer = stream.try_clone()?;
+    {
+        let mut output = output.lock().map_err(|_| io::Error::other("session output poisoned"))?;
+        stream.write_all(&output.scrollback)?;
+        output.viewers.push(viewer);
+    }
+
+    if kind == FRAME_ATTACH {
+        let master = Arc::clone(master);
+        thread::spawn(move || {
+            while let Ok((kind, payload)) = read_frame(&mut stream) {
+                match kind {
+                    FRAME_INPUT => {
+                        if let Some(ref recorder) = recorder {
+                            recorder.input(&payload);
+                        }
+                        if (&*master).write_all(&payload).is_err() {
+                            return;
+                        }
+                    }
+                    FRAME_RESIZE if payload.len() == 4 => {
+                        let rows = u16::from_be_bytes([payload[0], payload[1]]);
+                        let cols = u16::from_be_bytes([payload[2], payload[3]]);
+                        let _ = set_size(master.as_raw_fd(), rows, cols);
+                        if let Some(ref recorder) = recorder {
+                            recorder.resize(rows, cols);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Connect the calling terminal to the pseudo-terminal until every process using it has exited
+///
+/// For foreground sessions, which are only recorded when they have a terminal.
+pub fn relay(master: OwnedFd, recorder: &Recorder) -> Result<()> {
+    let master = File::from(master);
+    let stdin = io::stdin();
+    let _raw = RawMode::enable(stdin.as_fd())?;
+    let mut stdout = io::stdout();
+    let mut size = None;
+    let mut stdin_open = true;
+    let mut buf = [0u8; 8192];
+
+    loop {
+        let current = terminal_size(stdin.as_raw_fd());
+        if current.is_some() && current != size {
+            if let Some((rows, cols)) = current {
+                set_size(master.as_raw_fd(), rows, cols)?;
+                // The first size is already in the recording's header
+                if size.is_some() {
+                    recorder.resize(rows, cols);
+                }
+            }
+            size = current;
+        }
+
+        let mut fds = vec![PollFd::new(master.as_fd(), PollFlags::POLLIN)];
+        if stdin_open {
+            fds.push(PollFd::new(stdin.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut fds, PollTimeout::from(250u16)) {
+            Ok(_) | Err(nix::Error::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let ready: Vec<bool> = fds.iter().map(|fd| fd.revents().is_some_and(|events| !events.is_empty())).collect();
+
+        if ready[0] {
+            match (&master).read(&mut buf) {
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(n) => {
+                    recorder.output(&buf[..n]);
+                    stdout.write_all(&buf[..n])?;
+                    stdout.flush()?;
+                }
+            }
+        }
+
+        if stdin_open && ready[1] {
+            let n = nix::unistd::read(stdin.as_raw_fd(), &mut buf)?;
+            if n == 0 {
+                stdin_open = false;
+                continue;
+            }
+            recorder.input(&buf[..n]);
+            (&master).write_all(&buf[..n])?;
+        }
+    }
+}
+
+/// How an attached client finished
+#[derive(Debug, PartialEq, Eq)]
+pub enum Detach {
+    /// The user pressed the detach keys; the session goes on
+    Detached,
+    /// The session ended
+    Ended,
+}
+
+/// Attach the calling terminal to a session until detached or it ends
+pub fn attach(socket: &Path, read_only: bool) -> Result<Detach> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| Error::Config(format!("cannot connect to the session at {}: {e}", socket.display())))?;
+    write_frame(&mut stream, if read_only { FRAME_VIEW } else { FRAME_ATTACH }, &[])?;
+
+    // Read unbuffered, so polling the descriptor sees all pending input
+    let stdin = io::stdin();
+    let _raw = RawMode::enable(stdin.as_fd())?;
+    let mut stdout = io::stdout();
+    let mut size = None;
+    let mut pending_detach = false;
+    let mut buf = [0u8; 8192];
+
+    loop {
+        // Terminal sizes are polled rather than tracked with SIGWINCH
+        if !read_only {
+            let current = terminal_size(stdin.as_raw_fd());
+            if current.is_some() && current != size {
+                size = current;
+                if let Some((rows, cols)) = current {
+                    let mut payload = rows.to_be_bytes().to_vec();
+                    payload.extend(cols.to_be_bytes());
+                    write_frame(&mut stream, FRAME_RESIZE, &payload)?;
+                }
+            }
+        }
+
+        let mut fds = [PollFd::new(stdin.as_fd(), PollFlags::POLLIN), PollFd::new(stream.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::from(250u16)) {
+            Ok(_) | Err(nix::Error::EINTR) => {}
+            Err(e) => return Err(e.into()),
+        }
+        let ready = |fd: &PollFd| fd.revents().is_some_and(|events| !events.is_empty());
+        let (stdin_ready, stream_ready) = (ready(&fds[0]), ready(&fds[1]));
+
+        if stream_ready {
+            match stream.read(&mut buf) {
+                Ok(0) | Err(_) => return Ok(Detach::Ended),
+                Ok(n) => {
+                    stdout.write_all(&buf[..n])?;
+                    stdout.flush()?;
+                }
+            }
+        }
+
+        if stdin_ready {
+            let n = nix::unistd::read(stdin.as_raw_fd(), &mut buf)?;
+            if n == 0 {
+                return Ok(Detach::Detached);
+            }
+
+            let mut input = Vec::with_capacity(n + 1);
+            for &byte in &buf[..n] {
+                if pending_detach {
+                    pending_detach = false;
+                    if byte == DETACH_KEYS[1] {
+                        return Ok(Detach::Detached);
+                    }
+                    input.push(DETACH_KEYS[0]);
+                }
+                if byte == DETACH_KEYS[0] {
+                    pending_detach = true;
+                } else {
+                    input.push(byte);
+                }
+            }
+
+            // Watchers can also leave with Ctrl-C
+            if read_only && input.contains(&0x03) {
+                return Ok(Detach::Detached);
+            }
+            if !read_only && !input.is_empty() {
+                write_frame(&mut stream, FRAME_INPUT, &input)?;
+            }
+        }
+    }
+}
+
+/// Puts a terminal in raw mode, restoring it when dropped
+struct RawMode<'a> {
+    fd: BorrowedFd<'a>,
+    saved: Option<Termios>,
+}
+
+impl<'a> RawMode<'a> {
+    fn enable(fd: BorrowedFd<'a>) -> Result<Self> {
+        if !isatty(fd.as_raw_fd()).unwrap_or(false) {
+            return Ok(Self { fd, saved: None });
+        }
+
+        let saved = tcgetattr(fd)?;
+        let mut raw = saved.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(Self { fd, saved: Some(saved) })
+    }
+}
+
+impl Drop for RawMode<'_> {
+    fn drop(&mut self) {
+        if let Some(ref saved) = self.saved {
+            let _ = tcsetattr(self.fd, SetArg::TCSANOW, saved);
+        }
+    }
+}
+
+fn read_frame(stream: &mut UnixStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 3];
+    stream.read_exact(&mut header)?;
+    let mut payload = vec![0u8; u16::from_be_bytes([header[1], header[2]]) as usize];
+    stream.read_exact(&mut payload)?;
+    Ok((header[0], payload))
+}
+
+fn write_frame(stream: &mut UnixStream, kind: u8, payload: &[u8]) -> io::Result<()> {
+    for chunk in payload.chunks(u16::MAX as usize).chain(payload.is_empty().then_some(&[][..])) {
+        let mut frame = vec![kind];
+        frame.extend((chunk.len() as u16).to_be_bytes());
+        frame.extend(chunk);
+        stream.write_all(&frame)?;
+    }
+    Ok(())
+}
+
+/// Rows and columns of the terminal at `fd`
+pub fn terminal_size(fd: RawFd) -> Option<(u16, u16)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } < 0 || size.ws_row == 0 {
+        return None;
+    }
+    Some((size.ws_row, size.ws_col))
+}
+
+fn set_size(fd: RawFd, rows: u16, cols: u16) -> io::Result<()> {
+    let size = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+    if unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &size) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let (mut a, mut b) = UnixStream::pair().unwrap();
+        write_frame(&mut a, FRAME_ATTACH, &[]).unwrap();
+        write_frame(&mut a, FRAME_INPUT, b"ls\r").unwrap();
+
+        assert_eq!(read_frame(&mut b).unwrap(), (FRAME_ATTACH, vec![]));
+        assert_eq!(read_frame(&mut b).unwrap(), (FRAME_INPUT, b"ls\r".to_vec()));
+    }
+}
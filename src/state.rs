@@ -1,25 +1,333 @@
-use crate::config::Config;
+use crate::cgroup::ResourceLimits;
+use crate::config::{Config, NetworkMode};
 use crate::error::{Error, Result};
+use crate::overlay;
+use crate::sandbox::PortMapping;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use nix::fcntl::{Flock, FlockArg};
 use std::collections::HashMap;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+/// How a jail's root directory is backed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum JailMode {
+    /// A git worktree on a new branch
+    #[default]
+    Worktree,
+    /// An overlayfs with the repository checkout as the read-only lower layer
+    Overlay,
+}
+
+impl std::fmt::Display for JailMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JailMode::Worktree => write!(f, "worktree"),
+            JailMode::Overlay => write!(f, "overlay"),
+        }
+    }
+}
+
+/// A change to a jail's files in progress, during which it can't be used
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Busy {
+    /// `rename` is moving it to the given name
+    Renaming(String),
+    /// `destroy` is removing it
+    Destroying,
+}
+
+impl std::fmt::Display for Busy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Busy::Renaming(new) => write!(f, "being renamed to '{new}'"),
+            Busy::Destroying => write!(f, "being destroyed"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JailInfo {
     pub id: Uuid,
     pub name: String,
     pub repo_path: PathBuf,
+    /// The git worktree, or for overlay jails the directory holding the upper and work dirs
     pub worktree_path: PathBuf,
+    /// Worktree branch (empty for overlay jails)
+    #[serde(default)]
     pub branch_name: String,
+    /// How the jail's root is backed
+    #[serde(default)]
+    pub mode: JailMode,
+    /// Commit the jail started from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_commit: Option<String>,
+    /// Host branch the jail started from, if it started from a branch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base_branch: Option<String>,
     pub created_at: DateTime<Utc>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub pid: Option<u32>,
+    /// `enter`, `run` and detached sessions, including ones that may have died since
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sessions: Vec<Session>,
     /// Entrypoint command to run (first element is resolved path, rest are args)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub entrypoint: Option<Vec<String>>,
+    /// Landlock ABI version applied on the most recent enter/run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub landlock_abi: Option<u32>,
+    /// cgroup resource limits
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    pub limits: ResourceLimits,
+    /// Network mode (None follows the config)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkMode>,
+    /// Domains reachable in proxy mode, in addition to the config allowlist
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_allow: Vec<String>,
+    /// Destinations reachable in filtered mode, in addition to the config exceptions
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_exceptions: Vec<String>,
+    /// Jail ports published on the host
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortMapping>,
+    /// Hash of the repository's hooks and config when the jail was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo_fingerprint: Option<String>,
+    /// Saved states of the worktree, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub checkpoints: Vec<Checkpoint>,
+    /// Worktree states saved automatically during sessions, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub snapshots: Vec<Checkpoint>,
+    /// Set while `create` or `clone` sets the jail up on disk
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub creating: bool,
+    /// Set while another command changes the jail's files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub busy: Option<Busy>,
+}
+
+/// How a session was started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionKind {
+    Enter,
+    Run,
+    /// The supervisor of a session started with `--detach`
+    Detached,
+}
+
+impl std::fmt::Display for SessionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionKind::Enter => write!(f, "enter"),
+            SessionKind::Run => write!(f, "run"),
+            SessionKind::Detached => write!(f, "detached"),
+        }
+    }
+}
+
+/// An `enter`, `run` or detached session of a jail
+///
+/// Sessions are removed when they end; one whose process died without
+/// doing so is recognized as dead by its PID and the process start time,
+/// which tells it from a later process that reuses the PID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub kind: SessionKind,
+    pub command: String,
+    pub started_at: DateTime<Utc>,
+    /// PID of the robojail process running the sandbox
+    pub pid: u32,
+    /// Start time of that process, in clock ticks after boot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pid_start_time: Option<u64>,
+    /// Terminal the session was started from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<PathBuf>,
+    /// Socket `robojail attach` connects to, for detached sessions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+}
+
+impl Session {
+    /// A session run by the calling process
+    pub fn current(kind: SessionKind, command: &str, tty: Option<PathBuf>) -> Self {
+        let pid = std::process::id();
+        Self {
+            id: Uuid::new_v4(),
+            kind,
+            command: command.to_string(),
+            started_at: Utc::now(),
+            pid,
+            pid_start_time: process_start_time(pid),
+            tty,
+            socket: None,
+        }
+    }
+
+    /// Whether the session's process still runs
+    pub fn is_alive(&self) -> bool {
+        match self.pid_start_time {
+            Some(start_time) => process_start_time(self.pid) == Some(start_time),
+            None => State::is_pid_alive(self.pid),
+        }
+    }
+
+    /// Short form of the ID, for display
+    pub fn short_id(&self) -> String {
+        self.id.to_string()[..8].to_string()
+    }
+
+    /// One-line summary, for messages
+    pub fn describe(&self) -> String {
+        let tty = self.tty.as_ref().map(|tty| format!(" on {}", tty.display())).unwrap_or_default();
+        format!(
+            "{} ({} '{}', PID {}{tty}, since {})",
+            self.short_id(),
+            self.kind,
+            self.command,
+            self.pid,
+            self.started_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S")
+        )
+    }
+}
+
+/// Start time of a process in clock ticks after boot, from `/proc/<pid>/stat`
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    parse_start_time(&stat)
+}
+
+/// The 22nd field of a stat line; the command name in the 2nd may hold spaces
+fn parse_start_time(stat: &str) -> Option<u64> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// The series of saved worktree states a jail keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Series {
+    /// Saved on request, for rolling back to
+    Checkpoints,
+    /// Saved periodically during sessions
+    Snapshots,
+}
+
+impl Series {
+    /// Name used in refs and messages
+    pub fn name(self) -> &'static str {
+        match self {
+            Series::Checkpoints => "checkpoints",
+            Series::Snapshots => "snapshots",
+        }
+    }
+
+    /// Ref that keeps a saved state's commit alive in the repository
+    pub fn git_ref(self, jail: &str, id: u32) -> String {
+        format!("refs/robojail/{jail}/{}/{id}", self.name())
+    }
+}
+
+/// A saved state of a worktree jail
+///
+/// `commit` holds the whole worktree on top of the HEAD it had at the time,
+/// and is kept alive by a ref under `refs/robojail/<jail>/` in the
+/// repository (see `Series::git_ref`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub id: u32,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+    pub commit: String,
+    /// Whether ignored files were saved too
+    #[serde(default)]
+    pub include_ignored: bool,
+}
+
+impl JailInfo {
+    /// Directory holding the jail's changes in overlay mode
+    pub fn upper_dir(&self) -> PathBuf {
+        self.worktree_path.join(overlay::UPPER_DIR)
+    }
+
+    /// Overlayfs work directory in overlay mode
+    pub fn work_dir(&self) -> PathBuf {
+        self.worktree_path.join(overlay::WORK_DIR)
+    }
+
+    /// The saved states of one series
+    pub fn saved(&self, series: Series) -> &[Checkpoint] {
+        match series {
+            Series::Checkpoints => &self.checkpoints,
+            Series::Snapshots => &self.snapshots,
+        }
+    }
+
+    fn saved_mut(&mut self, series: Series) -> &mut Vec<Checkpoint> {
+        match series {
+            Series::Checkpoints => &mut self.checkpoints,
+            Series::Snapshots => &mut self.snapshots,
+        }
+    }
+
+    /// Sessions whose process still runs
+    pub fn live_sessions(&self) -> Vec<&Session> {
+        self.sessions.iter().filter(|session| session.is_alive()).collect()
+    }
+
+    /// Whether any session, detached or not, is running in the jail
+    pub fn is_running(&self) -> bool {
+        self.sessions.iter().any(Session::is_alive)
+    }
+
+    /// Fail if the jail is being created, or another command is changing its files
+    pub fn check_idle(&self) -> Result<()> {
+        if self.creating {
+            return Err(Error::Config(format!("jail '{}' is still being created", self.name)));
+        }
+        match self.busy {
+            Some(ref busy) => Err(Error::Config(format!("jail '{}' is {busy}", self.name))),
+            None => Ok(()),
+        }
+    }
+
+    /// The jail's detached session, if its supervisor is still alive
+    pub fn detached_session(&self) -> Option<&Session> {
+        self.sessions.iter().find(|session| session.kind == SessionKind::Detached && session.is_alive())
+    }
+
+    /// Effective network mode for this jail
+    pub fn network_mode(&self, config: &Config) -> NetworkMode {
+        self.network.unwrap_or_else(|| config.network_mode())
+    }
+
+    /// Effective proxy allowlist for this jail
+    pub fn network_allowlist(&self, config: &Config) -> Vec<String> {
+        merged(&config.network_allow, &self.network_allow)
+    }
+
+    /// Effective filtered-mode exceptions for this jail
+    pub fn network_exceptions(&self, config: &Config) -> Vec<String> {
+        merged(&config.network_exceptions, &self.network_exceptions)
+    }
+}
+
+/// Config entries followed by the jail's own, without duplicates
+fn merged(config: &[String], jail: &[String]) -> Vec<String> {
+    let mut all = config.to_vec();
+    for entry in jail {
+        if !all.contains(entry) {
+            all.push(entry.clone());
+        }
+    }
+    all
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -29,35 +337,71 @@ pub struct State {
 
 impl State {
     /// Load state from file, or create empty state
+    ///
+    /// For reading only: changes go through `update`, which holds the lock
+    /// from loading to saving. Saves replace the file in one rename, so
+    /// reading never needs the lock.
     pub fn load() -> Result<Self> {
-        let state_path = Self::state_path()?;
+        Self::load_from(&Self::state_path()?)
+    }
 
-        if state_path.exists() {
-            let content = fs::read_to_string(&state_path)?;
-            let state: State = serde_json::from_str(&content).map_err(|e| {
-                Error::StateCorrupted(format!("invalid JSON: {e}"))
-            })?;
-            Ok(state)
-        } else {
-            Ok(State::default())
+    fn load_from(state_path: &Path) -> Result<Self> {
+        match fs::read_to_string(state_path) {
+            Ok(content) => serde_json::from_str(&content)
+                .map_err(|e| Error::StateCorrupted(format!("invalid JSON: {e}"))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(e.into()),
         }
     }
 
-    /// Save state to file atomically
-    pub fn save(&self) -> Result<()> {
-        let state_path = Self::state_path()?;
+    /// Change the state in a transaction
+    ///
+    /// Takes an exclusive lock on the state's lockfile, applies `f` to the
+    /// state as it is on disk and saves the result, so concurrent robojail
+    /// processes can't lose each other's changes. Nothing is saved if `f`
+    /// fails. `f` must not call `update` itself.
+    pub fn update<T>(f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        Self::update_at(&Self::state_path()?, f)
+    }
+
+    /// Hold the state lock while changing other files robojail processes share
+    ///
+    /// Must not be held across a call to `update`.
+    pub fn lock() -> Result<Flock<File>> {
+        lock(&Self::state_path()?)
+    }
 
-        // Ensure parent directory exists
-        if let Some(parent) = state_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
+    fn update_at<T>(state_path: &Path, f: impl FnOnce(&mut State) -> Result<T>) -> Result<T> {
+        let _lock = lock(state_path)?;
+
+        let mut state = Self::load_from(state_path)?;
+        let result = f(&mut state)?;
+        state.save(state_path)?;
+        Ok(result)
+    }
+
+    /// Save state to file atomically and durably
+    ///
+    /// Writes a temp file of its own, flushes it to disk and renames it over
+    /// the state file, then flushes the directory so the rename sticks.
+    fn save(&self, state_path: &Path) -> Result<()> {
+        let dir = state_path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
 
-        // Write to temp file first, then rename (atomic)
-        let temp_path = state_path.with_extension("json.tmp");
-        let content = serde_json::to_string_pretty(self)?;
-        fs::write(&temp_path, content)?;
-        fs::rename(&temp_path, &state_path)?;
+        let temp_path = dir.join(format!(".jails.json.{}.{}.tmp", std::process::id(), Uuid::new_v4().simple()));
+        let written = (|| -> Result<()> {
+            let mut file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+            file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, state_path)?;
+            Ok(())
+        })();
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+        written?;
 
+        File::open(dir)?.sync_all()?;
         Ok(())
     }
 
@@ -68,19 +412,81 @@ impl State {
 
     /// Add a new jail
     pub fn add_jail(&mut self, info: JailInfo) -> Result<()> {
-        if self.jails.contains_key(&info.name) {
+        if self.name_taken(&info.name) {
             return Err(Error::JailExists(info.name.clone()));
         }
         self.jails.insert(info.name.clone(), info);
-        self.save()
+        Ok(())
+    }
+
+    /// Mark a jail reserved with `creating` set as ready
+    pub fn finish_jail(&mut self, name: &str) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.creating = false;
+        Ok(())
+    }
+
+    /// Drop the reservation of a jail whose creation failed
+    ///
+    /// Only the reservation with the given ID goes, in case the name was
+    /// destroyed and taken again meanwhile.
+    pub fn abandon_jail(&mut self, name: &str, id: Uuid) -> Result<()> {
+        if self.jails.get(name).is_some_and(|jail| jail.id == id && jail.creating) {
+            self.jails.remove(name);
+        }
+        Ok(())
     }
 
     /// Remove a jail by name
     pub fn remove_jail(&mut self, name: &str) -> Result<JailInfo> {
-        let info = self.jails.remove(name)
-            .ok_or_else(|| Error::JailNotFound(name.to_string()))?;
-        self.save()?;
-        Ok(info)
+        self.jails.remove(name)
+            .ok_or_else(|| Error::JailNotFound(name.to_string()))
+    }
+
+    /// Whether a jail has the name, or is being renamed to it
+    fn name_taken(&self, name: &str) -> bool {
+        self.jails.contains_key(name)
+            || self.jails.values().any(|jail| jail.busy == Some(Busy::Renaming(name.to_string())))
+    }
+
+    /// Reserve `new` for renaming `old`, which must be idle
+    ///
+    /// Sessions can't start until the rename is finished or abandoned.
+    pub fn begin_rename(&mut self, old: &str, new: &str) -> Result<JailInfo> {
+        if self.name_taken(new) {
+            return Err(Error::JailExists(new.to_string()));
+        }
+        let jail = self.get_jail_mut(old)?;
+        jail.check_idle()?;
+        if jail.is_running() {
+            return Err(Error::JailRunning(old.to_string()));
+        }
+
+        jail.busy = Some(Busy::Renaming(new.to_string()));
+        Ok(jail.clone())
+    }
+
+    /// Move a jail being renamed to its new name, along with its new paths
+    pub fn finish_rename(&mut self, old: &str, worktree_path: PathBuf, branch_name: String) -> Result<()> {
+        let mut jail = self.remove_jail(old)?;
+        let Some(Busy::Renaming(new)) = jail.busy.take() else {
+            return Err(Error::Config(format!("jail '{old}' is not being renamed")));
+        };
+
+        jail.name = new.clone();
+        jail.worktree_path = worktree_path;
+        jail.branch_name = branch_name;
+        self.jails.insert(new, jail);
+        Ok(())
+    }
+
+    /// Give up renaming a jail, leaving it under its old name
+    pub fn abandon_rename(&mut self, old: &str) -> Result<()> {
+        let jail = self.get_jail_mut(old)?;
+        if matches!(jail.busy, Some(Busy::Renaming(_))) {
+            jail.busy = None;
+        }
+        Ok(())
     }
 
     /// Get a jail by name
@@ -95,11 +501,56 @@ impl State {
             .ok_or_else(|| Error::JailNotFound(name.to_string()))
     }
 
-    /// Update jail PID
-    pub fn set_pid(&mut self, name: &str, pid: Option<u32>) -> Result<()> {
+    /// Record a session that started, forgetting dead ones
+    pub fn add_session(&mut self, name: &str, session: Session) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.check_idle()?;
+        jail.sessions.retain(Session::is_alive);
+        jail.sessions.push(session);
+        Ok(())
+    }
+
+    /// Forget a session that ended, and any dead ones
+    pub fn remove_session(&mut self, name: &str, id: Uuid) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.sessions.retain(|session| session.id != id && session.is_alive());
+        Ok(())
+    }
+
+    /// Replace the ports a jail publishes
+    pub fn set_ports(&mut self, name: &str, ports: Vec<PortMapping>) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.ports = ports;
+        Ok(())
+    }
+
+    /// Record a newly saved worktree state
+    pub fn add_saved(&mut self, name: &str, series: Series, checkpoint: Checkpoint) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.saved_mut(series).push(checkpoint);
+        Ok(())
+    }
+
+    /// Forget saved worktree states
+    pub fn remove_saved(&mut self, name: &str, series: Series, ids: &[u32]) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.saved_mut(series).retain(|checkpoint| !ids.contains(&checkpoint.id));
+        Ok(())
+    }
+
+    /// Move a jail's base, after its branch was rebased
+    pub fn set_base(&mut self, name: &str, commit: String, branch: Option<String>) -> Result<()> {
+        let jail = self.get_jail_mut(name)?;
+        jail.base_commit = Some(commit);
+        jail.base_branch = branch;
+        Ok(())
+    }
+
+    /// Record the Landlock ABI applied to a jail's latest session
+    pub fn set_landlock_abi(&mut self, name: &str, abi: Option<u32>) -> Result<()> {
         let jail = self.get_jail_mut(name)?;
-        jail.pid = pid;
-        self.save()
+        jail.landlock_abi = abi;
+        Ok(())
     }
 
     /// Check if a PID is still alive
@@ -111,11 +562,26 @@ impl State {
     /// Get all jails as a list
     pub fn list_jails(&self) -> Vec<&JailInfo> {
         let mut jails: Vec<_> = self.jails.values().collect();
-        jails.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        jails.sort_by_key(|j| j.created_at);
         jails
     }
 }
 
+/// Take the exclusive lock guarding changes to the state file
+///
+/// The lock is on a separate lockfile, as the state file itself is replaced
+/// on every save. It is released when the returned guard is dropped, or
+/// when the process dies.
+fn lock(state_path: &Path) -> Result<Flock<File>> {
+    if let Some(dir) = state_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let lock_path = state_path.with_extension("json.lock");
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path)?;
+    Flock::lock(file, FlockArg::LockExclusive)
+        .map_err(|(_, e)| Error::StateLock(format!("{}: {e}", lock_path.display())))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -128,9 +594,23 @@ mod tests {
             repo_path: PathBuf::from("/home/user/repo"),
             worktree_path: PathBuf::from("/home/user/.local/share/robojail/jails/test"),
             branch_name: "robojail/test-abc123".to_string(),
+            mode: JailMode::Worktree,
             created_at: Utc::now(),
-            pid: None,
+            sessions: vec![],
             entrypoint: None,
+            landlock_abi: None,
+            limits: ResourceLimits::default(),
+            network: None,
+            network_allow: vec![],
+            network_exceptions: vec![],
+            ports: vec![],
+            base_commit: None,
+            base_branch: None,
+            repo_fingerprint: None,
+            checkpoints: vec![],
+            snapshots: vec![],
+            creating: false,
+            busy: None,
         };
 
         let json = serde_json::to_string(&info).unwrap();
@@ -147,9 +627,23 @@ mod tests {
             repo_path: PathBuf::from("/repo"),
             worktree_path: PathBuf::from("/jail"),
             branch_name: "robojail/test".to_string(),
+            mode: JailMode::Worktree,
             created_at: Utc::now(),
-            pid: None,
+            sessions: vec![],
             entrypoint: None,
+            landlock_abi: None,
+            limits: ResourceLimits::default(),
+            network: None,
+            network_allow: vec![],
+            network_exceptions: vec![],
+            ports: vec![],
+            base_commit: None,
+            base_branch: None,
+            repo_fingerprint: None,
+            checkpoints: vec![],
+            snapshots: vec![],
+            creating: false,
+            busy: None,
         };
 
         // Can't actually save in tests without mocking, but we can test logic
@@ -157,4 +651,162 @@ mod tests {
         assert!(state.get_jail("test").is_ok());
         assert!(state.get_jail("nonexistent").is_err());
     }
+
+    fn test_jail(name: &str) -> JailInfo {
+        JailInfo {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            repo_path: PathBuf::from("/repo"),
+            worktree_path: PathBuf::from("/jail"),
+            branch_name: format!("robojail/{name}"),
+            mode: JailMode::Worktree,
+            created_at: Utc::now(),
+            sessions: vec![],
+            entrypoint: None,
+            landlock_abi: None,
+            limits: ResourceLimits::default(),
+            network: None,
+            network_allow: vec![],
+            network_exceptions: vec![],
+            ports: vec![],
+            base_commit: None,
+            base_branch: None,
+            repo_fingerprint: None,
+            checkpoints: vec![],
+            snapshots: vec![],
+            creating: false,
+            busy: None,
+        }
+    }
+
+    #[test]
+    fn test_concurrent_updates_keep_every_change() {
+        let dir = std::env::temp_dir().join(format!("robojail-state-{}", Uuid::new_v4().simple()));
+        let state_path = dir.join("jails.json");
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let state_path = state_path.clone();
+                std::thread::spawn(move || {
+                    for n in 0..10 {
+                        State::update_at(&state_path, |state| {
+                            state.add_jail(test_jail(&format!("jail-{writer}-{n}")))
+                        })
+                        .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // A failed update changes nothing
+        let failed = State::update_at(&state_path, |state| {
+            state.remove_jail("jail-0-0")?;
+            state.remove_jail("missing")
+        });
+        assert!(failed.is_err());
+
+        let state = State::load_from(&state_path).unwrap();
+        assert_eq!(state.jails.len(), 80);
+        let leftovers: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_abandon_only_own_reservation() {
+        let mut state = State::default();
+        let mut reserved = test_jail("new");
+        reserved.creating = true;
+        state.add_jail(reserved.clone()).unwrap();
+
+        // A second create of the same name fails and leaves the first alone
+        assert!(matches!(state.add_jail(test_jail("new")), Err(Error::JailExists(_))));
+        state.abandon_jail("new", Uuid::new_v4()).unwrap();
+        assert!(state.jails.contains_key("new"));
+
+        state.finish_jail("new").unwrap();
+        state.abandon_jail("new", reserved.id).unwrap();
+        assert!(!state.get_jail("new").unwrap().creating);
+
+        state.get_jail_mut("new").unwrap().creating = true;
+        state.abandon_jail("new", reserved.id).unwrap();
+        assert!(state.jails.is_empty());
+    }
+
+    #[test]
+    fn test_rename_reservation() {
+        let mut state = State::default();
+        state.add_jail(test_jail("old")).unwrap();
+        state.add_jail(test_jail("other")).unwrap();
+
+        state.begin_rename("old", "new").unwrap();
+        assert!(matches!(state.add_jail(test_jail("new")), Err(Error::JailExists(_))));
+        assert!(matches!(state.begin_rename("other", "new"), Err(Error::JailExists(_))));
+        assert!(state.add_session("old", Session::current(SessionKind::Run, "true", None)).is_err());
+
+        // Changes made while the files move are kept
+        state.set_ports("old", vec!["3000".parse().unwrap()]).unwrap();
+        state.finish_rename("old", PathBuf::from("/jails/new"), "robojail/new".to_string()).unwrap();
+        assert!(state.get_jail("old").is_err());
+        let jail = state.get_jail("new").unwrap();
+        assert_eq!((jail.name.as_str(), jail.ports.len(), jail.busy.clone()), ("new", 1, None));
+
+        state.begin_rename("new", "newer").unwrap();
+        state.abandon_rename("new").unwrap();
+        assert!(state.get_jail("new").unwrap().check_idle().is_ok());
+        state.add_jail(test_jail("newer")).unwrap();
+
+        state.add_session("new", Session::current(SessionKind::Run, "true", None)).unwrap();
+        assert!(matches!(state.begin_rename("new", "newest"), Err(Error::JailRunning(_))));
+    }
+
+    #[test]
+    fn test_destroying_jail_stays_unused() {
+        let mut state = State::default();
+        let mut jail = test_jail("doomed");
+        jail.busy = Some(Busy::Destroying);
+        state.add_jail(jail).unwrap();
+
+        assert!(state.add_session("doomed", Session::current(SessionKind::Run, "true", None)).is_err());
+        assert!(state.begin_rename("doomed", "saved").is_err());
+        assert!(state.get_jail("doomed").unwrap().check_idle().is_err());
+    }
+
+    #[test]
+    fn test_session_liveness() {
+        let mut session = Session::current(SessionKind::Run, "true", None);
+        assert!(session.pid_start_time.is_some());
+        assert!(session.is_alive());
+
+        // Same PID, but a different process
+        session.pid_start_time = session.pid_start_time.map(|time| time + 1);
+        assert!(!session.is_alive());
+    }
+
+    #[test]
+    fn test_detached_session_liveness() {
+        let mut jail = test_jail("detached");
+        jail.sessions.push(Session::current(SessionKind::Detached, "sh", None));
+        assert!(jail.detached_session().is_some());
+
+        // The supervisor's PID now belongs to another process
+        jail.sessions[0].pid_start_time = jail.sessions[0].pid_start_time.map(|time| time + 1);
+        assert!(jail.detached_session().is_none());
+        assert!(!jail.is_running());
+    }
+
+    #[test]
+    fn test_parse_start_time() {
+        let stat = "1234 (my (odd) cmd) S 1 1234 1234 0 -1 4194560 100 0 0 0 5 3 0 0 20 0 1 0 987654 10000 200";
+        assert_eq!(parse_start_time(stat), Some(987654));
+        assert_eq!(parse_start_time("garbage"), None);
+    }
 }
@@ -0,0 +1,131 @@
+//! Tamper detection for the host repository's hooks and config
+//!
+//! Hooks and config in a repository's common gitdir take effect on the host
+//! the next time anyone runs git there, so a jail able to write them could
+//! run code outside the sandbox. Jails never get them writable; as a second
+//! line of defense, a hash of them is recorded when a jail is created and
+//! checked again by `status` and `destroy`.
+
+use crate::error::{Error, Result};
+use crate::state::JailInfo;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Files and directories covered by the fingerprint, relative to the common gitdir
+const PROTECTED: [&str; 3] = ["config", "config.worktree", "hooks"];
+
+/// The repository's common gitdir (`.git` of the main checkout)
+pub fn common_dir(repo: &Path) -> Result<PathBuf> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["rev-parse", "--path-format=absolute", "--git-common-dir"])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::GitCommand(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Hash of the repository's hooks and config
+///
+/// Covers names, contents, symlink targets and executable bits, so adding,
+/// editing or enabling a hook all change it.
+pub fn fingerprint(repo: &Path) -> Result<String> {
+    let common = common_dir(repo)?;
+    let mut hasher = Sha256::new();
+    for name in PROTECTED {
+        hash_entry(&common, Path::new(name), &mut hasher)?;
+    }
+
+    let digest = hasher.finalize();
+    Ok(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+fn hash_entry(root: &Path, rel: &Path, hasher: &mut Sha256) -> Result<()> {
+    let path = root.join(rel);
+    let Ok(meta) = fs::symlink_metadata(&path) else {
+        return Ok(());
+    };
+
+    hasher.update(rel.as_os_str().as_encoded_bytes());
+    hasher.update([0]);
+
+    if meta.is_dir() {
+        hasher.update(b"d");
+        let mut names: Vec<_> = fs::read_dir(&path)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<std::io::Result<_>>()?;
+        names.sort();
+        for name in names {
+            hash_entry(root, &rel.join(name), hasher)?;
+        }
+    } else if meta.is_symlink() {
+        hasher.update(b"l");
+        hasher.update(fs::read_link(&path)?.as_os_str().as_encoded_bytes());
+    } else {
+        let executable = meta.permissions().mode() & 0o111 != 0;
+        hasher.update(if executable { b"x" } else { b"f" });
+        let contents = fs::read(&path)?;
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(contents);
+    }
+    hasher.update([0]);
+
+    Ok(())
+}
+
+/// Whether the hooks or config changed since the jail was created
+///
+/// Jails created before fingerprints were recorded are never reported.
+pub fn changed(jail: &JailInfo) -> bool {
+    let Some(ref recorded) = jail.repo_fingerprint else {
+        return false;
+    };
+    fingerprint(&jail.repo_path).map_or(true, |current| current != *recorded)
+}
+
+/// Print a warning if the hooks or config changed; returns whether they did
+pub fn warn_if_changed(jail: &JailInfo) -> bool {
+    if !changed(jail) {
+        return false;
+    }
+
+    let common = common_dir(&jail.repo_path).unwrap_or_else(|_| jail.repo_path.join(".git"));
+    eprintln!("WARNING: the git hooks or config of {} changed while jail '{}' existed.", jail.repo_path.display(), jail.name);
+    eprintln!("WARNING: Review {} and {} before running git in that repository.",
+        common.join("hooks").display(),
+        common.join("config").display()
+    );
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_tracks_hooks() {
+        let repo = std::env::temp_dir().join(format!("robojail-tamper-test-{}", std::process::id()));
+        fs::create_dir_all(&repo).unwrap();
+        let init = Command::new("git").arg("-C").arg(&repo).args(["init", "-q"]).status().unwrap();
+        assert!(init.success());
+
+        let hook = repo.join(".git/hooks/post-checkout");
+        let before = fingerprint(&repo).unwrap();
+        fs::write(&hook, "#!/bin/sh\n").unwrap();
+        let added = fingerprint(&repo).unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+        let enabled = fingerprint(&repo).unwrap();
+        let again = fingerprint(&repo).unwrap();
+        fs::remove_dir_all(&repo).unwrap();
+
+        assert_ne!(before, added);
+        assert_ne!(added, enabled);
+        assert_eq!(enabled, again);
+    }
+}